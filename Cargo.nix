@@ -1558,6 +1558,16 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" "std" ];
       };
+      "critical-section" = rec {
+        crateName = "critical-section";
+        version = "1.2.0";
+        edition = "2018";
+        sha256 = "02ylhcykxjc40xrfhk1lwc21jqgz4dbwv3jr49ymw733c51yl3kr";
+        libName = "critical_section";
+        features = {
+          "std" = [ "restore-state-bool" ];
+        };
+      };
       "crossbeam-channel" = rec {
         crateName = "crossbeam-channel";
         version = "0.5.16";
@@ -1882,6 +1892,21 @@ rec {
         ];
 
       };
+      "data-encoding" = rec {
+        crateName = "data-encoding";
+        version = "2.11.1";
+        edition = "2018";
+        sha256 = "01hzn6jwv19320gvk85vvvay5ljhx12srvicz292fvpl3mas90s5";
+        libName = "data_encoding";
+        authors = [
+          "Julien Cretin <git@ia0.eu>"
+        ];
+        features = {
+          "default" = [ "std" ];
+          "std" = [ "alloc" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "std" ];
+      };
       "defmt" = rec {
         crateName = "defmt";
         version = "1.1.1";
@@ -2633,6 +2658,36 @@ rec {
           }
         ];
 
+      };
+      "enum-as-inner" = rec {
+        crateName = "enum-as-inner";
+        version = "0.6.1";
+        edition = "2018";
+        sha256 = "1g3cywc65d9w974l2xy86ij13njss3qjc7b0kfbzbws9qrjs5rm1";
+        procMacro = true;
+        libName = "enum_as_inner";
+        authors = [
+          "Benjamin Fry <benjaminfry@me.com>"
+        ];
+        dependencies = [
+          {
+            name = "heck";
+            packageId = "heck";
+          }
+          {
+            name = "proc-macro2";
+            packageId = "proc-macro2";
+          }
+          {
+            name = "quote";
+            packageId = "quote";
+          }
+          {
+            name = "syn";
+            packageId = "syn 2.0.119";
+          }
+        ];
+
       };
       "enum-ordinalize" = rec {
         crateName = "enum-ordinalize";
@@ -3812,6 +3867,111 @@ rec {
         sha256 = "1sjmpsdl8czyh9ywl3qcsfsq9a307dg4ni2vnlwgnzzqhc4y0113";
 
       };
+      "hickory-proto" = rec {
+        crateName = "hickory-proto";
+        version = "0.25.2";
+        edition = "2021";
+        sha256 = "00k5dk572p0bsrnvsqv1yi8mwfpgwyj7q8bgiacri083q1bgx9pq";
+        libName = "hickory_proto";
+        authors = [
+          "The contributors to Hickory DNS"
+        ];
+        dependencies = [
+          {
+            name = "async-trait";
+            packageId = "async-trait";
+          }
+          {
+            name = "cfg-if";
+            packageId = "cfg-if";
+          }
+          {
+            name = "critical-section";
+            packageId = "critical-section";
+            optional = true;
+          }
+          {
+            name = "data-encoding";
+            packageId = "data-encoding";
+            usesDefaultFeatures = false;
+            features = [ "alloc" ];
+          }
+          {
+            name = "enum-as-inner";
+            packageId = "enum-as-inner";
+          }
+          {
+            name = "futures-channel";
+            packageId = "futures-channel";
+            usesDefaultFeatures = false;
+            features = [ "alloc" ];
+          }
+          {
+            name = "futures-io";
+            packageId = "futures-io";
+            optional = true;
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "futures-util";
+            packageId = "futures-util";
+            usesDefaultFeatures = false;
+            features = [ "alloc" ];
+          }
+          {
+            name = "idna";
+            packageId = "idna";
+            usesDefaultFeatures = false;
+            features = [ "alloc" "compiled_data" ];
+          }
+          {
+            name = "ipnet";
+            packageId = "ipnet";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "once_cell";
+            packageId = "once_cell";
+            usesDefaultFeatures = false;
+            features = [ "critical-section" ];
+          }
+          {
+            name = "rand";
+            packageId = "rand 0.9.5";
+            usesDefaultFeatures = false;
+            features = [ "alloc" "std_rng" ];
+          }
+          {
+            name = "thiserror";
+            packageId = "thiserror 2.0.19";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "tinyvec";
+            packageId = "tinyvec";
+            features = [ "alloc" ];
+          }
+          {
+            name = "tracing";
+            packageId = "tracing";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "url";
+            packageId = "url";
+            usesDefaultFeatures = false;
+          }
+        ];
+        features = {
+          "default" = [ "std" "tokio" ];
+          "no-std-rand" = [ "once_cell/critical-section" "dep:critical-section" ];
+          "serde" = [ "dep:serde" "std" "url/serde" ];
+          "std" = [ "data-encoding/std" "futures-channel/std" "futures-io/std" "futures-util/std" "ipnet/std" "rand/std" "rand/thread_rng" "ring?/std" "thiserror/std" "tracing/std" "url/std" ];
+          "text-parsing" = [ "std" ];
+          "tokio" = [ "dep:tokio" "std" "tokio/net" "tokio/rt" "tokio/time" "tokio/rt-multi-thread" ];
+        };
+        resolvedDefaultFeatures = [ "std" ];
+      };
       "hmac" = rec {
         crateName = "hmac";
         version = "0.12.1";
@@ -6694,6 +6854,19 @@ rec {
         authors = [
           "Aleksey Kladov <aleksey.kladov@gmail.com>"
         ];
+        dependencies = [
+          {
+            name = "critical-section";
+            packageId = "critical-section";
+            optional = true;
+          }
+          {
+            name = "portable-atomic";
+            packageId = "portable-atomic";
+            optional = true;
+            usesDefaultFeatures = false;
+          }
+        ];
         features = {
           "alloc" = [ "race" ];
           "atomic-polyfill" = [ "critical-section" ];
@@ -6703,7 +6876,7 @@ rec {
           "portable-atomic" = [ "dep:portable-atomic" ];
           "std" = [ "alloc" ];
         };
-        resolvedDefaultFeatures = [ "alloc" "default" "race" "std" ];
+        resolvedDefaultFeatures = [ "alloc" "critical-section" "default" "portable-atomic" "race" "std" ];
       };
       "once_cell_polyfill" = rec {
         crateName = "once_cell_polyfill";
@@ -10734,6 +10907,10 @@ rec {
             name = "anyhow";
             packageId = "anyhow";
           }
+          {
+            name = "axum";
+            packageId = "axum";
+          }
           {
             name = "clap";
             packageId = "clap";
@@ -10754,6 +10931,12 @@ rec {
             name = "h2";
             packageId = "h2";
           }
+          {
+            name = "hickory-proto";
+            packageId = "hickory-proto";
+            usesDefaultFeatures = false;
+            features = [ "std" ];
+          }
           {
             name = "libc";
             packageId = "libc";
@@ -10766,10 +10949,18 @@ rec {
             name = "prost";
             packageId = "prost";
           }
+          {
+            name = "rand";
+            packageId = "rand 0.9.5";
+          }
           {
             name = "serde";
             packageId = "serde";
           }
+          {
+            name = "serde_json";
+            packageId = "serde_json";
+          }
           {
             name = "serde_yaml";
             packageId = "serde_yaml";
@@ -12092,6 +12283,23 @@ rec {
         };
         resolvedDefaultFeatures = [ "zerovec" ];
       };
+      "tinyvec" = rec {
+        crateName = "tinyvec";
+        version = "1.13.3";
+        edition = "2018";
+        sha256 = "1vphg4gnlpykjy5h4v7r8nl38ij4zr9qyh7qd34ddvwjyqaa6g7x";
+        authors = [
+          "Lokathor <zefria@gmail.com>"
+        ];
+        features = {
+          "latest_stable_rust" = [ "rustc_1_61" ];
+          "rustc_1_61" = [ "rustc_1_57" ];
+          "schemars" = [ "dep:schemars" "alloc" ];
+          "serde" = [ "dep:serde_core" ];
+          "std" = [ "alloc" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "default" ];
+      };
       "tls_codec" = rec {
         crateName = "tls_codec";
         version = "0.4.2";
@@ -1,18 +1,78 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use csi_grpc as csi;
 use serde::{Deserialize, de::IntoDeserializer};
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
 use stackable_operator::{
+    client::Client,
     crd::listener,
-    k8s_openapi::api::core::v1::PersistentVolumeClaim,
-    kube::{core::DynamicObject, runtime::reflector::ObjectRef},
+    k8s_openapi::{
+        api::core::v1::{Node, ObjectReference, PersistentVolume, PersistentVolumeClaim, Pod},
+        apimachinery::pkg::apis::meta::v1::LabelSelector,
+    },
+    kube::{
+        Resource, ResourceExt,
+        api::ListParams,
+        core::DynamicObject,
+        runtime::{
+            events::{Recorder, Reporter},
+            reflector::ObjectRef,
+        },
+    },
 };
 use tonic::{Request, Response, Status};
 
-use super::{ListenerSelector, ListenerVolumeContext, tonic_unimplemented};
-use crate::utils::error::error_full_message;
+use super::{
+    ListenerSelector, ListenerVolumeContext, VolumeCapabilityError, report_error,
+    tonic_unimplemented, validate_volume_capability,
+};
+use crate::{
+    OPERATOR_KEY,
+    csi_server::node::{
+        NODE_TOPOLOGY_LABEL_HOSTNAME, NODE_TOPOLOGY_LABEL_REGION, NODE_TOPOLOGY_LABEL_ZONE,
+    },
+    listener_controller::{
+        ListenerClassNodeSelectorError, ListenerMountedPodLabelError, PV_LABEL_LISTENER_NAME,
+        PV_LABEL_LISTENER_NAMESPACE, listener_class_node_selector, listener_mounted_pod_label,
+        node_matches_selector,
+    },
+    utils::error::error_full_message,
+};
+
+const FULL_CONTROLLER_SERVICE_NAME: &str = "controller.listeners.stackable.tech";
+
+/// How many PersistentVolumes are requested at a time when listing volumes, if the CO doesn't limit the number of
+/// entries.
+const LIST_VOLUMES_PAGE_SIZE: u32 = 500;
+
+/// Volume context key that reports the Listener that a listed volume is bound to, as `<namespace>/<name>`.
+const VOLUME_CONTEXT_LISTENER: &str = "listeners.stackable.tech/listener";
+/// Volume context key that reports the ListenerClass of the Listener that a listed volume is bound to.
+const VOLUME_CONTEXT_LISTENER_CLASS_NAME: &str = "listeners.stackable.tech/listener-class-name";
+
+/// ListenerClass annotation that configures how precisely volumes are pinned if `pinnedNodePorts` is enabled, see
+/// [`PinningGranularity`].
+pub const LISTENER_CLASS_ANNOTATION_PINNING_GRANULARITY: &str =
+    "listeners.stackable.tech/pinning-granularity";
+
+/// What a pinned listener volume is pinned to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum PinningGranularity {
+    /// Pin the volume to the Node that it was first mounted on.
+    #[default]
+    Node,
+
+    /// Pin the volume to the zone of the Node that it was first mounted on, allowing it to move between the zone's
+    /// Nodes.
+    ///
+    /// This is only useful if the addresses stay stable within the zone, such as when using per-zone load balancers
+    /// or zone-local DNS.
+    Zone,
+}
 
 pub struct ListenerOperatorController {
-    pub client: stackable_operator::client::Client,
+    pub client: Client,
 }
 
 #[derive(Deserialize)]
@@ -23,9 +83,11 @@ struct ControllerVolumeParams {
     pvc_namespace: String,
 }
 
-#[derive(Snafu, Debug)]
+#[derive(Snafu, Debug, strum::IntoStaticStr)]
 #[snafu(module)]
 enum CreateVolumeError {
+    #[snafu(display("unsupported volume capability"))]
+    UnsupportedVolumeCapability { source: VolumeCapabilityError },
     #[snafu(display("failed to decode request parameters"))]
     DecodeRequestParams { source: serde::de::value::Error },
     #[snafu(display("failed to get {obj}"))]
@@ -39,6 +101,26 @@ enum CreateVolumeError {
     NoListenerClass {
         listener: ObjectRef<listener::v1alpha1::Listener>,
     },
+    #[snafu(display(
+        "invalid pinning granularity {granularity:?} in annotation {LISTENER_CLASS_ANNOTATION_PINNING_GRANULARITY:?} of {listener_class}"
+    ))]
+    InvalidPinningGranularity {
+        source: strum::ParseError,
+        granularity: String,
+        listener_class: ObjectRef<listener::v1alpha1::ListenerClass>,
+    },
+    #[snafu(display("failed to resolve the node selector of {listener_class}"))]
+    InvalidNodeSelector {
+        source: ListenerClassNodeSelectorError,
+        listener_class: ObjectRef<listener::v1alpha1::ListenerClass>,
+    },
+    #[snafu(display(
+        "selected Node (with topology {topology:?}) does not match the node selector of {listener_class}"
+    ))]
+    NodeNotSelected {
+        topology: BTreeMap<String, String>,
+        listener_class: ObjectRef<listener::v1alpha1::ListenerClass>,
+    },
 }
 
 impl From<CreateVolumeError> for Status {
@@ -46,39 +128,110 @@ impl From<CreateVolumeError> for Status {
         let full_msg = error_full_message(&err);
         // Convert to an appropriate tonic::Status representation and include full error message
         match err {
+            CreateVolumeError::UnsupportedVolumeCapability { .. } => {
+                Status::invalid_argument(full_msg)
+            }
             CreateVolumeError::DecodeRequestParams { .. } => Status::invalid_argument(full_msg),
             CreateVolumeError::DecodeVolumeContext { .. } => Status::invalid_argument(full_msg),
             CreateVolumeError::NoListenerClass { .. } => Status::invalid_argument(full_msg),
+            CreateVolumeError::InvalidPinningGranularity { .. } => {
+                Status::failed_precondition(full_msg)
+            }
+            CreateVolumeError::InvalidNodeSelector { .. } => Status::failed_precondition(full_msg),
+            // Tells the provisioner to let the Pod be rescheduled onto another Node
+            CreateVolumeError::NodeNotSelected { .. } => Status::resource_exhausted(full_msg),
             CreateVolumeError::GetObject { .. } => Status::unavailable(full_msg),
         }
     }
 }
 
-#[tonic::async_trait]
-impl csi::v1::controller_server::Controller for ListenerOperatorController {
-    async fn controller_get_capabilities(
-        &self,
-        _request: Request<csi::v1::ControllerGetCapabilitiesRequest>,
-    ) -> Result<Response<csi::v1::ControllerGetCapabilitiesResponse>, Status> {
-        Ok(Response::new(csi::v1::ControllerGetCapabilitiesResponse {
-            capabilities: vec![csi::v1::ControllerServiceCapability {
-                r#type: Some(csi::v1::controller_service_capability::Type::Rpc(
-                    csi::v1::controller_service_capability::Rpc {
-                        r#type:
-                            csi::v1::controller_service_capability::rpc::Type::CreateDeleteVolume
-                                .into(),
-                    },
-                )),
-            }],
-        }))
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum ListVolumesError {
+    #[snafu(display("failed to list PersistentVolumes"))]
+    ListPersistentVolumes {
+        source: stackable_operator::kube::Error,
+    },
+
+    #[snafu(display("failed to list the Pods that have mounted {listener}"))]
+    ListPods {
+        source: stackable_operator::client::Error,
+        listener: ObjectRef<listener::v1alpha1::Listener>,
+    },
+
+    #[snafu(display("failed to list Listeners in namespace {namespace:?}"))]
+    ListNamespaceListeners {
+        source: stackable_operator::client::Error,
+        namespace: String,
+    },
+
+    #[snafu(display("failed to list Pods in namespace {namespace:?}"))]
+    ListNamespacePods {
+        source: stackable_operator::client::Error,
+        namespace: String,
+    },
+
+    #[snafu(display("failed to generate {listener}'s pod selector"))]
+    ListenerPodSelector {
+        source: ListenerMountedPodLabelError,
+        listener: ObjectRef<listener::v1alpha1::Listener>,
+    },
+
+    #[snafu(display("failed to get {obj}"))]
+    GetObject {
+        source: stackable_operator::client::Error,
+        obj: Box<ObjectRef<DynamicObject>>,
+    },
+
+    #[snafu(display("invalid starting token {token:?}"))]
+    InvalidStartingToken { token: String },
+
+    #[snafu(display("volume {volume_id:?} does not exist"))]
+    VolumeNotFound { volume_id: String },
+
+    #[snafu(display("no volume capabilities were specified"))]
+    NoVolumeCapabilities,
+}
+
+impl From<ListVolumesError> for Status {
+    fn from(err: ListVolumesError) -> Self {
+        let full_msg = error_full_message(&err);
+        // Convert to an appropriate tonic::Status representation and include full error message
+        match err {
+            ListVolumesError::ListPersistentVolumes { .. } => Status::unavailable(full_msg),
+            ListVolumesError::ListPods { .. } => Status::unavailable(full_msg),
+            ListVolumesError::ListNamespaceListeners { .. } => Status::unavailable(full_msg),
+            ListVolumesError::ListNamespacePods { .. } => Status::unavailable(full_msg),
+            ListVolumesError::ListenerPodSelector { .. } => Status::failed_precondition(full_msg),
+            ListVolumesError::GetObject { .. } => Status::unavailable(full_msg),
+            ListVolumesError::InvalidStartingToken { .. } => Status::aborted(full_msg),
+            ListVolumesError::VolumeNotFound { .. } => Status::not_found(full_msg),
+            ListVolumesError::NoVolumeCapabilities => Status::invalid_argument(full_msg),
+        }
     }
+}
 
-    async fn create_volume(
+/// A volume that was provisioned by this driver, as reported by [`describe_volume`].
+struct VolumeDescription {
+    volume: csi::v1::Volume,
+    published_node_ids: Vec<String>,
+    condition: csi::v1::VolumeCondition,
+}
+
+impl ListenerOperatorController {
+    /// Implements [`csi::v1::controller_server::Controller::create_volume`].
+    ///
+    /// `involved_objects` collects the objects that any errors should be reported on.
+    async fn create_listener_volume(
         &self,
         request: Request<csi::v1::CreateVolumeRequest>,
-    ) -> Result<Response<csi::v1::CreateVolumeResponse>, Status> {
+        involved_objects: &mut Vec<ObjectReference>,
+    ) -> Result<csi::v1::CreateVolumeResponse, CreateVolumeError> {
         use create_volume_error::*;
         let request = request.into_inner();
+        for capability in &request.volume_capabilities {
+            validate_volume_capability(capability).context(UnsupportedVolumeCapabilitySnafu)?;
+        }
         let ControllerVolumeParams {
             pvc_name,
             pvc_namespace: ns,
@@ -93,6 +246,7 @@ impl csi::v1::controller_server::Controller for ListenerOperatorController {
                     .within(&ns)
                     .erase(),
             })?;
+        involved_objects.push(pvc.object_ref(&()));
         let raw_volume_context = pvc.metadata.annotations.unwrap_or_default();
         let ListenerVolumeContext { listener_selector } =
             ListenerVolumeContext::deserialize(raw_volume_context.clone().into_deserializer())
@@ -128,26 +282,66 @@ impl csi::v1::controller_server::Controller for ListenerOperatorController {
                     .erase(),
             })?;
 
+        let node_selector = match listener_class.spec.service_type {
+            listener::v1alpha1::ServiceType::NodePort => {
+                listener_class_node_selector(&listener_class).with_context(|_| {
+                    InvalidNodeSelectorSnafu {
+                        listener_class: ObjectRef::from_obj(&listener_class),
+                    }
+                })?
+            }
+            _ => None,
+        };
+        // The top node (as selected by the CSI client)
+        let preferred_topology = request
+            .accessibility_requirements
+            .unwrap_or_default()
+            .preferred
+            .into_iter()
+            .next();
+        if let (Some(selector), Some(preferred_topology)) = (&node_selector, &preferred_topology)
+            && !self
+                .node_matches_selector(preferred_topology, selector)
+                .await?
+        {
+            return NodeNotSelectedSnafu {
+                topology: preferred_topology
+                    .segments
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<BTreeMap<_, _>>(),
+                listener_class: ObjectRef::from_obj(&listener_class),
+            }
+            .fail();
+        }
+
         // We only configure a node stickiness in case it is enabled and the Service is of type
         // NodePort. Load balancers and services of type ClusterIP have no relationship to any
         // particular node, so don't try to pin.
-        let accessible_topology = if listener_class.spec.pinned_node_ports
+        let mut accessible_topology = if listener_class.spec.pinned_node_ports
             && listener_class.spec.service_type == listener::v1alpha1::ServiceType::NodePort
         {
-            // Pick the top node (as selected by the CSI client) and "stick" to that
-            // Since we want clients to have a stable address to connect to
-            request
-                .accessibility_requirements
-                .unwrap_or_default()
-                .preferred
+            let granularity = pinning_granularity(&listener_class)?;
+            // "Stick" to the top node, since we want clients to have a stable address to connect to
+            preferred_topology
                 .into_iter()
-                .take(1)
+                .map(|topology| pinned_topology(topology, granularity))
                 .collect()
         } else {
             Vec::new()
         };
+        // Unpinned volumes are still restricted to the selected Nodes, so that they are only ever mounted on Nodes
+        // that clients can reach
+        if let Some(selector) = node_selector {
+            if accessible_topology.is_empty() {
+                accessible_topology.push(csi::v1::Topology::default());
+            }
+            for topology in &mut accessible_topology {
+                topology.segments.extend(selector.clone());
+            }
+        }
 
-        Ok(Response::new(csi::v1::CreateVolumeResponse {
+        Ok(csi::v1::CreateVolumeResponse {
             volume: Some(csi::v1::Volume {
                 capacity_bytes: 0,
                 volume_id: request.name,
@@ -155,9 +349,463 @@ impl csi::v1::controller_server::Controller for ListenerOperatorController {
                 content_source: None,
                 accessible_topology,
             }),
+        })
+    }
+}
+
+impl ListenerOperatorController {
+    /// Whether the Node described by `topology` matches all requirements of a ListenerClass' node `selector`.
+    ///
+    /// The Node's labels are read directly, since the topology only includes the labels that the node driver is
+    /// configured to report. Nodes that no longer exist are never selected.
+    async fn node_matches_selector(
+        &self,
+        topology: &csi::v1::Topology,
+        selector: &BTreeMap<String, String>,
+    ) -> Result<bool, CreateVolumeError> {
+        use create_volume_error::*;
+        let Some(node_name) = topology.segments.get(NODE_TOPOLOGY_LABEL_HOSTNAME) else {
+            return Ok(false);
+        };
+        let node = self
+            .client
+            .get_opt::<Node>(node_name, &())
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<Node>::new(node_name).erase(),
+            })?;
+        Ok(node.is_some_and(|node| node_matches_selector(node.labels(), selector)))
+    }
+
+    /// Lists up to `max_entries` (or all, if [`None`]) of the [`PersistentVolume`]s that were provisioned by this
+    /// driver, starting at `starting_token`.
+    ///
+    /// The tokens are Kubernetes' continue tokens, so that the volumes don't need to be listed in full for each page.
+    /// Returns the token for the next page, if there are more volumes.
+    async fn listener_volumes(
+        &self,
+        starting_token: Option<&str>,
+        max_entries: Option<u32>,
+    ) -> Result<(Vec<PersistentVolume>, Option<String>), ListVolumesError> {
+        use list_volumes_error::*;
+        let api = self.client.get_all_api::<PersistentVolume>();
+        let mut volumes = Vec::new();
+        let mut continue_token = starting_token.map(str::to_string);
+        loop {
+            // Filtering can only ever remove volumes, so requesting no more than the remaining number of entries
+            // guarantees that the page never has to be split
+            let limit = match max_entries {
+                Some(max_entries) => max_entries.saturating_sub(volumes.len() as u32),
+                None => LIST_VOLUMES_PAGE_SIZE,
+            };
+            let mut list_params = ListParams::default().limit(limit);
+            if let Some(continue_token) = &continue_token {
+                list_params = list_params.continue_token(continue_token);
+            }
+            let page = match (api.list(&list_params).await, starting_token) {
+                (Ok(page), _) => page,
+                // The CO's token was not issued by us, or has expired
+                (Err(stackable_operator::kube::Error::Api(err)), Some(token))
+                    if matches!(err.code, 400 | 410)
+                        && continue_token.as_deref() == Some(token) =>
+                {
+                    return InvalidStartingTokenSnafu { token }.fail();
+                }
+                (Err(err), _) => return Err(err).context(ListPersistentVolumesSnafu),
+            };
+            volumes.extend(page.items.into_iter().filter(is_listener_volume));
+            continue_token = page.metadata.continue_.filter(|token| !token.is_empty());
+            let page_full =
+                max_entries.is_some_and(|max_entries| volumes.len() >= max_entries as usize);
+            if continue_token.is_none() || page_full {
+                return Ok((volumes, continue_token));
+            }
+        }
+    }
+
+    /// Gets the [`PersistentVolume`] of the volume `volume_id`, if it exists and was provisioned by this driver.
+    async fn listener_volume(
+        &self,
+        volume_id: &str,
+    ) -> Result<Option<PersistentVolume>, ListVolumesError> {
+        use list_volumes_error::*;
+        // The external-provisioner names PersistentVolumes after their volume ID
+        let pv = self
+            .client
+            .get_opt::<PersistentVolume>(volume_id, &())
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<PersistentVolume>::new(volume_id).erase(),
+            })?;
+        Ok(pv.filter(|pv| is_listener_volume(pv) && volume_handle(pv) == Some(volume_id)))
+    }
+
+    /// Lists the Pods that have mounted `listener`, which may be using its volumes.
+    ///
+    /// Only Pods with the Listener's mounted-pod label are listed, rather than all Pods in the namespace.
+    async fn pods_with_listener(
+        &self,
+        listener: &listener::v1alpha1::Listener,
+    ) -> Result<Vec<Pod>, ListVolumesError> {
+        use list_volumes_error::*;
+        let listener_ref = ObjectRef::from_obj(listener);
+        let (label_key, label_value) =
+            listener_mounted_pod_label(listener).context(ListenerPodSelectorSnafu {
+                listener: listener_ref.clone(),
+            })?;
+        self.client
+            .list_with_label_selector::<Pod>(
+                listener.metadata.namespace.as_deref().unwrap_or_default(),
+                &LabelSelector {
+                    match_labels: Some([(label_key, label_value)].into()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context(ListPodsSnafu {
+                listener: listener_ref,
+            })
+    }
+
+    /// Gets the Listener of `pv` (if any, see [`volume_listener_ref`]), along with the Pods that may be using it.
+    ///
+    /// Only used for single volumes, [`csi::v1::controller_server::Controller::list_volumes`] looks up all Listeners
+    /// and Pods of a page at once.
+    async fn volume_listener_and_pods(
+        &self,
+        pv: &PersistentVolume,
+    ) -> Result<(Option<listener::v1alpha1::Listener>, Vec<Pod>), ListVolumesError> {
+        use list_volumes_error::*;
+        let Some(listener_ref) = volume_listener_ref(pv) else {
+            return Ok((None, Vec::new()));
+        };
+        let listener = self
+            .client
+            .get_opt::<listener::v1alpha1::Listener>(
+                &listener_ref.name,
+                listener_ref.namespace.as_deref().unwrap_or_default(),
+            )
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: listener_ref.clone().erase(),
+            })?;
+        let pods = match &listener {
+            Some(listener) => self.pods_with_listener(listener).await?,
+            None => Vec::new(),
+        };
+        Ok((listener, pods))
+    }
+}
+
+/// The Listeners in each namespace, so that each namespace is only listed once when describing many volumes.
+#[derive(Default)]
+struct NamespaceListeners(BTreeMap<String, Vec<listener::v1alpha1::Listener>>);
+
+impl NamespaceListeners {
+    /// Finds the Listener `listener_ref`, listing the Listeners in its namespace if they have not been listed yet.
+    async fn get(
+        &mut self,
+        client: &Client,
+        listener_ref: &ObjectRef<listener::v1alpha1::Listener>,
+    ) -> Result<Option<&listener::v1alpha1::Listener>, ListVolumesError> {
+        use list_volumes_error::*;
+        let namespace = listener_ref.namespace.as_deref().unwrap_or_default();
+        if !self.0.contains_key(namespace) {
+            let listeners = client
+                .list_with_label_selector::<listener::v1alpha1::Listener>(
+                    namespace,
+                    &LabelSelector::default(),
+                )
+                .await
+                .context(ListNamespaceListenersSnafu { namespace })?;
+            self.0.insert(namespace.to_string(), listeners);
+        }
+        Ok(self
+            .0
+            .get(namespace)
+            .into_iter()
+            .flatten()
+            .find(|listener| listener.metadata.name.as_deref() == Some(listener_ref.name.as_str())))
+    }
+}
+
+/// The Pods in each namespace, so that each namespace is only listed once when looking up many claims.
+#[derive(Default)]
+pub struct NamespacePods(BTreeMap<String, Vec<Pod>>);
+
+impl NamespacePods {
+    /// Lists the Pods in `namespace`, if they have not been listed yet.
+    pub async fn get(
+        &mut self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<&[Pod], stackable_operator::client::Error> {
+        if !self.0.contains_key(namespace) {
+            let pods = client
+                .list_with_label_selector::<Pod>(namespace, &LabelSelector::default())
+                .await?;
+            self.0.insert(namespace.to_string(), pods);
+        }
+        Ok(self.0.get(namespace).map_or(&[], Vec::as_slice))
+    }
+}
+
+/// The Listener that `pv` is bound to.
+///
+/// The Listener is only recorded once the volume has been published.
+fn volume_listener_ref(pv: &PersistentVolume) -> Option<ObjectRef<listener::v1alpha1::Listener>> {
+    let labels = pv.labels();
+    let (listener_ns, listener_name) = labels
+        .get(PV_LABEL_LISTENER_NAMESPACE)
+        .zip(labels.get(PV_LABEL_LISTENER_NAME))?;
+    Some(ObjectRef::new(listener_name).within(listener_ns))
+}
+
+/// Describes the volume that is backed by `pv`.
+///
+/// `listener` is the Listener of the volume (as identified by [`volume_listener_ref`]), if it exists, and `pods` must
+/// include all Pods that use the volume (but may contain unrelated Pods in the same namespace).
+fn describe_volume(
+    pv: &PersistentVolume,
+    listener: Option<&listener::v1alpha1::Listener>,
+    pods: &[Pod],
+) -> VolumeDescription {
+    let spec = pv.spec.as_ref();
+    let mut volume_context = spec
+        .and_then(|spec| spec.csi.as_ref())
+        .and_then(|csi| csi.volume_attributes.clone())
+        .unwrap_or_default();
+    let claim_name = spec
+        .and_then(|spec| spec.claim_ref.as_ref())
+        .and_then(|claim| claim.name.as_deref());
+    let mut published_node_ids = Vec::new();
+
+    let condition = match volume_listener_ref(pv) {
+        None => csi::v1::VolumeCondition {
+            abnormal: false,
+            message: "volume has not been published yet".to_string(),
+        },
+        Some(listener_ref) => {
+            volume_context.insert(
+                VOLUME_CONTEXT_LISTENER.to_string(),
+                format!(
+                    "{}/{}",
+                    listener_ref.namespace.as_deref().unwrap_or_default(),
+                    listener_ref.name
+                ),
+            );
+            match listener {
+                Some(listener) => {
+                    // Pods are only labelled with the Listener once the volume has been published to them
+                    if let Some(claim_name) = claim_name {
+                        published_node_ids = claim_node_names(pods, claim_name);
+                    }
+                    if let Some(class_name) = &listener.spec.class_name {
+                        volume_context.insert(
+                            VOLUME_CONTEXT_LISTENER_CLASS_NAME.to_string(),
+                            class_name.clone(),
+                        );
+                    }
+                    csi::v1::VolumeCondition {
+                        abnormal: false,
+                        message: format!("volume is bound to {listener_ref}"),
+                    }
+                }
+                None => csi::v1::VolumeCondition {
+                    abnormal: true,
+                    message: format!("{listener_ref} has been deleted"),
+                },
+            }
+        }
+    };
+
+    VolumeDescription {
+        volume: csi::v1::Volume {
+            capacity_bytes: 0,
+            volume_id: volume_handle(pv).unwrap_or_default().to_string(),
+            volume_context: volume_context.into_iter().collect(),
+            content_source: None,
+            accessible_topology: pv_accessible_topology(pv),
+        },
+        published_node_ids,
+        condition,
+    }
+}
+
+/// Whether `pv` was provisioned by this driver.
+pub fn is_listener_volume(pv: &PersistentVolume) -> bool {
+    pv.spec
+        .as_ref()
+        .and_then(|spec| spec.csi.as_ref())
+        .is_some_and(|csi| csi.driver == OPERATOR_KEY)
+}
+
+/// The CSI volume ID of `pv`.
+fn volume_handle(pv: &PersistentVolume) -> Option<&str> {
+    Some(pv.spec.as_ref()?.csi.as_ref()?.volume_handle.as_str())
+}
+
+/// Lists the Nodes that are running Pods (out of `pods`) that use the PersistentVolumeClaim `claim_name`.
+fn claim_node_names(pods: &[Pod], claim_name: &str) -> Vec<String> {
+    pods.iter()
+        .filter(|pod| pod_uses_claim(pod, claim_name))
+        .filter_map(|pod| pod.spec.as_ref()?.node_name.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Whether `pod` uses the PersistentVolumeClaim `claim_name` (which must be in the same namespace).
+pub fn pod_uses_claim(pod: &Pod, claim_name: &str) -> bool {
+    // Terminated Pods don't use their volumes anymore
+    if matches!(
+        pod.status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref()),
+        Some("Succeeded" | "Failed")
+    ) {
+        return false;
+    }
+    let pod_name = pod.metadata.name.as_deref().unwrap_or_default();
+    pod.spec
+        .iter()
+        .flat_map(|spec| spec.volumes.iter().flatten())
+        .any(|volume| {
+            volume
+                .persistent_volume_claim
+                .as_ref()
+                .is_some_and(|pvc| pvc.claim_name == claim_name)
+                // Ephemeral volumes use a generated PVC, see
+                // https://kubernetes.io/docs/concepts/storage/ephemeral-volumes/#persistentvolumeclaim-naming
+                || (volume.ephemeral.is_some()
+                    && format!("{pod_name}-{}", volume.name) == claim_name)
+        })
+}
+
+/// The [`PinningGranularity`] configured for `listener_class`.
+fn pinning_granularity(
+    listener_class: &listener::v1alpha1::ListenerClass,
+) -> Result<PinningGranularity, CreateVolumeError> {
+    use create_volume_error::*;
+    match listener_class
+        .annotations()
+        .get(LISTENER_CLASS_ANNOTATION_PINNING_GRANULARITY)
+    {
+        Some(granularity) => granularity
+            .parse()
+            .with_context(|_| InvalidPinningGranularitySnafu {
+                granularity,
+                listener_class: ObjectRef::from_obj(listener_class),
+            }),
+        None => Ok(PinningGranularity::default()),
+    }
+}
+
+/// Restricts the `preferred` topology selected by the CSI client to the segments that a volume is pinned to.
+///
+/// Falls back to pinning to the Node if zone pinning is requested for a Node that has no zone.
+fn pinned_topology(
+    preferred: csi::v1::Topology,
+    granularity: PinningGranularity,
+) -> csi::v1::Topology {
+    match granularity {
+        PinningGranularity::Node => preferred,
+        PinningGranularity::Zone if !preferred.segments.contains_key(NODE_TOPOLOGY_LABEL_ZONE) => {
+            tracing::warn!(
+                topology = ?preferred.segments,
+                "selected Node has no zone, pinning volume to the Node instead",
+            );
+            preferred
+        }
+        PinningGranularity::Zone => csi::v1::Topology {
+            segments: preferred
+                .segments
+                .into_iter()
+                .filter(|(key, _)| {
+                    key == NODE_TOPOLOGY_LABEL_ZONE || key == NODE_TOPOLOGY_LABEL_REGION
+                })
+                .collect(),
+        },
+    }
+}
+
+/// The CSI topology that `pv` is restricted to, as recorded in its node affinity.
+fn pv_accessible_topology(pv: &PersistentVolume) -> Vec<csi::v1::Topology> {
+    pv.spec
+        .as_ref()
+        .and_then(|spec| spec.node_affinity.as_ref())
+        .and_then(|affinity| affinity.required.as_ref())
+        .into_iter()
+        .flat_map(|required| &required.node_selector_terms)
+        .map(|term| csi::v1::Topology {
+            segments: term
+                .match_expressions
+                .iter()
+                .flatten()
+                .filter_map(|expr| match expr.values.as_deref() {
+                    Some([value]) if expr.operator == "In" => {
+                        Some((expr.key.clone(), value.clone()))
+                    }
+                    _ => None,
+                })
+                .collect(),
+        })
+        .filter(|topology| !topology.segments.is_empty())
+        .collect()
+}
+
+#[tonic::async_trait]
+impl csi::v1::controller_server::Controller for ListenerOperatorController {
+    async fn controller_get_capabilities(
+        &self,
+        _request: Request<csi::v1::ControllerGetCapabilitiesRequest>,
+    ) -> Result<Response<csi::v1::ControllerGetCapabilitiesResponse>, Status> {
+        let rpc_capability = |rpc: csi::v1::controller_service_capability::rpc::Type| {
+            csi::v1::ControllerServiceCapability {
+                r#type: Some(csi::v1::controller_service_capability::Type::Rpc(
+                    csi::v1::controller_service_capability::Rpc { r#type: rpc.into() },
+                )),
+            }
+        };
+        Ok(Response::new(csi::v1::ControllerGetCapabilitiesResponse {
+            capabilities: vec![
+                rpc_capability(
+                    csi::v1::controller_service_capability::rpc::Type::CreateDeleteVolume,
+                ),
+                rpc_capability(csi::v1::controller_service_capability::rpc::Type::ListVolumes),
+                rpc_capability(
+                    csi::v1::controller_service_capability::rpc::Type::ListVolumesPublishedNodes,
+                ),
+                rpc_capability(csi::v1::controller_service_capability::rpc::Type::GetVolume),
+                rpc_capability(csi::v1::controller_service_capability::rpc::Type::VolumeCondition),
+            ],
         }))
     }
 
+    async fn create_volume(
+        &self,
+        request: Request<csi::v1::CreateVolumeRequest>,
+    ) -> Result<Response<csi::v1::CreateVolumeResponse>, Status> {
+        let mut involved_objects = Vec::new();
+        match self
+            .create_listener_volume(request, &mut involved_objects)
+            .await
+        {
+            Ok(response) => Ok(Response::new(response)),
+            Err(err) => {
+                let event_recorder = Recorder::new(
+                    self.client.as_kube_client(),
+                    Reporter {
+                        controller: FULL_CONTROLLER_SERVICE_NAME.to_string(),
+                        instance: None,
+                    },
+                );
+                report_error(&event_recorder, "CreateVolume", &err, &involved_objects).await;
+                Err(err.into())
+            }
+        }
+    }
+
     async fn delete_volume(
         &self,
         _request: Request<csi::v1::DeleteVolumeRequest>,
@@ -181,16 +829,90 @@ impl csi::v1::controller_server::Controller for ListenerOperatorController {
 
     async fn validate_volume_capabilities(
         &self,
-        _request: Request<csi::v1::ValidateVolumeCapabilitiesRequest>,
+        request: Request<csi::v1::ValidateVolumeCapabilitiesRequest>,
     ) -> Result<Response<csi::v1::ValidateVolumeCapabilitiesResponse>, Status> {
-        tonic_unimplemented()
+        use list_volumes_error::*;
+        let request = request.into_inner();
+        ensure!(
+            !request.volume_capabilities.is_empty(),
+            NoVolumeCapabilitiesSnafu
+        );
+        self.listener_volume(&request.volume_id)
+            .await?
+            .context(VolumeNotFoundSnafu {
+                volume_id: &request.volume_id,
+            })?;
+
+        if let Err(err) = request
+            .volume_capabilities
+            .iter()
+            .try_for_each(|capability| validate_volume_capability(capability).map(|_| ()))
+        {
+            return Ok(Response::new(csi::v1::ValidateVolumeCapabilitiesResponse {
+                confirmed: None,
+                message: error_full_message(&err),
+            }));
+        }
+        Ok(Response::new(csi::v1::ValidateVolumeCapabilitiesResponse {
+            confirmed: Some(csi::v1::validate_volume_capabilities_response::Confirmed {
+                volume_context: request.volume_context,
+                volume_capabilities: request.volume_capabilities,
+                parameters: request.parameters,
+                ..Default::default()
+            }),
+            message: String::new(),
+        }))
     }
 
     async fn list_volumes(
         &self,
-        _request: Request<csi::v1::ListVolumesRequest>,
+        request: Request<csi::v1::ListVolumesRequest>,
     ) -> Result<Response<csi::v1::ListVolumesResponse>, Status> {
-        tonic_unimplemented()
+        let request = request.into_inner();
+        let (volumes, next_token) = self
+            .listener_volumes(
+                Some(request.starting_token.as_str()).filter(|token| !token.is_empty()),
+                u32::try_from(request.max_entries)
+                    .ok()
+                    .filter(|max_entries| *max_entries > 0),
+            )
+            .await?;
+
+        // Each namespace's Listeners and Pods are only listed once per page, rather than once per volume
+        let mut listeners = NamespaceListeners::default();
+        let mut namespace_pods = NamespacePods::default();
+        let mut entries = Vec::with_capacity(volumes.len());
+        for pv in &volumes {
+            let listener = match volume_listener_ref(pv) {
+                Some(listener_ref) => listeners.get(&self.client, &listener_ref).await?,
+                None => None,
+            };
+            let claim_ref = pv.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref());
+            // Pods are only labelled with the Listener once the volume has been published to them
+            let pods: &[Pod] = match (listener, claim_ref) {
+                (Some(_), Some(claim_ref)) => {
+                    let namespace = claim_ref.namespace.as_deref().unwrap_or_default();
+                    namespace_pods
+                        .get(&self.client, namespace)
+                        .await
+                        .context(list_volumes_error::ListNamespacePodsSnafu { namespace })?
+                }
+                _ => &[],
+            };
+            let description = describe_volume(pv, listener, pods);
+            entries.push(csi::v1::list_volumes_response::Entry {
+                volume: Some(description.volume),
+                status: Some(csi::v1::list_volumes_response::VolumeStatus {
+                    published_node_ids: description.published_node_ids,
+                    volume_condition: Some(description.condition),
+                }),
+            });
+        }
+
+        Ok(Response::new(csi::v1::ListVolumesResponse {
+            entries,
+            next_token: next_token.unwrap_or_default(),
+        }))
     }
 
     async fn get_capacity(
@@ -230,8 +952,196 @@ impl csi::v1::controller_server::Controller for ListenerOperatorController {
 
     async fn controller_get_volume(
         &self,
-        _request: Request<csi::v1::ControllerGetVolumeRequest>,
+        request: Request<csi::v1::ControllerGetVolumeRequest>,
     ) -> Result<Response<csi::v1::ControllerGetVolumeResponse>, Status> {
-        tonic_unimplemented()
+        use list_volumes_error::*;
+        let request = request.into_inner();
+        let pv = self
+            .listener_volume(&request.volume_id)
+            .await?
+            .context(VolumeNotFoundSnafu {
+                volume_id: &request.volume_id,
+            })?;
+        let (listener, pods) = self.volume_listener_and_pods(&pv).await?;
+        let description = describe_volume(&pv, listener.as_ref(), &pods);
+        Ok(Response::new(csi::v1::ControllerGetVolumeResponse {
+            volume: Some(description.volume),
+            status: Some(csi::v1::controller_get_volume_response::VolumeStatus {
+                published_node_ids: description.published_node_ids,
+                volume_condition: Some(description.condition),
+            }),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stackable_operator::{
+        k8s_openapi::api::core::v1::{
+            EphemeralVolumeSource, PersistentVolumeClaimVolumeSource, PersistentVolumeSpec,
+            PodSpec, PodStatus, Volume,
+        },
+        kube::core::ObjectMeta,
+    };
+
+    use super::*;
+
+    fn pod(name: &str, node_name: &str, phase: &str, volume: Volume) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            spec: Some(PodSpec {
+                node_name: Some(node_name.to_string()),
+                volumes: Some(vec![volume]),
+                ..Default::default()
+            }),
+            status: Some(PodStatus {
+                phase: Some(phase.to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn pvc_volume(claim_name: &str) -> Volume {
+        Volume {
+            name: "listener".to_string(),
+            persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                claim_name: claim_name.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn claim_node_names_finds_running_pods() {
+        let pods = [
+            pod("a", "node-1", "Running", pvc_volume("listener-a")),
+            pod("b", "node-2", "Pending", pvc_volume("listener-a")),
+            pod("c", "node-3", "Succeeded", pvc_volume("listener-a")),
+            pod("d", "node-4", "Running", pvc_volume("listener-d")),
+            pod(
+                "e",
+                "node-5",
+                "Running",
+                Volume {
+                    name: "listener".to_string(),
+                    ephemeral: Some(EphemeralVolumeSource::default()),
+                    ..Default::default()
+                },
+            ),
+        ];
+        assert_eq!(claim_node_names(&pods, "listener-a"), ["node-1", "node-2"]);
+        assert_eq!(claim_node_names(&pods, "e-listener"), ["node-5"]);
+        assert!(claim_node_names(&pods, "listener-x").is_empty());
+    }
+
+    #[test]
+    fn describe_volume_reports_listener() {
+        let pv = PersistentVolume {
+            metadata: ObjectMeta {
+                labels: Some(
+                    [
+                        (
+                            PV_LABEL_LISTENER_NAMESPACE.to_string(),
+                            "default".to_string(),
+                        ),
+                        (
+                            PV_LABEL_LISTENER_NAME.to_string(),
+                            "my-listener".to_string(),
+                        ),
+                    ]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(PersistentVolumeSpec {
+                claim_ref: Some(ObjectReference {
+                    name: Some("listener-a".to_string()),
+                    namespace: Some("default".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            status: None,
+        };
+        let listener = listener::v1alpha1::Listener {
+            metadata: ObjectMeta {
+                name: Some("my-listener".to_string()),
+                namespace: Some("default".to_string()),
+                ..Default::default()
+            },
+            spec: listener::v1alpha1::ListenerSpec {
+                class_name: Some("external-stable".to_string()),
+                ..Default::default()
+            },
+            status: None,
+        };
+        // Pods of other volumes in the same namespace must be ignored
+        let pods = [
+            pod("a", "node-1", "Running", pvc_volume("listener-a")),
+            pod("b", "node-2", "Running", pvc_volume("listener-b")),
+        ];
+
+        let description = describe_volume(&pv, Some(&listener), &pods);
+        assert!(!description.condition.abnormal);
+        assert_eq!(description.published_node_ids, ["node-1"]);
+        assert_eq!(
+            description.volume.volume_context[VOLUME_CONTEXT_LISTENER],
+            "default/my-listener"
+        );
+        assert_eq!(
+            description.volume.volume_context[VOLUME_CONTEXT_LISTENER_CLASS_NAME],
+            "external-stable"
+        );
+
+        let description = describe_volume(&pv, None, &[]);
+        assert!(description.condition.abnormal);
+        assert!(description.published_node_ids.is_empty());
+
+        let description = describe_volume(&PersistentVolume::default(), None, &[]);
+        assert!(!description.condition.abnormal);
+        assert_eq!(
+            description.condition.message,
+            "volume has not been published yet"
+        );
+    }
+
+    #[test]
+    fn pinned_topology_granularity() {
+        let topology = |segments: &[(&str, &str)]| csi::v1::Topology {
+            segments: segments
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let node_topology = topology(&[
+            (NODE_TOPOLOGY_LABEL_HOSTNAME, "node-1"),
+            (NODE_TOPOLOGY_LABEL_ZONE, "zone-a"),
+            (NODE_TOPOLOGY_LABEL_REGION, "region-1"),
+        ]);
+        assert_eq!(
+            pinned_topology(node_topology.clone(), PinningGranularity::Node),
+            node_topology
+        );
+        assert_eq!(
+            pinned_topology(node_topology, PinningGranularity::Zone),
+            topology(&[
+                (NODE_TOPOLOGY_LABEL_ZONE, "zone-a"),
+                (NODE_TOPOLOGY_LABEL_REGION, "region-1"),
+            ])
+        );
+
+        // Nodes without a zone can only be pinned to themselves
+        let zoneless_topology = topology(&[(NODE_TOPOLOGY_LABEL_HOSTNAME, "node-2")]);
+        assert_eq!(
+            pinned_topology(zoneless_topology.clone(), PinningGranularity::Zone),
+            zoneless_topology
+        );
+
+        assert_eq!("zone".parse(), Ok(PinningGranularity::Zone));
+        assert!("rack".parse::<PinningGranularity>().is_err());
     }
 }
@@ -2,11 +2,106 @@ use std::collections::HashMap;
 
 use clap::crate_version;
 use csi_grpc as csi;
+use snafu::{OptionExt, ResultExt, Snafu};
+use stackable_operator::{
+    crd::listener::v1alpha1,
+    k8s_openapi::{
+        api::core::v1::Node,
+        apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
+    },
+    kube::{CustomResourceExt, runtime::reflector::ObjectRef},
+};
 use tonic::{Request, Response, Status};
 
-use crate::OPERATOR_KEY;
+use crate::{OPERATOR_KEY, utils::error::error_full_message};
 
-pub struct ListenerOperatorIdentity;
+pub struct ListenerOperatorIdentity {
+    pub client: stackable_operator::client::Client,
+
+    /// The Node that this plugin is serving, if it is running as a node plugin.
+    pub node_name: Option<String>,
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum ProbeError {
+    #[snafu(display("failed to get CustomResourceDefinition {crd:?}"))]
+    GetCrd {
+        source: stackable_operator::client::Error,
+        crd: String,
+    },
+
+    #[snafu(display("CustomResourceDefinition {crd:?} is not established yet"))]
+    CrdNotEstablished { crd: String },
+
+    #[snafu(display("failed to get {node}"))]
+    GetNode {
+        source: stackable_operator::client::Error,
+        node: ObjectRef<Node>,
+    },
+
+    #[snafu(display("{node} does not exist"))]
+    NodeNotFound { node: ObjectRef<Node> },
+}
+
+impl From<ProbeError> for Status {
+    fn from(err: ProbeError) -> Self {
+        let full_msg = error_full_message(&err);
+        // Convert to an appropriate tonic::Status representation and include full error message
+        match err {
+            ProbeError::GetCrd { .. } => Status::unavailable(full_msg),
+            ProbeError::CrdNotEstablished { .. } => Status::unavailable(full_msg),
+            ProbeError::GetNode { .. } => Status::unavailable(full_msg),
+            ProbeError::NodeNotFound { .. } => Status::failed_precondition(full_msg),
+        }
+    }
+}
+
+impl ListenerOperatorIdentity {
+    /// Checks whether the plugin is able to serve requests.
+    ///
+    /// This requires the Kubernetes API to be reachable, the listener-operator's CRDs to be established, and (for node
+    /// plugins) the plugin's Node to exist.
+    pub async fn check_readiness(&self) -> Result<(), ProbeError> {
+        use probe_error::*;
+        for crd_name in [
+            v1alpha1::Listener::crd_name(),
+            v1alpha1::ListenerClass::crd_name(),
+            v1alpha1::PodListeners::crd_name(),
+        ] {
+            let crd = self
+                .client
+                .get_opt::<CustomResourceDefinition>(crd_name, &())
+                .await
+                .context(GetCrdSnafu { crd: crd_name })?;
+            if !crd.as_ref().is_some_and(is_crd_established) {
+                return CrdNotEstablishedSnafu { crd: crd_name }.fail();
+            }
+        }
+
+        if let Some(node_name) = &self.node_name {
+            let node_ref = ObjectRef::<Node>::new(node_name);
+            self.client
+                .get_opt::<Node>(node_name, &())
+                .await
+                .with_context(|_| GetNodeSnafu {
+                    node: node_ref.clone(),
+                })?
+                .context(NodeNotFoundSnafu { node: node_ref })?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `crd` has been accepted by the Kubernetes API server, and its custom resources can be used.
+fn is_crd_established(crd: &CustomResourceDefinition) -> bool {
+    crd.status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .into_iter()
+        .flatten()
+        .any(|condition| condition.type_ == "Established" && condition.status == "True")
+}
 
 #[tonic::async_trait]
 impl csi::v1::identity_server::Identity for ListenerOperatorIdentity {
@@ -51,6 +146,63 @@ impl csi::v1::identity_server::Identity for ListenerOperatorIdentity {
         &self,
         _request: Request<csi::v1::ProbeRequest>,
     ) -> Result<Response<csi::v1::ProbeResponse>, Status> {
-        Ok(Response::new(csi::v1::ProbeResponse { ready: Some(true) }))
+        match self.check_readiness().await {
+            Ok(()) => Ok(Response::new(csi::v1::ProbeResponse { ready: Some(true) })),
+            // The CRDs are expected to become established shortly after (re)installing the operator, so this is
+            // reported as "not ready yet" rather than as a failure
+            Err(err @ ProbeError::CrdNotEstablished { .. }) => {
+                tracing::info!(
+                    error = &err as &dyn std::error::Error,
+                    "plugin is not ready yet"
+                );
+                Ok(Response::new(csi::v1::ProbeResponse { ready: Some(false) }))
+            }
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "plugin is not healthy"
+                );
+                Err(err.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stackable_operator::k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+        CustomResourceDefinitionCondition, CustomResourceDefinitionStatus,
+    };
+
+    use super::*;
+
+    #[test]
+    fn crd_must_be_established() {
+        let crd = |conditions: &[(&str, &str)]| CustomResourceDefinition {
+            status: Some(CustomResourceDefinitionStatus {
+                conditions: Some(
+                    conditions
+                        .iter()
+                        .map(|(type_, status)| CustomResourceDefinitionCondition {
+                            type_: type_.to_string(),
+                            status: status.to_string(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(!is_crd_established(&CustomResourceDefinition::default()));
+        assert!(!is_crd_established(&crd(&[])));
+        assert!(!is_crd_established(&crd(&[
+            ("NamesAccepted", "True"),
+            ("Established", "False"),
+        ])));
+        assert!(is_crd_established(&crd(&[
+            ("NamesAccepted", "True"),
+            ("Established", "True"),
+        ])));
     }
 }
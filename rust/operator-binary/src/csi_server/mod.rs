@@ -1,4 +1,14 @@
+use std::time::Duration;
+
+use csi_grpc as csi;
 use serde::Deserialize;
+use snafu::{OptionExt, Snafu};
+use stackable_operator::{
+    k8s_openapi::api::core::v1::ObjectReference,
+    kube::runtime::events::{Event, EventType, Recorder},
+};
+
+use crate::utils::error::error_full_message;
 
 pub mod controller;
 pub mod identity;
@@ -23,3 +33,208 @@ fn tonic_unimplemented<T>() -> Result<T, tonic::Status> {
         "this endpoint is not implemented",
     ))
 }
+
+/// The maximum length of an Event's note, as enforced by Kubernetes.
+const MAX_EVENT_NOTE_LEN: usize = 1024;
+
+/// Records Warning Events on each of `involved_objects`, reporting that the CSI operation `action` failed with `err`.
+///
+/// gRPC errors are otherwise only visible in the logs of the CSI client (such as kubelet), so this makes them visible
+/// where users will look for them.
+async fn report_error<E>(
+    event_recorder: &Recorder,
+    action: &str,
+    err: &E,
+    involved_objects: &[ObjectReference],
+) where
+    E: std::error::Error,
+    for<'a> &'a E: Into<&'static str>,
+{
+    let event = error_event(action, err);
+    for obj in involved_objects {
+        if let Err(event_err) = event_recorder.publish(&event, obj).await {
+            tracing::warn!(
+                action,
+                error = &event_err as &dyn std::error::Error,
+                "failed to publish CSI failure event"
+            );
+        }
+    }
+}
+
+/// Builds a Warning Event reporting that the CSI operation `action` failed with `err`.
+///
+/// The note names the error variant, so that failures can be matched on regardless of the exact error message.
+fn error_event<E>(action: &str, err: &E) -> Event
+where
+    E: std::error::Error,
+    for<'a> &'a E: Into<&'static str>,
+{
+    let variant: &'static str = err.into();
+    let mut note = format!("{variant}: {}", error_full_message(err));
+    if note.len() > MAX_EVENT_NOTE_LEN {
+        let mut truncate_at = MAX_EVENT_NOTE_LEN - '…'.len_utf8();
+        while !note.is_char_boundary(truncate_at) {
+            truncate_at -= 1;
+        }
+        note.truncate(truncate_at);
+        note.push('…');
+    }
+    Event {
+        type_: EventType::Warning,
+        reason: format!("{action}Failed"),
+        note: Some(note),
+        action: action.to_string(),
+        secondary: None,
+    }
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum VolumeCapabilityError {
+    #[snafu(display("listener volumes can only be mounted as filesystems, not as block devices"))]
+    BlockVolume,
+
+    #[snafu(display("volume capability does not specify an access type"))]
+    NoAccessType,
+
+    #[snafu(display("volume capability does not specify an access mode"))]
+    NoAccessMode,
+
+    #[snafu(display("access mode {mode} is not supported by listener volumes"))]
+    UnsupportedAccessMode { mode: &'static str },
+}
+
+/// Checks whether listener volumes can be used according to `capability`.
+///
+/// Returns whether the volume must be mounted read-only.
+fn validate_volume_capability(
+    capability: &csi::v1::VolumeCapability,
+) -> Result<bool, VolumeCapabilityError> {
+    use csi::v1::volume_capability::{AccessType, access_mode::Mode};
+    use volume_capability_error::*;
+    match capability.access_type.as_ref().context(NoAccessTypeSnafu)? {
+        AccessType::Mount(_) => {}
+        AccessType::Block(_) => return BlockVolumeSnafu.fail(),
+    }
+    match capability
+        .access_mode
+        .as_ref()
+        .context(NoAccessModeSnafu)?
+        .mode()
+    {
+        Mode::SingleNodeReaderOnly | Mode::MultiNodeReaderOnly => Ok(true),
+        Mode::SingleNodeWriter
+        | Mode::SingleNodeSingleWriter
+        | Mode::SingleNodeMultiWriter
+        | Mode::MultiNodeMultiWriter => Ok(false),
+        // Each Pod gets its own copy of the volume, so there is no single writer that could be enforced across Nodes
+        mode @ (Mode::Unknown | Mode::MultiNodeSingleWriter) => UnsupportedAccessModeSnafu {
+            mode: mode.as_str_name(),
+        }
+        .fail(),
+    }
+}
+
+/// The deadline that the client set for `request`, as sent in the `grpc-timeout` header.
+///
+/// See <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md> for the format.
+fn grpc_timeout<T>(request: &tonic::Request<T>) -> Option<Duration> {
+    parse_grpc_timeout(request.metadata().get("grpc-timeout")?.to_str().ok()?)
+}
+
+fn parse_grpc_timeout(timeout: &str) -> Option<Duration> {
+    let unit_start = timeout.len().checked_sub(1)?;
+    let value = timeout.get(..unit_start)?.parse::<u64>().ok()?;
+    Some(match timeout.get(unit_start..)? {
+        "H" => Duration::from_secs(value.checked_mul(60 * 60)?),
+        "M" => Duration::from_secs(value.checked_mul(60)?),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use snafu::Snafu;
+
+    use super::*;
+
+    #[derive(Snafu, Debug, strum::IntoStaticStr)]
+    enum TestError {
+        #[snafu(display("{message}"))]
+        FailedToDoThing { message: String },
+    }
+
+    #[test]
+    fn error_events_name_the_variant() {
+        let event = error_event(
+            "PublishVolume",
+            &TestError::FailedToDoThing {
+                message: "it broke".to_string(),
+            },
+        );
+        assert_eq!(event.reason, "PublishVolumeFailed");
+        assert_eq!(event.note.as_deref(), Some("FailedToDoThing: it broke"));
+
+        let event = error_event(
+            "PublishVolume",
+            &TestError::FailedToDoThing {
+                message: "ä".repeat(MAX_EVENT_NOTE_LEN),
+            },
+        );
+        let note = event.note.unwrap();
+        assert!(note.len() <= MAX_EVENT_NOTE_LEN);
+        assert!(note.ends_with('…'));
+    }
+
+    fn volume_capability(
+        access_type: csi::v1::volume_capability::AccessType,
+        mode: csi::v1::volume_capability::access_mode::Mode,
+    ) -> csi::v1::VolumeCapability {
+        csi::v1::VolumeCapability {
+            access_type: Some(access_type),
+            access_mode: Some(csi::v1::volume_capability::AccessMode { mode: mode.into() }),
+        }
+    }
+
+    #[test]
+    fn validate_volume_capabilities() {
+        use csi::v1::volume_capability::{AccessType, access_mode::Mode};
+        let mount = || AccessType::Mount(Default::default());
+        assert!(
+            !validate_volume_capability(&volume_capability(mount(), Mode::MultiNodeMultiWriter))
+                .unwrap()
+        );
+        assert!(
+            validate_volume_capability(&volume_capability(mount(), Mode::SingleNodeReaderOnly))
+                .unwrap()
+        );
+        assert!(matches!(
+            validate_volume_capability(&volume_capability(mount(), Mode::MultiNodeSingleWriter)),
+            Err(VolumeCapabilityError::UnsupportedAccessMode { .. })
+        ));
+        assert!(matches!(
+            validate_volume_capability(&volume_capability(
+                AccessType::Block(Default::default()),
+                Mode::SingleNodeWriter
+            )),
+            Err(VolumeCapabilityError::BlockVolume)
+        ));
+    }
+
+    #[test]
+    fn parse_grpc_timeouts() {
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_grpc_timeout("1500m"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_grpc_timeout("10"), None);
+        assert_eq!(parse_grpc_timeout("S"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+    }
+}
@@ -1,4 +1,9 @@
-use std::{fmt::Debug, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use csi_grpc::{self as csi, v1::Topology};
 use serde::{Deserialize, de::IntoDeserializer};
@@ -6,30 +11,71 @@ use snafu::{OptionExt, ResultExt, Snafu};
 use stackable_operator::{
     builder::meta::OwnerReferenceBuilder,
     crd::listener,
-    k8s_openapi::api::core::v1::{Node, PersistentVolume, PersistentVolumeClaim, Pod, Volume},
+    k8s_openapi::api::core::v1::{
+        ConfigMap, Node, ObjectReference, PersistentVolume, PersistentVolumeClaim, Pod, Volume,
+    },
     kube::{
+        Resource, ResourceExt,
         core::{DynamicObject, ObjectMeta},
-        runtime::reflector::ObjectRef,
+        runtime::{
+            events::{Event, EventType, Recorder, Reporter},
+            reflector::ObjectRef,
+            wait::await_condition,
+        },
     },
 };
 use tonic::{Request, Response, Status};
 
-use super::{ListenerSelector, ListenerVolumeContext, tonic_unimplemented};
+pub use self::volume_updater::{ListenerUpdateNotification, PublishedVolumes};
+use self::volume_updater::{PublishedVolume, addresses_eq};
+use super::{
+    ListenerSelector, ListenerVolumeContext, VolumeCapabilityError, grpc_timeout, report_error,
+    tonic_unimplemented, validate_volume_capability,
+};
 use crate::{
+    OPERATOR_KEY,
     listener_controller::{
         ListenerMountedPodLabelError, ListenerPersistentVolumeLabelError,
-        listener_mounted_pod_label, listener_persistent_volume_label,
+        ListenerPreferredAddressTypeError, PV_ANNOTATION_LISTENER_NODE,
+        listener_class_node_selector, listener_mounted_pod_label, listener_persistent_volume_label,
+        listener_preferred_address_type_override, node_matches_selector,
     },
-    utils::{address::node_primary_addresses, error::error_full_message},
+    utils::{address::node_primary_addresses, dns::AddressResolver, error::error_full_message},
 };
 
+mod mount;
+mod pod_dir;
+mod template;
+mod volume_updater;
+
 const FIELD_MANAGER_SCOPE: &str = "volume";
+const FULL_NODE_SERVICE_NAME: &str = "node.listeners.stackable.tech";
 
 pub const NODE_TOPOLOGY_LABEL_HOSTNAME: &str = "listeners.stackable.tech/hostname";
+/// The well-known Node label for the zone that the Node runs in, which is also reported as a topology segment.
+pub const NODE_TOPOLOGY_LABEL_ZONE: &str = "topology.kubernetes.io/zone";
+/// The well-known Node label for the region that the Node runs in, which is also reported as a topology segment.
+pub const NODE_TOPOLOGY_LABEL_REGION: &str = "topology.kubernetes.io/region";
+
+/// How long to wait for a Listener to be assigned addresses, if kubelet did not set a deadline for the request.
+const DEFAULT_LISTENER_ADDRESS_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How much of kubelet's deadline is reserved for finishing the publish once the Listener has been assigned addresses.
+const LISTENER_ADDRESS_WAIT_DEADLINE_MARGIN: Duration = Duration::from_secs(10);
 
+#[derive(Clone)]
 pub struct ListenerOperatorNode {
     pub client: stackable_operator::client::Client,
     pub node_name: String,
+    pub published_volumes: PublishedVolumes,
+    pub listener_update_notification: ListenerUpdateNotification,
+    /// Whether listener volumes should be backed by a tmpfs, rather than a plain directory on the Node's disk.
+    pub tmpfs_volumes: bool,
+    /// The maximum number of listener volumes that may be published on this Node, unlimited if [`None`].
+    pub max_volumes_per_node: Option<i64>,
+    /// Node labels that are reported as additional topology segments, if the Node has them.
+    pub topology_labels: Vec<String>,
+    /// Picks the Node's address, so that it is published the same way as by the Listener controller.
+    pub address_resolver: AddressResolver,
 }
 
 #[derive(Deserialize)]
@@ -38,13 +84,29 @@ struct ListenerNodeVolumeContext {
     pod_namespace: String,
     #[serde(rename = "csi.storage.k8s.io/pod.name")]
     pod_name: String,
+    /// Set to `"true"` by kubelet for CSI inline ephemeral volumes, which are defined directly in the Pod rather than
+    /// being provisioned for a PersistentVolumeClaim.
+    #[serde(rename = "csi.storage.k8s.io/ephemeral", default)]
+    ephemeral: Option<String>,
     #[serde(flatten)]
     common: ListenerVolumeContext,
 }
 
-#[derive(Snafu, Debug)]
+impl ListenerNodeVolumeContext {
+    fn is_inline(&self) -> bool {
+        self.ephemeral.as_deref() == Some("true")
+    }
+}
+
+#[derive(Snafu, Debug, strum::IntoStaticStr)]
 #[snafu(module)]
 enum PublishVolumeError {
+    #[snafu(display("request does not specify a volume capability"))]
+    NoVolumeCapability,
+
+    #[snafu(display("unsupported volume capability"))]
+    UnsupportedVolumeCapability { source: VolumeCapabilityError },
+
     #[snafu(display("failed to decode volume context"))]
     DecodeVolumeContext { source: serde::de::value::Error },
 
@@ -69,6 +131,12 @@ enum PublishVolumeError {
         listener: ObjectRef<listener::v1alpha1::Listener>,
     },
 
+    #[snafu(display("failed to resolve {listener}'s preferred address type"))]
+    ListenerPreferredAddressType {
+        source: ListenerPreferredAddressTypeError,
+        listener: ObjectRef<listener::v1alpha1::Listener>,
+    },
+
     #[snafu(display("{listener} has no associated ListenerClass"))]
     ListenerHasNoClass {
         listener: ObjectRef<listener::v1alpha1::Listener>,
@@ -100,6 +168,12 @@ enum PublishVolumeError {
         pod: ObjectRef<Pod>,
     },
 
+    #[snafu(display("failed to wait for {listener} to be assigned addresses"))]
+    WaitForListenerAddresses {
+        source: stackable_operator::kube::runtime::wait::Error,
+        listener: ObjectRef<listener::v1alpha1::Listener>,
+    },
+
     #[snafu(display("listener has no addresses yet"))]
     NoAddresses,
 
@@ -109,10 +183,9 @@ enum PublishVolumeError {
         target_path: PathBuf,
     },
 
-    #[snafu(display("failed to write {pod_listeners} (also tried to create: {create_error})"))]
-    WritePodListeners {
+    #[snafu(display("failed to apply {pod_listeners}"))]
+    ApplyPodListeners {
         source: stackable_operator::client::Error,
-        create_error: stackable_operator::client::Error,
         pod_listeners: ObjectRef<listener::v1alpha1::PodListeners>,
     },
 
@@ -120,6 +193,24 @@ enum PublishVolumeError {
     FindPodVolumeForPvc {
         pvc: ObjectRef<PersistentVolumeClaim>,
     },
+
+    #[snafu(display("failed to find inline listener volume at {target_path:?} in {pod}"))]
+    FindPodVolumeForInlineVolume {
+        pod: ObjectRef<Pod>,
+        target_path: PathBuf,
+    },
+
+    #[snafu(display("template {template:?} of {obj} does not have a valid file name"))]
+    InvalidTemplateName {
+        template: String,
+        obj: ObjectRef<DynamicObject>,
+    },
+
+    #[snafu(display("failed to render template {template:?}"))]
+    RenderTemplate {
+        source: template::Error,
+        template: String,
+    },
 }
 
 impl From<PublishVolumeError> for Status {
@@ -127,21 +218,34 @@ impl From<PublishVolumeError> for Status {
         let full_msg = error_full_message(&err);
         // Convert to an appropriate tonic::Status representation and include full error message
         match err {
+            PublishVolumeError::NoVolumeCapability => Status::invalid_argument(full_msg),
+            PublishVolumeError::UnsupportedVolumeCapability { .. } => {
+                Status::invalid_argument(full_msg)
+            }
             PublishVolumeError::DecodeVolumeContext { .. } => Status::invalid_argument(full_msg),
             PublishVolumeError::GetObject { .. } => Status::unavailable(full_msg),
             PublishVolumeError::UnclaimedPv => Status::unavailable(full_msg),
             PublishVolumeError::PodHasNoNode { .. } => Status::unavailable(full_msg),
             PublishVolumeError::ListenerPvReference { .. } => Status::failed_precondition(full_msg),
             PublishVolumeError::ListenerPodSelector { .. } => Status::failed_precondition(full_msg),
+            PublishVolumeError::ListenerPreferredAddressType { .. } => {
+                Status::failed_precondition(full_msg)
+            }
             PublishVolumeError::ListenerHasNoClass { .. } => Status::failed_precondition(full_msg),
             PublishVolumeError::BuildListenerOwnerRef { .. } => Status::unavailable(full_msg),
             PublishVolumeError::ApplyListener { .. } => Status::unavailable(full_msg),
             PublishVolumeError::AddListenerLabelToPv { .. } => Status::unavailable(full_msg),
             PublishVolumeError::AddListenerLabelToPod { .. } => Status::unavailable(full_msg),
+            PublishVolumeError::WaitForListenerAddresses { .. } => Status::unavailable(full_msg),
             PublishVolumeError::NoAddresses => Status::unavailable(full_msg),
             PublishVolumeError::PreparePodDir { .. } => Status::internal(full_msg),
-            PublishVolumeError::WritePodListeners { .. } => Status::unavailable(full_msg),
+            PublishVolumeError::ApplyPodListeners { .. } => Status::unavailable(full_msg),
             PublishVolumeError::FindPodVolumeForPvc { .. } => Status::failed_precondition(full_msg),
+            PublishVolumeError::FindPodVolumeForInlineVolume { .. } => {
+                Status::failed_precondition(full_msg)
+            }
+            PublishVolumeError::InvalidTemplateName { .. } => Status::failed_precondition(full_msg),
+            PublishVolumeError::RenderTemplate { .. } => Status::failed_precondition(full_msg),
         }
     }
 }
@@ -149,9 +253,36 @@ impl From<PublishVolumeError> for Status {
 #[derive(Snafu, Debug)]
 #[snafu(module)]
 enum UnpublishVolumeError {
+    #[snafu(display("failed to read volume metadata"))]
+    ReadVolumeMetadata { source: pod_dir::Error },
+
+    #[snafu(display("failed to get {obj}"))]
+    GetObject {
+        source: stackable_operator::client::Error,
+        obj: ObjectRef<DynamicObject>,
+    },
+
+    #[snafu(display("failed to remove listener label from {pod}"))]
+    RemoveListenerLabelFromPod {
+        source: stackable_operator::client::Error,
+        pod: ObjectRef<Pod>,
+    },
+
+    #[snafu(display("failed to build PodListeners' owner reference"))]
+    BuildPodListenersOwnerRef {
+        source: stackable_operator::builder::meta::Error,
+    },
+
+    #[snafu(display("failed to remove volume {pod_volume_name:?} from {pod_listeners}"))]
+    RemovePodListener {
+        source: stackable_operator::client::Error,
+        pod_listeners: ObjectRef<listener::v1alpha1::PodListeners>,
+        pod_volume_name: String,
+    },
+
     #[snafu(display("failed to clean up volume data at {path:?}"))]
     CleanupData {
-        source: std::io::Error,
+        source: pod_dir::Error,
         path: PathBuf,
     },
 }
@@ -161,76 +292,291 @@ impl From<UnpublishVolumeError> for Status {
         let full_msg = error_full_message(&err);
         // Convert to an appropriate tonic::Status representation and include full error message
         match err {
+            UnpublishVolumeError::ReadVolumeMetadata { .. } => Status::internal(full_msg),
+            UnpublishVolumeError::GetObject { .. } => Status::unavailable(full_msg),
+            UnpublishVolumeError::RemoveListenerLabelFromPod { .. } => {
+                Status::unavailable(full_msg)
+            }
+            UnpublishVolumeError::BuildPodListenersOwnerRef { .. } => Status::unavailable(full_msg),
+            UnpublishVolumeError::RemovePodListener { .. } => Status::unavailable(full_msg),
             UnpublishVolumeError::CleanupData { .. } => Status::internal(full_msg),
         }
     }
 }
 
-#[tonic::async_trait]
-impl csi::v1::node_server::Node for ListenerOperatorNode {
-    async fn node_get_info(
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum GetVolumeStatsError {
+    #[snafu(display("failed to check whether volume {volume_path:?} exists"))]
+    CheckVolumeExists {
+        source: std::io::Error,
+        volume_path: PathBuf,
+    },
+
+    #[snafu(display("volume {volume_path:?} does not exist"))]
+    VolumeNotFound { volume_path: PathBuf },
+
+    #[snafu(display("failed to read volume"))]
+    ReadVolume { source: pod_dir::Error },
+
+    #[snafu(display("failed to get {obj}"))]
+    GetObject {
+        source: stackable_operator::client::Error,
+        obj: ObjectRef<DynamicObject>,
+    },
+
+    #[snafu(display("failed to get current addresses of {listener}"))]
+    ListenerAddresses {
+        source: PublishVolumeError,
+        listener: ObjectRef<listener::v1alpha1::Listener>,
+    },
+}
+
+impl From<GetVolumeStatsError> for Status {
+    fn from(err: GetVolumeStatsError) -> Self {
+        let full_msg = error_full_message(&err);
+        // Convert to an appropriate tonic::Status representation and include full error message
+        match err {
+            GetVolumeStatsError::CheckVolumeExists { .. } => Status::internal(full_msg),
+            GetVolumeStatsError::VolumeNotFound { .. } => Status::not_found(full_msg),
+            GetVolumeStatsError::ReadVolume { .. } => Status::internal(full_msg),
+            GetVolumeStatsError::GetObject { .. } => Status::unavailable(full_msg),
+            GetVolumeStatsError::ListenerAddresses { .. } => Status::unavailable(full_msg),
+        }
+    }
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum GetInfoError {
+    #[snafu(display("failed to get {node}"))]
+    GetNode {
+        source: stackable_operator::client::Error,
+        node: ObjectRef<Node>,
+    },
+}
+
+impl From<GetInfoError> for Status {
+    fn from(err: GetInfoError) -> Self {
+        let full_msg = error_full_message(&err);
+        // Convert to an appropriate tonic::Status representation and include full error message
+        match err {
+            GetInfoError::GetNode { .. } => Status::unavailable(full_msg),
+        }
+    }
+}
+
+impl ListenerOperatorNode {
+    fn event_recorder(&self) -> Recorder {
+        Recorder::new(
+            self.client.as_kube_client(),
+            Reporter {
+                controller: FULL_NODE_SERVICE_NAME.to_string(),
+                instance: Some(self.node_name.clone()),
+            },
+        )
+    }
+
+    /// Warns (as an Event on `pod`) if this Node doesn't match the node selector of `listener`'s ListenerClass.
+    ///
+    /// New PersistentVolumes are already restricted to the selected Nodes by the controller, but inline volumes (and
+    /// volumes provisioned before the selector was configured) are not. These are still published, rather than
+    /// breaking workloads that are already running, but the Node's addresses may not be reachable by clients.
+    async fn warn_if_node_not_selected(
         &self,
-        _request: Request<csi::v1::NodeGetInfoRequest>,
-    ) -> Result<Response<csi::v1::NodeGetInfoResponse>, Status> {
-        Ok(Response::new(csi::v1::NodeGetInfoResponse {
-            node_id: self.node_name.clone(),
-            max_volumes_per_node: i64::MAX,
-            accessible_topology: Some(Topology {
-                segments: [(
-                    NODE_TOPOLOGY_LABEL_HOSTNAME.to_string(),
-                    self.node_name.clone(),
-                )]
-                .into(),
-            }),
-        }))
+        listener: &listener::v1alpha1::Listener,
+        pod: &Pod,
+    ) -> Result<(), PublishVolumeError> {
+        use publish_volume_error::*;
+        // The selector only applies to NodePort Listeners
+        let is_node_port = listener
+            .status
+            .as_ref()
+            .is_some_and(|status| status.node_ports.is_some());
+        let (true, Some(listener_class_name)) = (is_node_port, listener.spec.class_name.as_deref())
+        else {
+            return Ok(());
+        };
+        let listener_class = self
+            .client
+            .get::<listener::v1alpha1::ListenerClass>(listener_class_name, &())
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<listener::v1alpha1::ListenerClass>::new(listener_class_name)
+                    .erase(),
+            })?;
+        // Invalid selectors are already reported by the Listener controller
+        let Ok(Some(selector)) = listener_class_node_selector(&listener_class) else {
+            return Ok(());
+        };
+        let node = self
+            .client
+            .get::<Node>(&self.node_name, &())
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<Node>::new(&self.node_name).erase(),
+            })?;
+        if node_matches_selector(node.labels(), &selector) {
+            return Ok(());
+        }
+
+        let listener_ref = ObjectRef::from_obj(listener);
+        let listener_class_ref = ObjectRef::from_obj(&listener_class);
+        tracing::warn!(
+            pod = %ObjectRef::from_obj(pod),
+            listener = %listener_ref,
+            listener_class = %listener_class_ref,
+            node = self.node_name,
+            "Node does not match the node selector of the ListenerClass, clients may not be able to reach the Pod"
+        );
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "NodeNotSelected".to_string(),
+            note: Some(format!(
+                "Node {node:?} does not match the node selector of {listener_class_ref}, \
+                so clients may not be able to reach the Pod through {listener_ref}",
+                node = self.node_name,
+            )),
+            action: "PublishVolume".to_string(),
+            secondary: Some(listener_ref.into()),
+        };
+        if let Err(err) = self
+            .event_recorder()
+            .publish(&event, &pod.object_ref(&()))
+            .await
+        {
+            tracing::warn!(
+                pod = %ObjectRef::from_obj(pod),
+                error = &err as &dyn std::error::Error,
+                "failed to publish node selector mismatch event"
+            );
+        }
+        Ok(())
     }
 
-    async fn node_get_capabilities(
+    /// Waits for up to `timeout` for `listener` to be assigned addresses.
+    ///
+    /// Returns the latest version of `listener`, which may still not have any addresses if the timeout expired.
+    async fn wait_for_listener_addresses(
         &self,
-        _request: Request<csi::v1::NodeGetCapabilitiesRequest>,
-    ) -> Result<Response<csi::v1::NodeGetCapabilitiesResponse>, Status> {
-        Ok(Response::new(csi::v1::NodeGetCapabilitiesResponse {
-            capabilities: Vec::new(),
-        }))
+        listener: listener::v1alpha1::Listener,
+        pod: &Pod,
+        timeout: Duration,
+    ) -> Result<listener::v1alpha1::Listener, PublishVolumeError> {
+        use publish_volume_error::*;
+        let listener_ref = ObjectRef::from_obj(&listener);
+        let event = Event {
+            type_: EventType::Normal,
+            reason: "WaitingForListenerAddresses".to_string(),
+            note: Some(format!(
+                "waiting up to {timeout:?} for {listener_ref} to be assigned addresses (such as by a LoadBalancer)"
+            )),
+            action: "PublishVolume".to_string(),
+            secondary: Some(listener_ref.clone().into()),
+        };
+        if let Err(err) = self
+            .event_recorder()
+            .publish(&event, &pod.object_ref(&()))
+            .await
+        {
+            tracing::warn!(
+                pod = %ObjectRef::from_obj(pod),
+                error = &err as &dyn std::error::Error,
+                "failed to publish listener address wait event"
+            );
+        }
+
+        let has_addresses = |listener: Option<&listener::v1alpha1::Listener>| {
+            listener
+                .and_then(|listener| listener.status.as_ref())
+                .is_some_and(|status| {
+                    status.node_ports.is_some()
+                        || status
+                            .ingress_addresses
+                            .as_ref()
+                            .is_some_and(|addrs| !addrs.is_empty())
+                })
+        };
+        let ns = listener_ref.namespace.as_deref().unwrap_or_default();
+        match tokio::time::timeout(
+            timeout,
+            await_condition(
+                self.client.get_api::<listener::v1alpha1::Listener>(ns),
+                &listener_ref.name,
+                has_addresses,
+            ),
+        )
+        .await
+        {
+            Ok(updated_listener) => Ok(updated_listener
+                .with_context(|_| WaitForListenerAddressesSnafu {
+                    listener: listener_ref.clone(),
+                })?
+                // The Listener was deleted in the meantime
+                .unwrap_or(listener)),
+            Err(_) => {
+                tracing::info!(
+                    listener = %listener_ref,
+                    ?timeout,
+                    "timed out waiting for listener to be assigned addresses"
+                );
+                Ok(listener)
+            }
+        }
     }
 
-    async fn node_publish_volume(
+    /// The topology segments of this Node.
+    ///
+    /// The zone, region, and [`Self::topology_labels`] are only reported if the Node is labelled with them.
+    async fn node_topology(&self) -> Result<Topology, GetInfoError> {
+        use get_info_error::*;
+        let node = self
+            .client
+            .get::<Node>(&self.node_name, &())
+            .await
+            .with_context(|_| GetNodeSnafu {
+                node: ObjectRef::<Node>::new(&self.node_name),
+            })?;
+        Ok(Topology {
+            segments: node_topology_segments(&self.node_name, node.labels(), &self.topology_labels),
+        })
+    }
+
+    /// Implements [`csi::v1::node_server::Node::node_publish_volume`].
+    ///
+    /// `involved_objects` collects the objects that any errors should be reported on.
+    async fn publish_volume(
         &self,
         request: Request<csi::v1::NodePublishVolumeRequest>,
-    ) -> Result<Response<csi::v1::NodePublishVolumeResponse>, Status> {
+        involved_objects: &mut Vec<ObjectReference>,
+    ) -> Result<(), PublishVolumeError> {
         use publish_volume_error::*;
 
+        let address_wait_timeout = grpc_timeout(&request)
+            .map_or(DEFAULT_LISTENER_ADDRESS_WAIT_TIMEOUT, |timeout| {
+                timeout.saturating_sub(LISTENER_ADDRESS_WAIT_DEADLINE_MARGIN)
+            });
         let request = request.into_inner();
+        let read_only_capability = validate_volume_capability(
+            request
+                .volume_capability
+                .as_ref()
+                .context(NoVolumeCapabilitySnafu)?,
+        )
+        .context(UnsupportedVolumeCapabilitySnafu)?;
+        let read_only = request.readonly || read_only_capability;
+        let volume_context = ListenerNodeVolumeContext::deserialize(
+            request.volume_context.clone().into_deserializer(),
+        )
+        .context(DecodeVolumeContextSnafu)?;
+        let inline = volume_context.is_inline();
         let ListenerNodeVolumeContext {
             pod_namespace: ns,
             pod_name,
             common: ListenerVolumeContext { listener_selector },
-        } = ListenerNodeVolumeContext::deserialize(request.volume_context.into_deserializer())
-            .context(DecodeVolumeContextSnafu)?;
-        let pv_name = &request.volume_id;
-
-        let pv = self
-            .client
-            .get::<PersistentVolume>(pv_name, &())
-            .await
-            .with_context(|_| GetObjectSnafu {
-                obj: {
-                    let obj = ObjectRef::<PersistentVolume>::new(pv_name);
-                    obj.erase()
-                },
-            })?;
-        let pvc_name = pv
-            .spec
-            .as_ref()
-            .and_then(|pv_spec| pv_spec.claim_ref.as_ref()?.name.as_deref())
-            .context(UnclaimedPvSnafu)?;
-        let pvc = self
-            .client
-            .get::<PersistentVolumeClaim>(pvc_name, &ns)
-            .await
-            .with_context(|_| GetObjectSnafu {
-                obj: { ObjectRef::<PersistentVolumeClaim>::new(pvc_name).erase() },
-            })?;
+            ..
+        } = volume_context;
+        let target_path = PathBuf::from(request.target_path);
 
         let pod = self
             .client
@@ -239,6 +585,67 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
             .with_context(|_| GetObjectSnafu {
                 obj: { ObjectRef::<Pod>::new(&pod_name).erase() },
             })?;
+        involved_objects.push(pod.object_ref(&()));
+
+        // Inline volumes are defined directly in the Pod, so there is no PersistentVolume(Claim) to look up
+        let claim = if inline {
+            None
+        } else {
+            let pv_name = &request.volume_id;
+            let pv = self
+                .client
+                .get::<PersistentVolume>(pv_name, &())
+                .await
+                .with_context(|_| GetObjectSnafu {
+                    obj: {
+                        let obj = ObjectRef::<PersistentVolume>::new(pv_name);
+                        obj.erase()
+                    },
+                })?;
+            let pvc_name = pv
+                .spec
+                .as_ref()
+                .and_then(|pv_spec| pv_spec.claim_ref.as_ref()?.name.as_deref())
+                .context(UnclaimedPvSnafu)?;
+            let pvc = self
+                .client
+                .get::<PersistentVolumeClaim>(pvc_name, &ns)
+                .await
+                .with_context(|_| GetObjectSnafu {
+                    obj: { ObjectRef::<PersistentVolumeClaim>::new(pvc_name).erase() },
+                })?;
+            involved_objects.push(pvc.object_ref(&()));
+            Some((pv, pvc))
+        };
+
+        let pod_volume_name = match &claim {
+            Some((_, pvc)) => listener_pod_volume_name(&pod, &pod_name, &pvc.name_any())?,
+            None => inline_listener_pod_volume_name(&pod, &target_path)?,
+        }
+        .to_string();
+
+        let templates = match &claim {
+            Some((_, pvc)) => {
+                listener_volume_templates(
+                    &self.client,
+                    &ns,
+                    pvc.metadata.annotations.as_ref(),
+                    ObjectRef::from_obj(pvc).erase(),
+                )
+                .await?
+            }
+            // Inline volumes have no PersistentVolumeClaim to annotate, so their templates are defined as volume
+            // attributes instead
+            None => {
+                listener_volume_templates(
+                    &self.client,
+                    &ns,
+                    Some(&request.volume_context.into_iter().collect()),
+                    ObjectRef::from_obj(&pod).erase(),
+                )
+                .await?
+            }
+        };
 
         let listener = match listener_selector {
             ListenerSelector::Listener(listener_name) => self
@@ -253,19 +660,34 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
                     },
                 })?,
             ListenerSelector::ListenerClass(listener_class_name) => {
+                let (listener_name, owner_ref, labels) = match &claim {
+                    Some((pv, pvc)) => (
+                        pvc.name_any(),
+                        OwnerReferenceBuilder::new()
+                            .initialize_from_resource(pv)
+                            .build(),
+                        // Propagate the labels from the PVC to the Listener object, so it can be found easier, e.g. to
+                        // determine the endpoints of stacklets.
+                        pvc.metadata.labels.clone(),
+                    ),
+                    // Inline volumes are named after the Pod volume (the same way as the PVCs of generic ephemeral
+                    // volumes), and only live as long as the Pod
+                    None => (
+                        format!("{pod_name}-{pod_volume_name}"),
+                        OwnerReferenceBuilder::new()
+                            .initialize_from_resource(&pod)
+                            .build(),
+                        None,
+                    ),
+                };
                 let listener = listener::v1alpha1::Listener {
                     metadata: ObjectMeta {
                         namespace: Some(ns.clone()),
-                        name: Some(pvc_name.to_string()),
+                        name: Some(listener_name),
                         owner_references: Some(vec![
-                            OwnerReferenceBuilder::new()
-                                .initialize_from_resource(&pv)
-                                .build()
-                                .context(BuildListenerOwnerRefSnafu)?,
+                            owner_ref.context(BuildListenerOwnerRefSnafu)?,
                         ]),
-                        // Propagate the labels from the PVC to the Listener object, so it can be found easier, e.g. to
-                        // determine the endpoints of stacklets.
-                        labels: pvc.metadata.labels,
+                        labels,
                         ..Default::default()
                     },
                     spec: listener::v1alpha1::ListenerSpec {
@@ -300,46 +722,77 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
             }
         };
 
+        let mounted_pod_label =
+            listener_mounted_pod_label(&listener).context(ListenerPodSelectorSnafu {
+                listener: ObjectRef::from_obj(&listener),
+            })?;
+        pod_dir::prepare_target_path(&target_path, self.tmpfs_volumes)
+            .await
+            .context(PreparePodDirSnafu {
+                target_path: &target_path,
+            })?;
+        // Record what needs to be cleaned up before making any changes, since unpublish requests don't carry the volume
+        // context
+        pod_dir::write_volume_metadata(
+            &target_path,
+            &pod_dir::VolumeMetadata {
+                pod_namespace: ns.clone(),
+                pod_name: pod_name.clone(),
+                pod_uid: pod.metadata.uid.clone().unwrap_or_default(),
+                pod_volume_name: pod_volume_name.clone(),
+                mounted_pod_label: mounted_pod_label.0.clone(),
+                templates: templates.clone(),
+                read_only,
+            },
+        )
+        .await
+        .context(PreparePodDirSnafu {
+            target_path: &target_path,
+        })?;
+
         // Add listener label to PV, allowing traffic to be directed based on reservations, rather than which replicas are *currently* active.
         // See https://github.com/stackabletech/listener-operator/issues/220
-        self.client
-            .apply_patch(
-                FIELD_MANAGER_SCOPE,
-                &pv,
-                &PersistentVolume {
-                    metadata: ObjectMeta {
-                        labels: Some(listener_persistent_volume_label(&listener).context(
-                            ListenerPvReferenceSnafu {
-                                listener: ObjectRef::from_obj(&listener),
-                            },
-                        )?),
+        // The Node is recorded too, since PVs that are only pinned to a zone don't identify it in their node affinity.
+        if let Some((pv, _)) = &claim {
+            self.client
+                .apply_patch(
+                    FIELD_MANAGER_SCOPE,
+                    pv,
+                    &PersistentVolume {
+                        metadata: ObjectMeta {
+                            labels: Some(listener_persistent_volume_label(&listener).context(
+                                ListenerPvReferenceSnafu {
+                                    listener: ObjectRef::from_obj(&listener),
+                                },
+                            )?),
+                            annotations: Some(
+                                [(
+                                    PV_ANNOTATION_LISTENER_NODE.to_string(),
+                                    self.node_name.clone(),
+                                )]
+                                .into(),
+                            ),
+                            ..Default::default()
+                        },
                         ..Default::default()
                     },
-                    ..Default::default()
-                },
-            )
-            .await
-            .with_context(|_| AddListenerLabelToPvSnafu {
-                pv: ObjectRef::from_obj(&pv),
-            })?;
+                )
+                .await
+                .with_context(|_| AddListenerLabelToPvSnafu {
+                    pv: ObjectRef::from_obj(pv),
+                })?;
+        }
 
         // Add listener label to pod so that traffic can be directed to it
         self.client
             // IMPORTANT
             // Use a merge patch rather than an apply so that we don't delete labels added by other listener volumes.
-            // Volumes aren't hot-swappable anyway, and all labels will be removed when the pod is deleted.
+            // The label is removed again when the volume is unpublished.
             .merge_patch(
                 &pod,
                 &Pod {
                     metadata: ObjectMeta {
-                        labels: Some(
-                            [listener_mounted_pod_label(&listener).context(
-                                ListenerPodSelectorSnafu {
-                                    listener: ObjectRef::from_obj(&listener),
-                                },
-                            )?]
-                            .into(),
-                        ),
+                        labels: Some([mounted_pod_label].into()),
                         ..Default::default()
                     },
                     ..Default::default()
@@ -350,26 +803,388 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
                 pod: ObjectRef::from_obj(&pod),
             })?;
 
-        let listener_addrs =
-            local_listener_addresses_for_pod(&self.client, &listener, &pod).await?;
+        let mut listener_addrs =
+            local_listener_addresses_for_pod(&self.client, &self.address_resolver, &listener, &pod)
+                .await?;
+        // Wait for the addresses here rather than failing, since kubelet's retry backoff would delay the Pod for much
+        // longer than necessary
+        let listener = if listener_addrs.is_empty() {
+            let listener = self
+                .wait_for_listener_addresses(listener, &pod, address_wait_timeout)
+                .await?;
+            listener_addrs = local_listener_addresses_for_pod(
+                &self.client,
+                &self.address_resolver,
+                &listener,
+                &pod,
+            )
+            .await?;
+            listener
+        } else {
+            listener
+        };
         if listener_addrs.is_empty() {
             NoAddressesSnafu.fail()?
         }
+        self.warn_if_node_not_selected(&listener, &pod).await?;
         publish_pod_listener(
             &self.client,
             &pod,
-            &pod_name,
-            pvc_name,
+            &pod_volume_name,
             &listener,
             &listener_addrs,
         )
         .await?;
-        let target_path = PathBuf::from(request.target_path);
-        pod_dir::write_listener_info_to_pod_dir(&target_path, &listener_addrs)
+        let listener_info = pod_dir_listener_info(&listener, &listener_addrs);
+        pod_dir::write_listener_info_to_pod_dir(
+            &target_path,
+            &listener_info,
+            &render_templates(&templates, &listener_info)?,
+            read_only,
+        )
+        .await
+        .context(PreparePodDirSnafu {
+            target_path: &target_path,
+        })?;
+        // Keep the volume up to date if the Listener's addresses change later on
+        self.published_volumes.insert(
+            target_path,
+            PublishedVolume {
+                listener: ObjectRef::from_obj(&listener),
+                pod: ObjectRef::from_obj(&pod),
+                pod_uid: pod.metadata.uid.clone().unwrap_or_default(),
+                pod_volume_name,
+                addresses: listener_addrs,
+                templates,
+                read_only,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Reports the usage and health of the volume at `volume_path`.
+    async fn volume_stats(
+        &self,
+        volume_path: &Path,
+    ) -> Result<csi::v1::NodeGetVolumeStatsResponse, GetVolumeStatsError> {
+        use get_volume_stats_error::*;
+        if !tokio::fs::try_exists(volume_path)
             .await
-            .context(PreparePodDirSnafu { target_path })?;
+            .context(CheckVolumeExistsSnafu { volume_path })?
+        {
+            return VolumeNotFoundSnafu { volume_path }.fail();
+        }
 
-        Ok(Response::new(csi::v1::NodePublishVolumeResponse {}))
+        // Volumes that are plain directories on the Node's disk have no usage of their own
+        let usage = pod_dir::filesystem_usage(volume_path)
+            .await
+            .context(ReadVolumeSnafu)?;
+        let volume_usage =
+            |unit: csi::v1::volume_usage::Unit, total, available, used| csi::v1::VolumeUsage {
+                total: saturating_i64(total),
+                available: saturating_i64(available),
+                used: saturating_i64(used),
+                unit: unit.into(),
+            };
+        Ok(csi::v1::NodeGetVolumeStatsResponse {
+            usage: usage
+                .map(|usage| {
+                    vec![
+                        volume_usage(
+                            csi::v1::volume_usage::Unit::Bytes,
+                            usage.total_bytes,
+                            usage.available_bytes,
+                            usage.used_bytes,
+                        ),
+                        volume_usage(
+                            csi::v1::volume_usage::Unit::Inodes,
+                            usage.total_inodes,
+                            usage.available_inodes,
+                            usage.used_inodes,
+                        ),
+                    ]
+                })
+                .unwrap_or_default(),
+            volume_condition: Some(self.volume_condition(volume_path).await?),
+        })
+    }
+
+    /// Checks whether the addresses in the volume at `volume_path` still match its Listener.
+    async fn volume_condition(
+        &self,
+        volume_path: &Path,
+    ) -> Result<csi::v1::VolumeCondition, GetVolumeStatsError> {
+        use get_volume_stats_error::*;
+        let Some(published) = pod_dir::read_published_listener(volume_path)
+            .await
+            .context(ReadVolumeSnafu)?
+        else {
+            return Ok(csi::v1::VolumeCondition {
+                abnormal: false,
+                message: "volume does not contain any listener information to check".to_string(),
+            });
+        };
+        let listener_ref = ObjectRef::<listener::v1alpha1::Listener>::new(&published.name)
+            .within(&published.namespace);
+        let abnormal = |message: String| {
+            Ok(csi::v1::VolumeCondition {
+                abnormal: true,
+                message,
+            })
+        };
+
+        let Some(listener) = self
+            .client
+            .get_opt::<listener::v1alpha1::Listener>(&published.name, &published.namespace)
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: listener_ref.clone().erase(),
+            })?
+        else {
+            return abnormal(format!("{listener_ref} has been deleted"));
+        };
+
+        // Node-scoped addresses depend on the Pod's Node, so they can only be compared if the Pod is known
+        let pod = match pod_dir::read_volume_metadata(volume_path)
+            .await
+            .context(ReadVolumeSnafu)?
+        {
+            Some(metadata) => self
+                .client
+                .get_opt::<Pod>(&metadata.pod_name, &metadata.pod_namespace)
+                .await
+                .with_context(|_| GetObjectSnafu {
+                    obj: ObjectRef::<Pod>::new(&metadata.pod_name)
+                        .within(&metadata.pod_namespace)
+                        .erase(),
+                })?
+                .filter(|pod| pod.metadata.uid.as_deref() == Some(metadata.pod_uid.as_str())),
+            None => None,
+        };
+        let current_addresses = match &pod {
+            Some(pod) => Some(
+                local_listener_addresses_for_pod(
+                    &self.client,
+                    &self.address_resolver,
+                    &listener,
+                    pod,
+                )
+                .await
+                .with_context(|_| ListenerAddressesSnafu {
+                    listener: listener_ref.clone(),
+                })?,
+            ),
+            None => None,
+        };
+
+        let has_addresses = match &current_addresses {
+            Some(addresses) => !addresses.is_empty(),
+            None => listener
+                .status
+                .as_ref()
+                .and_then(|status| status.ingress_addresses.as_ref())
+                .is_some_and(|addresses| !addresses.is_empty()),
+        };
+        if !has_addresses {
+            return abnormal(format!("{listener_ref} has no addresses"));
+        }
+        if let Some(current_addresses) = current_addresses
+            && !addresses_eq(&current_addresses, &published.addresses)
+        {
+            return abnormal(format!(
+                "volume contains the addresses [{published}], which no longer match the current addresses of {listener_ref}: [{current}]",
+                published = format_addresses(&published.addresses),
+                current = format_addresses(&current_addresses),
+            ));
+        }
+
+        Ok(csi::v1::VolumeCondition {
+            abnormal: false,
+            message: format!("volume matches the current addresses of {listener_ref}"),
+        })
+    }
+
+    /// Stops directing the Listener's traffic to the Pod, and removes the volume from the Pod's
+    /// [`listener::v1alpha1::PodListeners`].
+    ///
+    /// `other_volumes` are the Pod's other listener volumes, which may still be using the same Listener.
+    async fn unpublish_pod_listener(
+        &self,
+        metadata: &pod_dir::VolumeMetadata,
+        other_volumes: &[pod_dir::VolumeMetadata],
+    ) -> Result<(), UnpublishVolumeError> {
+        use unpublish_volume_error::*;
+        let ns = &metadata.pod_namespace;
+
+        let pod = self
+            .client
+            .get_opt::<Pod>(&metadata.pod_name, ns)
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<Pod>::new(&metadata.pod_name).within(ns).erase(),
+            })?;
+        // If the Pod is gone (or has been replaced by another one with the same name, such as by a StatefulSet), then
+        // so is its PodListeners object
+        let Some(pod) =
+            pod.filter(|pod| pod.metadata.uid.as_deref() == Some(metadata.pod_uid.as_str()))
+        else {
+            return Ok(());
+        };
+
+        let pod_listeners_name = pod_listeners_name(&metadata.pod_uid);
+        let pod_listeners = self
+            .client
+            .get_opt::<listener::v1alpha1::PodListeners>(&pod_listeners_name, ns)
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<listener::v1alpha1::PodListeners>::new(&pod_listeners_name)
+                    .within(ns)
+                    .erase(),
+            })?;
+
+        if pod
+            .metadata
+            .labels
+            .as_ref()
+            .is_some_and(|labels| labels.contains_key(&metadata.mounted_pod_label))
+            && !mounted_pod_label_still_used(metadata, pod_listeners.as_ref(), other_volumes)
+        {
+            let patch = serde_json::json!({
+                "metadata": {
+                    "labels": {
+                        &metadata.mounted_pod_label: null,
+                    },
+                },
+            });
+            self.client
+                .merge_patch(&pod, &patch)
+                .await
+                .with_context(|_| RemoveListenerLabelFromPodSnafu {
+                    pod: ObjectRef::from_obj(&pod),
+                })?;
+        }
+
+        // Applying an empty entry would create the PodListeners object if it doesn't exist (anymore)
+        let Some(pod_listeners) = pod_listeners else {
+            return Ok(());
+        };
+        // Applying an empty entry releases the volume's ownership of its entry, which removes it
+        let empty_pod_listeners = listener::v1alpha1::PodListeners {
+            metadata: pod_listeners_metadata(&pod).context(BuildPodListenersOwnerRefSnafu)?,
+            spec: listener::v1alpha1::PodListenersSpec {
+                listeners: Default::default(),
+            },
+        };
+        let pod_listeners = self
+            .client
+            .apply_patch(
+                &pod_listeners_field_manager_scope(&metadata.pod_volume_name),
+                &empty_pod_listeners,
+                &empty_pod_listeners,
+            )
+            .await
+            .with_context(|_| RemovePodListenerSnafu {
+                pod_listeners: ObjectRef::from_obj(&pod_listeners),
+                pod_volume_name: &metadata.pod_volume_name,
+            })?;
+        // Entries written by older versions are not owned by the volume's field manager, and must be removed explicitly
+        if pod_listeners
+            .spec
+            .listeners
+            .contains_key(&metadata.pod_volume_name)
+        {
+            let patch = serde_json::json!({
+                "spec": {
+                    "listeners": {
+                        &metadata.pod_volume_name: null,
+                    },
+                },
+            });
+            self.client
+                .merge_patch(&pod_listeners, &patch)
+                .await
+                .with_context(|_| RemovePodListenerSnafu {
+                    pod_listeners: ObjectRef::from_obj(&pod_listeners),
+                    pod_volume_name: &metadata.pod_volume_name,
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether any of the Pod's other entries in `pod_listeners` still use the Listener that `metadata`'s
+/// mounted-pod label refers to, in which case the label must be kept.
+///
+/// PodListeners entries don't record their Listener, so they are matched using the metadata of the Pod's
+/// `other_volumes`. Entries without any metadata (such as those published by older versions) are assumed to use
+/// the same Listener, since removing a label that is still needed would stop traffic to the Pod.
+fn mounted_pod_label_still_used(
+    metadata: &pod_dir::VolumeMetadata,
+    pod_listeners: Option<&listener::v1alpha1::PodListeners>,
+    other_volumes: &[pod_dir::VolumeMetadata],
+) -> bool {
+    pod_listeners
+        .into_iter()
+        .flat_map(|pod_listeners| pod_listeners.spec.listeners.keys())
+        .filter(|pod_volume_name| **pod_volume_name != metadata.pod_volume_name)
+        .any(|pod_volume_name| {
+            other_volumes
+                .iter()
+                .find(|other| other.pod_volume_name == *pod_volume_name)
+                .is_none_or(|other| other.mounted_pod_label == metadata.mounted_pod_label)
+        })
+}
+
+#[tonic::async_trait]
+impl csi::v1::node_server::Node for ListenerOperatorNode {
+    async fn node_get_info(
+        &self,
+        _request: Request<csi::v1::NodeGetInfoRequest>,
+    ) -> Result<Response<csi::v1::NodeGetInfoResponse>, Status> {
+        Ok(Response::new(csi::v1::NodeGetInfoResponse {
+            node_id: self.node_name.clone(),
+            max_volumes_per_node: self.max_volumes_per_node.unwrap_or(i64::MAX),
+            accessible_topology: Some(self.node_topology().await?),
+        }))
+    }
+
+    async fn node_get_capabilities(
+        &self,
+        _request: Request<csi::v1::NodeGetCapabilitiesRequest>,
+    ) -> Result<Response<csi::v1::NodeGetCapabilitiesResponse>, Status> {
+        let rpc_capability =
+            |rpc: csi::v1::node_service_capability::rpc::Type| csi::v1::NodeServiceCapability {
+                r#type: Some(csi::v1::node_service_capability::Type::Rpc(
+                    csi::v1::node_service_capability::Rpc { r#type: rpc.into() },
+                )),
+            };
+        Ok(Response::new(csi::v1::NodeGetCapabilitiesResponse {
+            capabilities: vec![
+                rpc_capability(csi::v1::node_service_capability::rpc::Type::GetVolumeStats),
+                rpc_capability(csi::v1::node_service_capability::rpc::Type::VolumeCondition),
+            ],
+        }))
+    }
+
+    async fn node_publish_volume(
+        &self,
+        request: Request<csi::v1::NodePublishVolumeRequest>,
+    ) -> Result<Response<csi::v1::NodePublishVolumeResponse>, Status> {
+        let mut involved_objects = Vec::new();
+        match self.publish_volume(request, &mut involved_objects).await {
+            Ok(()) => Ok(Response::new(csi::v1::NodePublishVolumeResponse {})),
+            Err(err) => {
+                report_error(
+                    &self.event_recorder(),
+                    "PublishVolume",
+                    &err,
+                    &involved_objects,
+                )
+                .await;
+                Err(err.into())
+            }
+        }
     }
 
     async fn node_unpublish_volume(
@@ -378,13 +1193,24 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
     ) -> Result<Response<csi::v1::NodeUnpublishVolumeResponse>, Status> {
         let request = request.into_inner();
         let path = PathBuf::from(request.target_path);
-        match tokio::fs::remove_dir_all(&path).await {
-            Ok(()) => {}
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                // already deleted => nothing to do
-            }
-            Err(err) => Err(err).context(unpublish_volume_error::CleanupDataSnafu { path })?,
-        }
+        self.published_volumes
+            .unpublish(&path, async {
+                // Volumes published by older versions don't record any metadata, so their objects can't be cleaned up
+                if let Some(metadata) = pod_dir::read_volume_metadata(&path)
+                    .await
+                    .context(unpublish_volume_error::ReadVolumeMetadataSnafu)?
+                {
+                    let other_volumes = pod_dir::read_other_volume_metadata(&path)
+                        .await
+                        .context(unpublish_volume_error::ReadVolumeMetadataSnafu)?;
+                    self.unpublish_pod_listener(&metadata, &other_volumes)
+                        .await?;
+                }
+                pod_dir::remove_target_path(&path)
+                    .await
+                    .context(unpublish_volume_error::CleanupDataSnafu { path: &path })
+            })
+            .await?;
         Ok(Response::new(csi::v1::NodeUnpublishVolumeResponse {}))
     }
 
@@ -404,9 +1230,12 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
 
     async fn node_get_volume_stats(
         &self,
-        _request: Request<csi::v1::NodeGetVolumeStatsRequest>,
+        request: Request<csi::v1::NodeGetVolumeStatsRequest>,
     ) -> Result<Response<csi::v1::NodeGetVolumeStatsResponse>, Status> {
-        tonic_unimplemented()
+        let request = request.into_inner();
+        Ok(Response::new(
+            self.volume_stats(Path::new(&request.volume_path)).await?,
+        ))
     }
 
     async fn node_expand_volume(
@@ -426,6 +1255,7 @@ impl csi::v1::node_server::Node for ListenerOperatorNode {
 /// (and so can't be found in `Endpoints`).
 async fn local_listener_addresses_for_pod(
     client: &stackable_operator::client::Client,
+    address_resolver: &AddressResolver,
     listener: &listener::v1alpha1::Listener,
     pod: &Pod,
 ) -> Result<Vec<listener::v1alpha1::ListenerIngress>, PublishVolumeError> {
@@ -465,12 +1295,19 @@ async fn local_listener_addresses_for_pod(
                     .erase(),
             })?;
 
-        Ok(node_primary_addresses(&node)
-            .pick(listener_class.spec.resolve_preferred_address_type())
+        let preferred_address_type = listener_preferred_address_type_override(listener)
+            .with_context(|_| ListenerPreferredAddressTypeSnafu {
+                listener: ObjectRef::from_obj(listener),
+            })?
+            .unwrap_or_else(|| listener_class.spec.resolve_preferred_address_type());
+
+        Ok(address_resolver
+            .pick(node_primary_addresses(&node), preferred_address_type)
+            .await
             .map(
                 |(address, address_type)| listener::v1alpha1::ListenerIngress {
                     // nodes: Some(vec![node_name.to_string()]),
-                    address: address.to_string(),
+                    address,
                     address_type,
                     ports: node_ports,
                 },
@@ -487,18 +1324,154 @@ async fn local_listener_addresses_for_pod(
     }
 }
 
-/// Publish listener into a [`listener::v1alpha1::PodListeners`] Kubernetes object.
-async fn publish_pod_listener(
+/// Formats `addresses` for humans, such as `10.0.0.1 (http=30080, https=30443)`.
+fn format_addresses(addresses: &[listener::v1alpha1::ListenerIngress]) -> String {
+    addresses
+        .iter()
+        .map(|addr| {
+            let ports = addr
+                .ports
+                .iter()
+                .map(|(name, port)| format!("{name}={port}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{} ({ports})", addr.address)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Converts `value` for CSI, which uses signed integers for sizes.
+fn saturating_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Whether the addresses of `listener` are specific to the Pod's Node, or shared by the whole cluster.
+fn pod_listener_scope(
+    listener: &listener::v1alpha1::Listener,
+) -> listener::v1alpha1::PodListenerScope {
+    if listener
+        .status
+        .as_ref()
+        .and_then(|s| s.node_ports.as_ref())
+        .is_some()
+    {
+        listener::v1alpha1::PodListenerScope::Node
+    } else {
+        listener::v1alpha1::PodListenerScope::Cluster
+    }
+}
+
+/// Describes `listener` and its `listener_addresses` for the volume contents written by [`pod_dir`].
+fn pod_dir_listener_info<'a>(
+    listener: &'a listener::v1alpha1::Listener,
+    listener_addresses: &'a [listener::v1alpha1::ListenerIngress],
+) -> pod_dir::ListenerInfo<'a> {
+    pod_dir::ListenerInfo {
+        name: listener.metadata.name.as_deref().unwrap_or_default(),
+        namespace: listener.metadata.namespace.as_deref().unwrap_or_default(),
+        class_name: listener.spec.class_name.as_deref(),
+        scope: pod_listener_scope(listener),
+        addresses: listener_addresses,
+    }
+}
+
+/// The topology segments of the Node `node_name`, with the labels `node_labels`.
+///
+/// `extra_labels` are reported in addition to the zone and region.
+fn node_topology_segments(
+    node_name: &str,
+    node_labels: &BTreeMap<String, String>,
+    extra_labels: &[String],
+) -> HashMap<String, String> {
+    let mut segments = HashMap::from([(
+        NODE_TOPOLOGY_LABEL_HOSTNAME.to_string(),
+        node_name.to_string(),
+    )]);
+    for key in [NODE_TOPOLOGY_LABEL_ZONE, NODE_TOPOLOGY_LABEL_REGION]
+        .into_iter()
+        .chain(extra_labels.iter().map(String::as_str))
+    {
+        if let Some(value) = node_labels.get(key) {
+            segments.insert(key.to_string(), value.clone());
+        }
+    }
+    segments
+}
+
+/// Loads the user-defined templates of a listener volume, keyed by the name of the file that they are rendered into.
+///
+/// `annotations` are the annotations of the volume's PersistentVolumeClaim, or the volume attributes of inline volumes,
+/// which are defined by `obj` in the namespace `ns`.
+///
+/// Templates defined using [`template::PVC_ANNOTATION_TEMPLATE_PREFIX`] annotations take precedence over templates
+/// defined in the [`template::PVC_ANNOTATION_TEMPLATES_CONFIGMAP`].
+async fn listener_volume_templates(
     client: &stackable_operator::client::Client,
-    pod: &Pod,
+    ns: &str,
+    annotations: Option<&BTreeMap<String, String>>,
+    obj: ObjectRef<DynamicObject>,
+) -> Result<BTreeMap<String, String>, PublishVolumeError> {
+    use publish_volume_error::*;
+    let mut templates = BTreeMap::new();
+    if let Some(cm_name) = annotations
+        .and_then(|annotations| annotations.get(template::PVC_ANNOTATION_TEMPLATES_CONFIGMAP))
+    {
+        let cm = client
+            .get::<ConfigMap>(cm_name, ns)
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: ObjectRef::<ConfigMap>::new(cm_name).within(ns).erase(),
+            })?;
+        templates.extend(cm.data.unwrap_or_default());
+    }
+    templates.extend(
+        annotations
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                Some((
+                    key.strip_prefix(template::PVC_ANNOTATION_TEMPLATE_PREFIX)?
+                        .to_string(),
+                    value.clone(),
+                ))
+            }),
+    );
+    if let Some(template) = templates
+        .keys()
+        .find(|name| !template::is_valid_file_name(name))
+    {
+        return InvalidTemplateNameSnafu { template, obj }.fail();
+    }
+    Ok(templates)
+}
+
+/// Renders `templates` for the listener described by `listener_info`.
+fn render_templates(
+    templates: &BTreeMap<String, String>,
+    listener_info: &pod_dir::ListenerInfo,
+) -> Result<BTreeMap<String, String>, PublishVolumeError> {
+    use publish_volume_error::*;
+    templates
+        .iter()
+        .map(|(name, contents)| {
+            Ok((
+                name.clone(),
+                template::render(contents, listener_info)
+                    .context(RenderTemplateSnafu { template: name })?,
+            ))
+        })
+        .collect()
+}
+
+/// The name of the Pod's volume that corresponds to the listener volume's PersistentVolumeClaim.
+fn listener_pod_volume_name<'a>(
+    pod: &'a Pod,
     pod_name: &str,
     pvc_name: &str,
-    listener: &listener::v1alpha1::Listener,
-    listener_addresses: &[listener::v1alpha1::ListenerIngress],
-) -> Result<(), PublishVolumeError> {
+) -> Result<&'a str, PublishVolumeError> {
     use publish_volume_error::*;
-    let listener_pod_volume = pod
-        .spec
+    pod.spec
         .as_ref()
         .and_then(|ps| {
             ps.volumes.as_ref()?.iter().find(|volume| match volume {
@@ -514,35 +1487,89 @@ async fn publish_pod_listener(
                 _ => false,
             })
         })
+        .map(|volume| volume.name.as_str())
         .with_context(|| FindPodVolumeForPvcSnafu {
             pvc: ObjectRef::<PersistentVolumeClaim>::new(pvc_name),
-        })?;
+        })
+}
+
+/// The name of the Pod's volume that corresponds to the inline listener volume mounted at `target_path`.
+///
+/// The CSI spec doesn't tell the node plugin which of the Pod's volumes it is publishing, so this relies on kubelet's
+/// layout of target paths instead, which is `<kubelet dir>/pods/<pod uid>/volumes/kubernetes.io~csi/<volume name>/mount`
+/// (see `GetPath` in kubelet's `pkg/volume/csi/csi_mounter.go`). For inline volumes, `<volume name>` is the name of
+/// the volume in the Pod spec. If kubelet ever changes this layout, then publishing inline volumes fails with
+/// [`PublishVolumeError::FindPodVolumeForInlineVolume`].
+fn inline_listener_pod_volume_name<'a>(
+    pod: &'a Pod,
+    target_path: &Path,
+) -> Result<&'a str, PublishVolumeError> {
+    use publish_volume_error::*;
+    let volume_name = target_path
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str());
+    pod.spec
+        .as_ref()
+        .and_then(|ps| {
+            ps.volumes.as_ref()?.iter().find(|volume| {
+                Some(volume.name.as_str()) == volume_name
+                    && volume
+                        .csi
+                        .as_ref()
+                        .is_some_and(|csi| csi.driver == OPERATOR_KEY)
+            })
+        })
+        .map(|volume| volume.name.as_str())
+        .with_context(|| FindPodVolumeForInlineVolumeSnafu {
+            pod: ObjectRef::from_obj(pod),
+            target_path,
+        })
+}
+
+/// The name of the [`listener::v1alpha1::PodListeners`] object for the Pod with the UID `pod_uid`.
+fn pod_listeners_name(pod_uid: &str) -> String {
+    format!("pod-{pod_uid}")
+}
+
+/// The metadata of `pod`'s [`listener::v1alpha1::PodListeners`] object.
+fn pod_listeners_metadata(
+    pod: &Pod,
+) -> Result<ObjectMeta, stackable_operator::builder::meta::Error> {
+    Ok(ObjectMeta {
+        name: pod.metadata.uid.as_deref().map(pod_listeners_name),
+        namespace: pod.metadata.namespace.clone(),
+        owner_references: Some(vec![
+            OwnerReferenceBuilder::new()
+                .initialize_from_resource(pod)
+                .build()?,
+        ]),
+        ..Default::default()
+    })
+}
+
+/// The field manager scope that owns the entry for the Pod volume `pod_volume_name` in the Pod's
+/// [`listener::v1alpha1::PodListeners`].
+fn pod_listeners_field_manager_scope(pod_volume_name: &str) -> String {
+    format!("{FIELD_MANAGER_SCOPE}-{pod_volume_name}")
+}
+
+/// Publish listener into a [`listener::v1alpha1::PodListeners`] Kubernetes object.
+async fn publish_pod_listener(
+    client: &stackable_operator::client::Client,
+    pod: &Pod,
+    pod_volume_name: &str,
+    listener: &listener::v1alpha1::Listener,
+    listener_addresses: &[listener::v1alpha1::ListenerIngress],
+) -> Result<(), PublishVolumeError> {
+    use publish_volume_error::*;
     let pod_listeners = listener::v1alpha1::PodListeners {
-        metadata: ObjectMeta {
-            name: pod.metadata.uid.as_deref().map(|uid| format!("pod-{uid}")),
-            namespace: pod.metadata.namespace.clone(),
-            owner_references: Some(vec![
-                OwnerReferenceBuilder::new()
-                    .initialize_from_resource(pod)
-                    .build()
-                    .context(BuildListenerOwnerRefSnafu)?,
-            ]),
-            ..Default::default()
-        },
+        metadata: pod_listeners_metadata(pod).context(BuildListenerOwnerRefSnafu)?,
         spec: listener::v1alpha1::PodListenersSpec {
             listeners: [(
-                listener_pod_volume.name.clone(),
+                pod_volume_name.to_string(),
                 listener::v1alpha1::PodListener {
-                    scope: if listener
-                        .status
-                        .as_ref()
-                        .and_then(|s| s.node_ports.as_ref())
-                        .is_some()
-                    {
-                        listener::v1alpha1::PodListenerScope::Node
-                    } else {
-                        listener::v1alpha1::PodListenerScope::Cluster
-                    },
+                    scope: pod_listener_scope(listener),
                     ingress_addresses: Some(listener_addresses.to_vec()),
                 },
             )]
@@ -550,73 +1577,152 @@ async fn publish_pod_listener(
         },
     };
     // IMPORTANT
-    // Use a merge patch rather than apply to avoid removing other volumes.
-    // Merge doesn't create the object if missing, so try that first.
-    if let Err(create_error) = client.create(&pod_listeners).await {
-        client
-            .merge_patch(&pod_listeners, &pod_listeners)
-            .await
-            .context(WritePodListenersSnafu {
-                create_error,
-                pod_listeners: ObjectRef::from_obj(&pod_listeners),
-            })?;
-    }
+    // Each volume applies its own entry using its own field manager, so that volumes only own (and can only remove)
+    // their own entries, regardless of which other volumes the Pod has.
+    client
+        .apply_patch(
+            &pod_listeners_field_manager_scope(pod_volume_name),
+            &pod_listeners,
+            &pod_listeners,
+        )
+        .await
+        .with_context(|_| ApplyPodListenersSnafu {
+            pod_listeners: ObjectRef::from_obj(&pod_listeners),
+        })?;
     Ok(())
 }
 
-mod pod_dir {
-    use std::path::Path;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    use snafu::{OptionExt, ResultExt, Snafu};
-    use stackable_operator::crd::listener;
-
-    #[derive(Snafu, Debug)]
-    pub enum Error {
-        #[snafu(display("failed to write content"), context(false))]
-        WriteContent { source: std::io::Error },
-        #[snafu(display("listener has no address yet"))]
-        NoDefaultAddress,
-        #[snafu(display("default address folder is outside of the volume root"))]
-        DefaultAddrIsOutsideRoot { source: std::path::StripPrefixError },
+    #[test]
+    fn node_topology_segments_only_include_present_labels() {
+        let labels = BTreeMap::from([
+            (NODE_TOPOLOGY_LABEL_ZONE.to_string(), "zone-a".to_string()),
+            ("edge".to_string(), "true".to_string()),
+            ("unrelated".to_string(), "value".to_string()),
+        ]);
+        let segments = node_topology_segments(
+            "node-1",
+            &labels,
+            &["edge".to_string(), "missing".to_string()],
+        );
+        assert_eq!(
+            segments.into_iter().collect::<BTreeMap<_, _>>(),
+            BTreeMap::from([
+                (
+                    NODE_TOPOLOGY_LABEL_HOSTNAME.to_string(),
+                    "node-1".to_string()
+                ),
+                (NODE_TOPOLOGY_LABEL_ZONE.to_string(), "zone-a".to_string()),
+                ("edge".to_string(), "true".to_string()),
+            ])
+        );
     }
 
-    pub async fn write_listener_info_to_pod_dir(
-        target_path: &Path,
-        listener_addrs: &[listener::v1alpha1::ListenerIngress],
-    ) -> Result<(), Error> {
-        let addrs_path = target_path.join("addresses");
-        tokio::fs::create_dir_all(&addrs_path).await?;
-        let mut default_addr_dir = None;
-        for addr in listener_addrs {
-            let addr_dir = addrs_path.join(&addr.address);
-            let ports_dir = addr_dir.join("ports");
-            tokio::fs::create_dir_all(&ports_dir).await?;
-            tokio::fs::write(addr_dir.join("address"), addr.address.as_bytes()).await?;
-            for (port_name, port) in &addr.ports {
-                tokio::fs::write(ports_dir.join(port_name), port.to_string().as_bytes()).await?;
-            }
-            default_addr_dir.get_or_insert(addr_dir);
-        }
+    #[test]
+    fn mounted_pod_label_is_kept_while_listener_is_used() {
+        let volume = |pod_volume_name: &str, listener: &str| pod_dir::VolumeMetadata {
+            pod_namespace: "default".to_string(),
+            pod_name: "my-pod".to_string(),
+            pod_uid: "1234".to_string(),
+            pod_volume_name: pod_volume_name.to_string(),
+            mounted_pod_label: format!("listener.stackable.tech/mnt.{listener}"),
+            templates: BTreeMap::new(),
+            read_only: false,
+        };
+        let pod_listeners = |pod_volume_names: &[&str]| listener::v1alpha1::PodListeners {
+            metadata: ObjectMeta::default(),
+            spec: listener::v1alpha1::PodListenersSpec {
+                listeners: pod_volume_names
+                    .iter()
+                    .map(|pod_volume_name| {
+                        (
+                            pod_volume_name.to_string(),
+                            listener::v1alpha1::PodListener {
+                                scope: listener::v1alpha1::PodListenerScope::Node,
+                                ingress_addresses: None,
+                            },
+                        )
+                    })
+                    .collect(),
+            },
+        };
+        let unpublished = volume("a", "shared");
 
-        let relative_default_addr_dir = default_addr_dir
-            .context(NoDefaultAddressSnafu)?
-            .strip_prefix(target_path)
-            .context(DefaultAddrIsOutsideRootSnafu)?
-            .to_owned();
-        let default_addr_dir_link = target_path.join("default-address");
-        // Check if the symlink needs to be updated
-        if tokio::fs::read_link(&default_addr_dir_link)
-            .await
-            .ok()
-            .as_ref()
-            != Some(&relative_default_addr_dir)
-        {
-            // Remove any existing symlink because `tokio::fs::symlink` fails if it already exists.
-            // This happens if the node was restarted. The pod then restarts with the same UID and
-            // the pre-populated volume.
-            let _ = tokio::fs::remove_file(&default_addr_dir_link).await;
-            tokio::fs::symlink(relative_default_addr_dir, &default_addr_dir_link).await?;
-        }
-        Ok(())
+        // No other volumes, or the PodListeners is already gone
+        assert!(!mounted_pod_label_still_used(&unpublished, None, &[]));
+        assert!(!mounted_pod_label_still_used(
+            &unpublished,
+            Some(&pod_listeners(&["a"])),
+            &[]
+        ));
+        // Another volume uses a different Listener
+        assert!(!mounted_pod_label_still_used(
+            &unpublished,
+            Some(&pod_listeners(&["a", "b"])),
+            &[volume("b", "other")]
+        ));
+        // Another volume uses the same Listener
+        assert!(mounted_pod_label_still_used(
+            &unpublished,
+            Some(&pod_listeners(&["a", "b"])),
+            &[volume("b", "shared")]
+        ));
+        // Another volume's Listener is unknown
+        assert!(mounted_pod_label_still_used(
+            &unpublished,
+            Some(&pod_listeners(&["a", "b"])),
+            &[]
+        ));
+    }
+
+    #[test]
+    fn inline_pod_volume_name_from_kubelet_target_path() {
+        let pod = Pod {
+            metadata: ObjectMeta {
+                name: Some("my-pod".to_string()),
+                ..Default::default()
+            },
+            spec: Some(stackable_operator::k8s_openapi::api::core::v1::PodSpec {
+                volumes: Some(vec![
+                    Volume {
+                        name: "listener".to_string(),
+                        csi: Some(
+                            stackable_operator::k8s_openapi::api::core::v1::CSIVolumeSource {
+                                driver: OPERATOR_KEY.to_string(),
+                                ..Default::default()
+                            },
+                        ),
+                        ..Default::default()
+                    },
+                    Volume {
+                        name: "other".to_string(),
+                        csi: Some(
+                            stackable_operator::k8s_openapi::api::core::v1::CSIVolumeSource {
+                                driver: "secrets.stackable.tech".to_string(),
+                                ..Default::default()
+                            },
+                        ),
+                        ..Default::default()
+                    },
+                ]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let target_path = |volume_name: &str| {
+            PathBuf::from(format!(
+                "/var/lib/kubelet/pods/1234/volumes/kubernetes.io~csi/{volume_name}/mount"
+            ))
+        };
+        assert_eq!(
+            inline_listener_pod_volume_name(&pod, &target_path("listener")).unwrap(),
+            "listener"
+        );
+        // Volumes of other drivers must not be mistaken for listener volumes
+        assert!(inline_listener_pod_volume_name(&pod, &target_path("other")).is_err());
+        assert!(inline_listener_pod_volume_name(&pod, &target_path("missing")).is_err());
     }
 }
@@ -0,0 +1,105 @@
+//! Manages the tmpfs mounts that back listener volumes.
+//!
+//! All functions in this module block, and should be called using [`tokio::task::spawn_blocking`].
+
+use std::{
+    ffi::{CStr, CString},
+    fs, io,
+    mem::MaybeUninit,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+/// The maximum size of a listener volume's tmpfs.
+///
+/// Payloads are written next to the previous payload before it is removed, so this must fit two copies of the
+/// volume's contents (including rendered templates).
+const TMPFS_OPTIONS: &CStr = c"size=4m,mode=0755";
+
+/// Flags that are applied to all listener volume mounts, since they never contain anything executable.
+const MOUNT_FLAGS: libc::c_ulong = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Mounts an empty tmpfs at `path`, which must be an existing directory.
+pub fn mount_tmpfs(path: &Path) -> io::Result<()> {
+    let path = c_path(path)?;
+    check(unsafe {
+        libc::mount(
+            c"tmpfs".as_ptr(),
+            path.as_ptr(),
+            c"tmpfs".as_ptr(),
+            MOUNT_FLAGS,
+            TMPFS_OPTIONS.as_ptr().cast(),
+        )
+    })
+}
+
+/// Remounts the mount at `path` as read-only or read-write, keeping its contents.
+pub fn remount(path: &Path, read_only: bool) -> io::Result<()> {
+    let path = c_path(path)?;
+    let read_only_flag = if read_only { libc::MS_RDONLY } else { 0 };
+    check(unsafe {
+        libc::mount(
+            std::ptr::null(),
+            path.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REMOUNT | MOUNT_FLAGS | read_only_flag,
+            std::ptr::null(),
+        )
+    })
+}
+
+/// Unmounts the mount at `path`.
+///
+/// The mount is detached immediately, even if it is still in use.
+pub fn unmount(path: &Path) -> io::Result<()> {
+    let path = c_path(path)?;
+    check(unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) })
+}
+
+/// Whether `path` is the root of a mount, rather than a plain directory in its parent's filesystem.
+pub fn is_mount_point(path: &Path) -> io::Result<bool> {
+    let Some(parent) = path.parent() else {
+        // The root directory is always a mount point
+        return Ok(true);
+    };
+    Ok(fs::symlink_metadata(path)?.dev() != fs::metadata(parent)?.dev())
+}
+
+/// Whether the filesystem that contains `path` is mounted read-only.
+pub fn is_read_only(path: &Path) -> io::Result<bool> {
+    Ok(statvfs(path)?.f_flag & libc::ST_RDONLY != 0)
+}
+
+/// Gets the statistics of the filesystem that contains `path`.
+pub fn statvfs(path: &Path) -> io::Result<libc::statvfs> {
+    let path = c_path(path)?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    check(unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) })?;
+    // statvfs initializes the struct on success
+    Ok(unsafe { stat.assume_init() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_mount_points() {
+        assert!(is_mount_point(Path::new("/")).unwrap());
+        assert!(is_mount_point(Path::new("/proc")).unwrap());
+        assert!(!is_mount_point(Path::new("/proc/self")).unwrap());
+        assert!(is_mount_point(Path::new("/does-not-exist")).is_err());
+    }
+}
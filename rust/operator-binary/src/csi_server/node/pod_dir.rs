@@ -0,0 +1,964 @@
+//! Writes the listener information (the [downwards API](https://docs.stackable.tech/home/stable/listener-operator/volume#downwards-api))
+//! into a listener volume.
+//!
+//! The contents are written atomically, similar to Kubernetes' `AtomicWriter` (which is used for ConfigMap and Secret
+//! volumes), so that readers never see a partially written or mixed tree:
+//!
+//! ```text
+//! <target_path>
+//! ├── ..1767225600.000000000           (the current payload)
+//! │   ├── addresses/<address>/...
+//! │   └── default-address -> addresses/<address>
+//! ├── ..data -> ..1767225600.000000000 (swapped atomically whenever the payload changes)
+//! ├── addresses -> ..data/addresses
+//! ├── default-address -> ..data/default-address
+//! ├── listener.{json,yaml,env} -> ..data/listener.{json,yaml,env}
+//! └── templates -> ..data/templates    (only if the volume defines any templates)
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs, io,
+    os::unix::fs::{PermissionsExt, symlink},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+use stackable_operator::crd::listener;
+
+use super::mount;
+
+/// Records which objects the volume was published for, see [`VolumeMetadata`].
+///
+/// This is kept outside of the payload, so that it is not replaced when the payload is rewritten.
+const METADATA_FILE: &str = "..listener-volume.json";
+
+/// The link to the payload directory that is currently active.
+const DATA_LINK: &str = "..data";
+/// The name that [`DATA_LINK`] is prepared under, before being renamed into place.
+const DATA_LINK_TMP: &str = "..data_tmp";
+
+/// The mode of directories in read-only volumes.
+const READ_ONLY_DIR_MODE: u32 = 0o555;
+/// The mode of files in read-only volumes.
+const READ_ONLY_FILE_MODE: u32 = 0o444;
+/// The mode that directories are restored to before they are modified.
+const WRITABLE_DIR_MODE: u32 = 0o755;
+
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("failed to write {path:?}"))]
+    WriteContent { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to swap payload into place"))]
+    SwapPayload { source: io::Error },
+
+    #[snafu(display("failed to clean up {path:?}"))]
+    CleanUp { source: io::Error, path: PathBuf },
+
+    #[snafu(display("listener has no address yet"))]
+    NoDefaultAddress,
+
+    #[snafu(display("address {address:?} is not a valid file name"))]
+    InvalidAddress { address: String },
+
+    #[snafu(display("failed to serialize listener summary as JSON"))]
+    SerializeSummaryJson { source: serde_json::Error },
+
+    #[snafu(display("failed to serialize listener summary as YAML"))]
+    SerializeSummaryYaml { source: serde_yaml::Error },
+
+    #[snafu(display("failed to serialize volume metadata"))]
+    SerializeMetadata { source: serde_json::Error },
+
+    #[snafu(display("failed to read volume metadata from {path:?}"))]
+    ReadMetadata { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to parse volume metadata from {path:?}"))]
+    ParseMetadata {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("failed to read listener summary from {path:?}"))]
+    ReadSummary { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to parse listener summary from {path:?}"))]
+    ParseSummary {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("failed to get filesystem statistics for {path:?}"))]
+    StatFilesystem { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to check whether {path:?} is mounted"))]
+    CheckMount { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to remount {path:?}"))]
+    Remount { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to unmount {path:?}"))]
+    Unmount { source: io::Error, path: PathBuf },
+
+    #[snafu(display("failed to search {path:?} for published volumes"))]
+    FindVolumes { source: io::Error, path: PathBuf },
+
+    #[snafu(display("blocking filesystem task failed"))]
+    BlockingTaskFailed { source: tokio::task::JoinError },
+}
+
+/// An entry in a [`Payload`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    File(Vec<u8>),
+    Symlink(PathBuf),
+    /// An empty directory (parent directories of other entries are created implicitly).
+    Directory,
+}
+
+/// The contents of a listener volume, keyed by their path relative to the volume root.
+pub type Payload = BTreeMap<PathBuf, Entry>;
+
+/// The Listener that a volume is bound to, and the addresses that it exposes to the Pod.
+#[derive(Debug)]
+pub struct ListenerInfo<'a> {
+    pub name: &'a str,
+    pub namespace: &'a str,
+    pub class_name: Option<&'a str>,
+    pub scope: listener::v1alpha1::PodListenerScope,
+    pub addresses: &'a [listener::v1alpha1::ListenerIngress],
+}
+
+impl ListenerInfo<'_> {
+    /// The name of [`Self::scope`], as used in the summary files.
+    pub fn scope_name(&self) -> &'static str {
+        match self.scope {
+            listener::v1alpha1::PodListenerScope::Node => "Node",
+            listener::v1alpha1::PodListenerScope::Cluster => "Cluster",
+        }
+    }
+}
+
+/// The name of `address_type`, as used in the summary files.
+pub fn address_type_name(address_type: listener::v1alpha1::AddressType) -> &'static str {
+    match address_type {
+        listener::v1alpha1::AddressType::Ip => "IP",
+        listener::v1alpha1::AddressType::Hostname => "Hostname",
+    }
+}
+
+/// The contents of the `listener.json` and `listener.yaml` summary files.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListenerSummary<'a> {
+    name: &'a str,
+    namespace: &'a str,
+    class_name: Option<&'a str>,
+    scope: &'a listener::v1alpha1::PodListenerScope,
+    addresses: &'a [listener::v1alpha1::ListenerIngress],
+    default_address: &'a listener::v1alpha1::ListenerIngress,
+}
+
+/// Writes the listener information for `info` into the volume at `target_path`.
+///
+/// `rendered_templates` are written into the `templates/` directory, keyed by file name.
+/// If `read_only` is set, then the volume's contents are made read-only.
+pub async fn write_listener_info_to_pod_dir(
+    target_path: &Path,
+    info: &ListenerInfo<'_>,
+    rendered_templates: &BTreeMap<String, String>,
+    read_only: bool,
+) -> Result<(), Error> {
+    write_payload(
+        target_path,
+        listener_info_payload(info, rendered_templates)?,
+        read_only,
+    )
+    .await
+}
+
+/// Builds the `addresses/` tree, the `default-address` link, the summary files, and the `templates/` directory for
+/// `info`.
+pub fn listener_info_payload(
+    info: &ListenerInfo,
+    rendered_templates: &BTreeMap<String, String>,
+) -> Result<Payload, Error> {
+    let mut payload = Payload::new();
+    let addrs_path = Path::new("addresses");
+    for addr in info.addresses {
+        // Addresses are used as directory names, so they must not be able to escape the addresses directory
+        if addr.address.is_empty() || addr.address.contains('/') || addr.address.starts_with('.') {
+            return InvalidAddressSnafu {
+                address: &addr.address,
+            }
+            .fail();
+        }
+        let addr_dir = addrs_path.join(&addr.address);
+        let ports_dir = addr_dir.join("ports");
+        payload.insert(
+            addr_dir.join("address"),
+            Entry::File(addr.address.clone().into_bytes()),
+        );
+        payload.insert(ports_dir.clone(), Entry::Directory);
+        for (port_name, port) in &addr.ports {
+            payload.insert(
+                ports_dir.join(port_name),
+                Entry::File(port.to_string().into_bytes()),
+            );
+        }
+    }
+    let default_addr = info.addresses.first().context(NoDefaultAddressSnafu)?;
+    payload.insert(
+        PathBuf::from("default-address"),
+        Entry::Symlink(addrs_path.join(&default_addr.address)),
+    );
+
+    let summary = ListenerSummary {
+        name: info.name,
+        namespace: info.namespace,
+        class_name: info.class_name,
+        scope: &info.scope,
+        addresses: info.addresses,
+        default_address: default_addr,
+    };
+    let mut summary_json =
+        serde_json::to_vec_pretty(&summary).context(SerializeSummaryJsonSnafu)?;
+    summary_json.push(b'\n');
+    payload.insert(PathBuf::from("listener.json"), Entry::File(summary_json));
+    payload.insert(
+        PathBuf::from("listener.yaml"),
+        Entry::File(
+            serde_yaml::to_string(&summary)
+                .context(SerializeSummaryYamlSnafu)?
+                .into_bytes(),
+        ),
+    );
+    payload.insert(
+        PathBuf::from("listener.env"),
+        Entry::File(listener_env(info, default_addr).into_bytes()),
+    );
+    for (file_name, contents) in rendered_templates {
+        payload.insert(
+            Path::new("templates").join(file_name),
+            Entry::File(contents.clone().into_bytes()),
+        );
+    }
+    Ok(payload)
+}
+
+/// The listener information that was last written into a volume, as recorded in its `listener.json` summary file.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishedListener {
+    pub name: String,
+    pub namespace: String,
+    pub addresses: Vec<listener::v1alpha1::ListenerIngress>,
+}
+
+/// Reads the listener information that was last written into the volume at `target_path`.
+///
+/// Returns [`None`] if the volume doesn't contain any listener information (yet).
+pub async fn read_published_listener(
+    target_path: &Path,
+) -> Result<Option<PublishedListener>, Error> {
+    let path = target_path.join("listener.json");
+    match tokio::fs::read(&path).await {
+        Ok(summary) => Ok(Some(
+            serde_json::from_slice(&summary).context(ParseSummarySnafu { path })?,
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(ReadSummarySnafu { path }),
+    }
+}
+
+/// The capacity and usage of a volume's own filesystem.
+#[derive(Debug)]
+pub struct FilesystemUsage {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub used_bytes: u64,
+    pub total_inodes: u64,
+    pub available_inodes: u64,
+    pub used_inodes: u64,
+}
+
+/// Gets the capacity and usage of the volume at `target_path`.
+///
+/// Returns [`None`] unless the volume is backed by its own mount (such as a tmpfs), since a plain directory would
+/// report the capacity and usage of the Node's disk.
+pub async fn filesystem_usage(target_path: &Path) -> Result<Option<FilesystemUsage>, Error> {
+    let target_path = target_path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        if !mount::is_mount_point(&target_path).context(CheckMountSnafu { path: &target_path })? {
+            return Ok(None);
+        }
+        statvfs(&target_path)
+            .map(Some)
+            .context(StatFilesystemSnafu { path: &target_path })
+    })
+    .await
+    .context(BlockingTaskFailedSnafu)?
+}
+
+fn statvfs(path: &Path) -> io::Result<FilesystemUsage> {
+    let stat = mount::statvfs(path)?;
+    let fragment_size = u64::from(stat.f_frsize);
+    let (blocks, free_blocks) = (u64::from(stat.f_blocks), u64::from(stat.f_bfree));
+    let (inodes, free_inodes) = (u64::from(stat.f_files), u64::from(stat.f_ffree));
+    Ok(FilesystemUsage {
+        total_bytes: blocks * fragment_size,
+        available_bytes: u64::from(stat.f_bavail) * fragment_size,
+        used_bytes: blocks.saturating_sub(free_blocks) * fragment_size,
+        total_inodes: inodes,
+        available_inodes: u64::from(stat.f_favail),
+        used_inodes: inodes.saturating_sub(free_inodes),
+    })
+}
+
+/// Renders a shell-sourceable file that exports the most commonly used listener information.
+///
+/// The ports of the default address are exported as `LISTENER_PORT_<NAME>`, where `<NAME>` is the uppercased port name
+/// with all other characters replaced by underscores.
+fn listener_env(info: &ListenerInfo, default_addr: &listener::v1alpha1::ListenerIngress) -> String {
+    let mut vars = vec![
+        ("LISTENER_NAME".to_string(), info.name.to_string()),
+        ("LISTENER_NAMESPACE".to_string(), info.namespace.to_string()),
+        (
+            "LISTENER_CLASS".to_string(),
+            info.class_name.unwrap_or_default().to_string(),
+        ),
+        ("LISTENER_SCOPE".to_string(), info.scope_name().to_string()),
+        (
+            "LISTENER_DEFAULT_ADDRESS".to_string(),
+            default_addr.address.clone(),
+        ),
+        (
+            "LISTENER_DEFAULT_ADDRESS_TYPE".to_string(),
+            address_type_name(default_addr.address_type).to_string(),
+        ),
+    ];
+    vars.extend(default_addr.ports.iter().map(|(port_name, port)| {
+        let port_name = port_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        (format!("LISTENER_PORT_{port_name}"), port.to_string())
+    }));
+    vars.into_iter()
+        .map(|(name, value)| {
+            // Single-quote all values, so that they are never interpreted by the shell
+            format!("export {name}='{}'\n", value.replace('\'', r"'\''"))
+        })
+        .collect()
+}
+
+/// The objects that a volume was published for, which must be cleaned up when it is unpublished.
+///
+/// This is persisted in the volume, since unpublish requests do not carry the volume context.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeMetadata {
+    pub pod_namespace: String,
+    pub pod_name: String,
+    pub pod_uid: String,
+    /// The name of the Pod's volume, which is used as the key in the Pod's [`listener::v1alpha1::PodListeners`].
+    pub pod_volume_name: String,
+    /// The label that marks the Pod as having mounted the Listener.
+    pub mounted_pod_label: String,
+    /// The user-defined templates of the volume, so that they can be re-rendered after the node driver restarts.
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+    /// Whether the volume was published read-only.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Records `metadata` for the volume at `target_path`.
+pub async fn write_volume_metadata(
+    target_path: &Path,
+    metadata: &VolumeMetadata,
+) -> Result<(), Error> {
+    tokio::fs::create_dir_all(target_path)
+        .await
+        .context(WriteContentSnafu { path: target_path })?;
+    let path = target_path.join(METADATA_FILE);
+    let tmp_path = target_path.join(format!("{METADATA_FILE}.tmp"));
+    let metadata = serde_json::to_vec(metadata).context(SerializeMetadataSnafu)?;
+    tokio::fs::write(&tmp_path, metadata)
+        .await
+        .context(WriteContentSnafu { path: &tmp_path })?;
+    tokio::fs::rename(&tmp_path, &path)
+        .await
+        .context(WriteContentSnafu { path })
+}
+
+/// Reads the metadata recorded for the volume at `target_path`.
+///
+/// Returns [`None`] if the volume was published by a version that did not record any metadata.
+pub async fn read_volume_metadata(target_path: &Path) -> Result<Option<VolumeMetadata>, Error> {
+    let path = target_path.join(METADATA_FILE);
+    match tokio::fs::read(&path).await {
+        Ok(metadata) => Ok(Some(
+            serde_json::from_slice(&metadata).context(ParseMetadataSnafu { path })?,
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(ReadMetadataSnafu { path }),
+    }
+}
+
+/// Finds the target paths of all volumes below `kubelet_dir` that have recorded [`VolumeMetadata`], and have
+/// therefore been published by this driver.
+///
+/// This relies on kubelet's layout of CSI volume target paths, which is
+/// `<kubelet_dir>/pods/<pod uid>/volumes/kubernetes.io~csi/<volume name>/mount`.
+pub async fn find_published_volumes(kubelet_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let pods_dir = kubelet_dir.join("pods");
+    tokio::task::spawn_blocking(move || {
+        let mut target_paths = Vec::new();
+        for pod_dir in read_dir_if_exists(&pods_dir)? {
+            for volume_dir in read_dir_if_exists(&pod_dir.join("volumes/kubernetes.io~csi"))? {
+                let target_path = volume_dir.join("mount");
+                if target_path.join(METADATA_FILE).is_file() {
+                    target_paths.push(target_path);
+                }
+            }
+        }
+        Ok(target_paths)
+    })
+    .await
+    .context(BlockingTaskFailedSnafu)?
+}
+
+/// Lists the entries of the directory at `path`, or nothing if it doesn't exist.
+fn read_dir_if_exists(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context(FindVolumesSnafu { path }),
+    };
+    entries
+        .map(|entry| Ok(entry.context(FindVolumesSnafu { path })?.path()))
+        .collect()
+}
+
+/// Reads the metadata of the Pod's other volumes that have been published by this driver, next to the volume at
+/// `target_path`.
+///
+/// This relies on kubelet's layout of CSI volume target paths, see [`find_published_volumes`].
+pub async fn read_other_volume_metadata(target_path: &Path) -> Result<Vec<VolumeMetadata>, Error> {
+    let Some(volumes_dir) = target_path.parent().and_then(Path::parent) else {
+        return Ok(Vec::new());
+    };
+    let volumes_dir = volumes_dir.to_owned();
+    let volume_dirs = tokio::task::spawn_blocking(move || read_dir_if_exists(&volumes_dir))
+        .await
+        .context(BlockingTaskFailedSnafu)??;
+    let mut other_volumes = Vec::new();
+    for volume_dir in volume_dirs {
+        let other_target_path = volume_dir.join("mount");
+        if other_target_path != target_path
+            && let Some(metadata) = read_volume_metadata(&other_target_path).await?
+        {
+            other_volumes.push(metadata);
+        }
+    }
+    Ok(other_volumes)
+}
+
+/// Prepares the volume at `target_path` to be written to.
+///
+/// If `tmpfs` is set, then a tmpfs is mounted at `target_path`. If that fails (such as when the node driver is not
+/// privileged), then the volume falls back to being a plain directory on the Node's disk.
+///
+/// Volumes that were made read-only by a previous publish are made writable again, so that their metadata can be
+/// rewritten.
+pub async fn prepare_target_path(target_path: &Path, tmpfs: bool) -> Result<(), Error> {
+    let target_path = target_path.to_owned();
+    tokio::task::spawn_blocking(move || prepare_target_path_blocking(&target_path, tmpfs))
+        .await
+        .context(BlockingTaskFailedSnafu)?
+}
+
+fn prepare_target_path_blocking(target_path: &Path, tmpfs: bool) -> Result<(), Error> {
+    fs::create_dir_all(target_path).context(WriteContentSnafu { path: target_path })?;
+    if mount::is_mount_point(target_path).context(CheckMountSnafu { path: target_path })? {
+        // Already mounted by a previous attempt to publish the volume
+        make_mount_writable(target_path)?;
+    } else if tmpfs && let Err(err) = mount::mount_tmpfs(target_path) {
+        tracing::warn!(
+            ?target_path,
+            error = &err as &dyn std::error::Error,
+            "failed to mount tmpfs for listener volume, falling back to a plain directory"
+        );
+    }
+    set_mode(target_path, WRITABLE_DIR_MODE).context(WriteContentSnafu { path: target_path })
+}
+
+/// Remounts the volume at `target_path` as read-write, if it is a read-only mount.
+fn make_mount_writable(target_path: &Path) -> Result<(), Error> {
+    if mount::is_mount_point(target_path).context(CheckMountSnafu { path: target_path })?
+        && mount::is_read_only(target_path).context(CheckMountSnafu { path: target_path })?
+    {
+        mount::remount(target_path, false).context(RemountSnafu { path: target_path })?;
+    }
+    Ok(())
+}
+
+/// Removes the volume at `target_path`, unmounting it if required.
+pub async fn remove_target_path(target_path: &Path) -> Result<(), Error> {
+    let target_path = target_path.to_owned();
+    tokio::task::spawn_blocking(move || remove_target_path_blocking(&target_path))
+        .await
+        .context(BlockingTaskFailedSnafu)?
+}
+
+fn remove_target_path_blocking(target_path: &Path) -> Result<(), Error> {
+    match mount::is_mount_point(target_path) {
+        Ok(true) => mount::unmount(target_path).context(UnmountSnafu { path: target_path })?,
+        Ok(false) => {}
+        // already deleted => nothing to do
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).context(CheckMountSnafu { path: target_path }),
+    }
+    remove_if_exists(target_path)
+}
+
+/// Atomically replaces the contents of the volume at `target_path` with `payload`.
+///
+/// If `read_only` is set, then the volume's contents are made read-only.
+pub async fn write_payload(
+    target_path: &Path,
+    payload: Payload,
+    read_only: bool,
+) -> Result<(), Error> {
+    let target_path = target_path.to_owned();
+    tokio::task::spawn_blocking(move || write_payload_blocking(&target_path, &payload, read_only))
+        .await
+        .context(BlockingTaskFailedSnafu)?
+}
+
+fn write_payload_blocking(
+    target_path: &Path,
+    payload: &Payload,
+    read_only: bool,
+) -> Result<(), Error> {
+    fs::create_dir_all(target_path).context(WriteContentSnafu { path: target_path })?;
+    // The volume may have been made read-only by a previous write
+    make_mount_writable(target_path)?;
+    set_mode(target_path, WRITABLE_DIR_MODE).context(WriteContentSnafu { path: target_path })?;
+
+    // Write the new payload into a fresh directory, which is not visible to readers yet
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let data_dir_name = format!("..{}.{:09}", timestamp.as_secs(), timestamp.subsec_nanos());
+    let data_dir = target_path.join(&data_dir_name);
+    fs::create_dir(&data_dir).context(WriteContentSnafu { path: &data_dir })?;
+    for (path, entry) in payload {
+        let path = data_dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(WriteContentSnafu { path: parent })?;
+        }
+        match entry {
+            Entry::File(contents) => fs::write(&path, contents),
+            Entry::Symlink(target) => symlink(target, &path),
+            Entry::Directory => fs::create_dir_all(&path),
+        }
+        .context(WriteContentSnafu { path: &path })?;
+    }
+    // The payload must be sealed before it becomes visible, since the workload could otherwise modify it in the meantime
+    if read_only {
+        make_read_only(&data_dir)?;
+    }
+
+    // Atomically switch readers over to the new payload
+    let data_link_tmp = target_path.join(DATA_LINK_TMP);
+    remove_if_exists(&data_link_tmp)?;
+    symlink(&data_dir_name, &data_link_tmp).context(SwapPayloadSnafu)?;
+    fs::rename(&data_link_tmp, target_path.join(DATA_LINK)).context(SwapPayloadSnafu)?;
+
+    // Make sure that all top-level entries are available via stable links
+    let top_level_names = payload
+        .keys()
+        .filter_map(|path| path.iter().next())
+        .collect::<Vec<_>>();
+    for name in &top_level_names {
+        let link_path = target_path.join(name);
+        let link_target = Path::new(DATA_LINK).join(name);
+        if fs::read_link(&link_path).ok().as_ref() != Some(&link_target) {
+            // Volumes written by older versions contain plain directories rather than links
+            remove_if_exists(&link_path)?;
+            symlink(&link_target, &link_path).context(WriteContentSnafu { path: &link_path })?;
+        }
+    }
+
+    // Remove previous payloads, and links to entries that are no longer part of the payload
+    for entry in fs::read_dir(target_path).context(CleanUpSnafu { path: target_path })? {
+        let entry = entry.context(CleanUpSnafu { path: target_path })?;
+        let name = entry.file_name();
+        let path = entry.path();
+        let is_stale_payload = is_payload_dir_name(&name) && name != *data_dir_name;
+        let is_stale_link = !top_level_names.contains(&name.as_os_str())
+            && fs::read_link(&path).is_ok_and(|target| target.starts_with(DATA_LINK));
+        if is_stale_payload || is_stale_link {
+            remove_if_exists(&path)?;
+        }
+    }
+
+    if read_only {
+        set_mode(target_path, READ_ONLY_DIR_MODE)
+            .context(WriteContentSnafu { path: target_path })?;
+        if mount::is_mount_point(target_path).context(CheckMountSnafu { path: target_path })? {
+            mount::remount(target_path, true).context(RemountSnafu { path: target_path })?;
+        }
+    }
+    Ok(())
+}
+
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// Recursively makes the files and directories in `path` read-only (symlinks are left alone).
+fn make_read_only(path: &Path) -> Result<(), Error> {
+    let meta = fs::symlink_metadata(path).context(WriteContentSnafu { path })?;
+    if meta.is_dir() {
+        for entry in fs::read_dir(path).context(WriteContentSnafu { path })? {
+            make_read_only(&entry.context(WriteContentSnafu { path })?.path())?;
+        }
+        set_mode(path, READ_ONLY_DIR_MODE).context(WriteContentSnafu { path })
+    } else if meta.is_file() {
+        set_mode(path, READ_ONLY_FILE_MODE).context(WriteContentSnafu { path })
+    } else {
+        Ok(())
+    }
+}
+
+/// Recursively makes the directories in `path` writable again, so that their contents can be removed.
+fn make_dirs_writable(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        set_mode(path, WRITABLE_DIR_MODE)?;
+        for entry in fs::read_dir(path)? {
+            make_dirs_writable(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is a payload directory created by [`write_payload`].
+fn is_payload_dir_name(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| {
+        name.strip_prefix("..")
+            .is_some_and(|ts| ts.starts_with(|c: char| c.is_ascii_digit()))
+    })
+}
+
+/// Removes whatever is at `path` (without following symlinks).
+fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    let result = match fs::symlink_metadata(path) {
+        // Read-only payloads must be made writable again before they can be removed
+        Ok(meta) if meta.is_dir() => {
+            make_dirs_writable(path).and_then(|()| fs::remove_dir_all(path))
+        }
+        Ok(_) => fs::remove_file(path),
+        Err(err) => Err(err),
+    };
+    match result {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(err).context(CleanUpSnafu { path })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "listener-operator-pod-dir-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ingress(address: &str, port: i32) -> listener::v1alpha1::ListenerIngress {
+        listener::v1alpha1::ListenerIngress {
+            address: address.to_string(),
+            address_type: listener::v1alpha1::AddressType::Ip,
+            ports: [("http".to_string(), port)].into(),
+        }
+    }
+
+    fn info(addresses: &[listener::v1alpha1::ListenerIngress]) -> ListenerInfo<'_> {
+        ListenerInfo {
+            name: "my-listener",
+            namespace: "default",
+            class_name: Some("external-stable"),
+            scope: listener::v1alpha1::PodListenerScope::Node,
+            addresses,
+        }
+    }
+
+    fn payload(addresses: &[listener::v1alpha1::ListenerIngress]) -> Result<Payload, Error> {
+        listener_info_payload(&info(addresses), &BTreeMap::new())
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut entries = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn write_listener_info() {
+        let dir = temp_dir();
+        let payload = payload(&[ingress("10.0.0.1", 8080), ingress("10.0.0.2", 8080)]).unwrap();
+        write_payload_blocking(&dir, &payload, false).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("default-address/address")).unwrap(),
+            "10.0.0.1"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("addresses/10.0.0.2/ports/http")).unwrap(),
+            "8080"
+        );
+        assert_eq!(
+            fs::read_link(dir.join("addresses")).unwrap(),
+            Path::new("..data/addresses")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rewrite_removes_stale_addresses() {
+        let dir = temp_dir();
+        write_payload_blocking(&dir, &payload(&[ingress("10.0.0.1", 8080)]).unwrap(), false)
+            .unwrap();
+        write_payload_blocking(&dir, &payload(&[ingress("10.0.0.2", 9090)]).unwrap(), false)
+            .unwrap();
+
+        assert_eq!(entries(&dir.join("addresses")), ["10.0.0.2"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("default-address/ports/http")).unwrap(),
+            "9090"
+        );
+        // Only the current payload should be kept
+        assert_eq!(
+            entries(&dir).iter().filter(|e| e.starts_with("..")).count(),
+            2
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rewrite_replaces_legacy_layout() {
+        let dir = temp_dir();
+        fs::create_dir_all(dir.join("addresses/10.0.0.1/ports")).unwrap();
+        fs::write(dir.join("addresses/10.0.0.1/address"), "10.0.0.1").unwrap();
+        symlink("addresses/10.0.0.1", dir.join("default-address")).unwrap();
+
+        write_payload_blocking(&dir, &payload(&[ingress("10.0.0.2", 8080)]).unwrap(), false)
+            .unwrap();
+
+        assert_eq!(entries(&dir.join("addresses")), ["10.0.0.2"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("default-address/address")).unwrap(),
+            "10.0.0.2"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_only_volume() {
+        let dir = temp_dir();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        write_payload_blocking(&dir, &payload(&[ingress("10.0.0.1", 8080)]).unwrap(), true)
+            .unwrap();
+        assert_eq!(mode(&dir), READ_ONLY_DIR_MODE);
+        assert_eq!(
+            mode(&dir.join("addresses/10.0.0.1/ports")),
+            READ_ONLY_DIR_MODE
+        );
+        assert_eq!(
+            mode(&dir.join("default-address/ports/http")),
+            READ_ONLY_FILE_MODE
+        );
+
+        // Read-only volumes must still be updatable
+        write_payload_blocking(&dir, &payload(&[ingress("10.0.0.2", 8080)]).unwrap(), true)
+            .unwrap();
+        assert_eq!(entries(&dir.join("addresses")), ["10.0.0.2"]);
+        assert_eq!(mode(&dir), READ_ONLY_DIR_MODE);
+
+        make_dirs_writable(&dir).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_addresses() {
+        assert!(payload(&[]).is_err());
+        assert!(payload(&[ingress("../etc", 8080)]).is_err());
+        assert!(payload(&[ingress("a/b", 8080)]).is_err());
+    }
+
+    #[test]
+    fn summary_files() {
+        let mut addresses = vec![ingress("10.0.0.1", 8080), ingress("10.0.0.2", 8081)];
+        addresses[0].ports.insert("metrics-http".to_string(), 9100);
+        let payload = payload(&addresses).unwrap();
+
+        let Some(Entry::File(summary)) = payload.get(Path::new("listener.json")) else {
+            panic!("listener.json is missing");
+        };
+        let summary = serde_json::from_slice::<serde_json::Value>(summary).unwrap();
+        assert_eq!(summary["name"], "my-listener");
+        assert_eq!(summary["className"], "external-stable");
+        assert_eq!(summary["scope"], "Node");
+        assert_eq!(summary["addresses"][1]["ports"]["http"], 8081);
+        assert_eq!(summary["defaultAddress"]["address"], "10.0.0.1");
+        assert!(payload.contains_key(Path::new("listener.yaml")));
+
+        let Some(Entry::File(env)) = payload.get(Path::new("listener.env")) else {
+            panic!("listener.env is missing");
+        };
+        assert_eq!(
+            String::from_utf8_lossy(env),
+            "export LISTENER_NAME='my-listener'
+export LISTENER_NAMESPACE='default'
+export LISTENER_CLASS='external-stable'
+export LISTENER_SCOPE='Node'
+export LISTENER_DEFAULT_ADDRESS='10.0.0.1'
+export LISTENER_DEFAULT_ADDRESS_TYPE='IP'
+export LISTENER_PORT_HTTP='8080'
+export LISTENER_PORT_METRICS_HTTP='9100'
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn read_published_listener_summary() {
+        let dir = temp_dir();
+        assert!(read_published_listener(&dir).await.unwrap().is_none());
+
+        let addresses = [ingress("10.0.0.1", 8080), ingress("10.0.0.2", 8081)];
+        write_payload_blocking(&dir, &payload(&addresses).unwrap(), false).unwrap();
+        let published = read_published_listener(&dir).await.unwrap().unwrap();
+        assert_eq!(published.name, "my-listener");
+        assert_eq!(published.namespace, "default");
+        assert_eq!(
+            published
+                .addresses
+                .iter()
+                .map(|addr| (addr.address.as_str(), addr.ports["http"]))
+                .collect::<Vec<_>>(),
+            [("10.0.0.1", 8080), ("10.0.0.2", 8081)]
+        );
+
+        // Plain directories share the usage of the Node's disk, which must not be reported as the volume's
+        assert!(filesystem_usage(&dir).await.unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn volume_metadata_survives_rewrites() {
+        let dir = temp_dir();
+        assert_eq!(read_volume_metadata(&dir).await.unwrap(), None);
+
+        let metadata = VolumeMetadata {
+            pod_namespace: "default".to_string(),
+            pod_name: "my-pod".to_string(),
+            pod_uid: "1234".to_string(),
+            pod_volume_name: "listener".to_string(),
+            mounted_pod_label: "listener.stackable.tech/mnt.1234".to_string(),
+            templates: [("greeting".to_string(), "hello".to_string())].into(),
+            read_only: true,
+        };
+        write_volume_metadata(&dir, &metadata).await.unwrap();
+        write_payload_blocking(&dir, &payload(&[ingress("10.0.0.1", 8080)]).unwrap(), false)
+            .unwrap();
+        assert_eq!(read_volume_metadata(&dir).await.unwrap(), Some(metadata));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn find_volumes_in_kubelet_dir() {
+        let dir = temp_dir();
+        let volume_dir = |pod_uid: &str, volume: &str| {
+            dir.join("pods")
+                .join(pod_uid)
+                .join("volumes/kubernetes.io~csi")
+                .join(volume)
+                .join("mount")
+        };
+        let published = volume_dir("1234", "pvc-1");
+        fs::create_dir_all(&published).unwrap();
+        fs::write(published.join(METADATA_FILE), "{}").unwrap();
+        // Volumes of other drivers (or that are still being published) don't have any metadata
+        fs::create_dir_all(volume_dir("1234", "pvc-2")).unwrap();
+        fs::create_dir_all(dir.join("pods/5678/volumes")).unwrap();
+
+        assert_eq!(find_published_volumes(&dir).await.unwrap(), [published]);
+        assert_eq!(
+            find_published_volumes(&dir.join("missing")).await.unwrap(),
+            Vec::<PathBuf>::new()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn republish_read_only_volume() {
+        let dir = temp_dir();
+        let metadata = VolumeMetadata {
+            pod_namespace: "default".to_string(),
+            pod_name: "my-pod".to_string(),
+            pod_uid: "1234".to_string(),
+            pod_volume_name: "listener".to_string(),
+            mounted_pod_label: "listener.stackable.tech/mnt.1234".to_string(),
+            templates: BTreeMap::new(),
+            read_only: true,
+        };
+        prepare_target_path(&dir, false).await.unwrap();
+        write_volume_metadata(&dir, &metadata).await.unwrap();
+        write_payload(&dir, payload(&[ingress("10.0.0.1", 8080)]).unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            READ_ONLY_DIR_MODE
+        );
+
+        // kubelet may publish the same volume again, such as after the node driver was restarted
+        prepare_target_path(&dir, false).await.unwrap();
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            WRITABLE_DIR_MODE
+        );
+        write_volume_metadata(&dir, &metadata).await.unwrap();
+        write_payload(&dir, payload(&[ingress("10.0.0.2", 8080)]).unwrap(), true)
+            .await
+            .unwrap();
+        remove_target_path(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+//! Renders user-defined templates into listener volumes.
+//!
+//! Templates are plain text, where `{{ variable }}` placeholders are replaced by the listener information:
+//!
+//! - `name`, `namespace`, `class`, `scope`: The Listener's metadata
+//! - `default_address`, `default_address_type`: The Listener's default address
+//! - `port.<name>`: The port number of the named port, for the default address
+//! - `endpoints.<name>`: A comma-separated list of `address:port` pairs of the named port, for all addresses
+
+use std::net::IpAddr;
+
+use snafu::{OptionExt, Snafu};
+
+use super::pod_dir::{ListenerInfo, address_type_name};
+
+/// Prefix of PersistentVolumeClaim annotations that define a template, followed by the name of the rendered file.
+pub const PVC_ANNOTATION_TEMPLATE_PREFIX: &str = "templates.listeners.stackable.tech/";
+
+/// PersistentVolumeClaim annotation that references a ConfigMap (in the same namespace), where each key defines a
+/// template.
+pub const PVC_ANNOTATION_TEMPLATES_CONFIGMAP: &str = "listeners.stackable.tech/templates-configmap";
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("placeholder at offset {offset} is not terminated by \"}}}}\""))]
+    UnterminatedPlaceholder { offset: usize },
+
+    #[snafu(display("unknown variable {variable:?}"))]
+    UnknownVariable { variable: String },
+
+    #[snafu(display("listener has no address yet"))]
+    NoDefaultAddress,
+}
+
+/// Renders `template` for the listener described by `info`.
+pub fn render(template: &str, info: &ListenerInfo) -> Result<String, Error> {
+    use error::*;
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let end = placeholder
+            .find("}}")
+            .with_context(|| UnterminatedPlaceholderSnafu {
+                offset: template.len() - rest.len() + start,
+            })?;
+        rendered.push_str(&variable(placeholder[..end].trim(), info)?);
+        rest = &placeholder[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Looks up the value of the template variable `name`.
+fn variable(name: &str, info: &ListenerInfo) -> Result<String, Error> {
+    use error::*;
+    let default_address = || info.addresses.first().context(NoDefaultAddressSnafu);
+    Ok(match name {
+        "name" => info.name.to_string(),
+        "namespace" => info.namespace.to_string(),
+        "class" => info.class_name.unwrap_or_default().to_string(),
+        "scope" => info.scope_name().to_string(),
+        "default_address" => default_address()?.address.clone(),
+        "default_address_type" => address_type_name(default_address()?.address_type).to_string(),
+        _ => {
+            if let Some(port_name) = name.strip_prefix("port.") {
+                default_address()?
+                    .ports
+                    .get(port_name)
+                    .with_context(|| UnknownVariableSnafu { variable: name })?
+                    .to_string()
+            } else if let Some(port_name) = name.strip_prefix("endpoints.") {
+                let endpoints = info
+                    .addresses
+                    .iter()
+                    .filter_map(|addr| {
+                        let port = addr.ports.get(port_name)?;
+                        Some(match addr.address.parse::<IpAddr>() {
+                            Ok(IpAddr::V6(_)) => format!("[{}]:{port}", addr.address),
+                            _ => format!("{}:{port}", addr.address),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                if endpoints.is_empty() {
+                    return UnknownVariableSnafu { variable: name }.fail();
+                }
+                endpoints.join(",")
+            } else {
+                return UnknownVariableSnafu { variable: name }.fail();
+            }
+        }
+    })
+}
+
+/// Whether `name` can be used as the file name of a rendered template.
+pub fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.starts_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use stackable_operator::crd::listener;
+
+    use super::*;
+
+    fn ingress(address: &str, port: i32) -> listener::v1alpha1::ListenerIngress {
+        listener::v1alpha1::ListenerIngress {
+            address: address.to_string(),
+            address_type: listener::v1alpha1::AddressType::Ip,
+            ports: [("kafka".to_string(), port)].into(),
+        }
+    }
+
+    fn listener_info(addresses: &[listener::v1alpha1::ListenerIngress]) -> ListenerInfo<'_> {
+        ListenerInfo {
+            name: "kafka-broker-0",
+            namespace: "default",
+            class_name: Some("external-unstable"),
+            scope: listener::v1alpha1::PodListenerScope::Node,
+            addresses,
+        }
+    }
+
+    #[test]
+    fn render_variables() {
+        let addresses = [ingress("10.0.0.1", 30092), ingress("fd00::1", 30093)];
+        let info = listener_info(&addresses);
+        assert_eq!(
+            render(
+                "advertised.listeners=CLIENT://{{default_address}}:{{ port.kafka }}\n",
+                &info
+            )
+            .unwrap(),
+            "advertised.listeners=CLIENT://10.0.0.1:30092\n"
+        );
+        assert_eq!(
+            render(
+                "{{ name }}.{{ namespace }} ({{ class }}, {{ scope }}): {{ endpoints.kafka }}",
+                &info
+            )
+            .unwrap(),
+            "kafka-broker-0.default (external-unstable, Node): 10.0.0.1:30092,[fd00::1]:30093"
+        );
+        assert_eq!(render("no placeholders", &info).unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn render_errors() {
+        let addresses = [ingress("10.0.0.1", 30092)];
+        let info = listener_info(&addresses);
+        assert!(matches!(
+            render("{{ port.http }}", &info),
+            Err(Error::UnknownVariable { variable }) if variable == "port.http"
+        ));
+        assert!(matches!(
+            render("{{ foo }}", &info),
+            Err(Error::UnknownVariable { .. })
+        ));
+        assert!(matches!(
+            render("a {{ name", &info),
+            Err(Error::UnterminatedPlaceholder { offset: 2 })
+        ));
+        assert!(matches!(
+            render("{{ default_address }}", &listener_info(&[])),
+            Err(Error::NoDefaultAddress)
+        ));
+    }
+}
@@ -0,0 +1,460 @@
+//! Keeps the contents of published listener volumes up to date when their [`listener::v1alpha1::Listener`]'s
+//! addresses change.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use snafu::ResultExt;
+use stackable_operator::{
+    crd::listener,
+    k8s_openapi::{api::core::v1::Pod, jiff::Timestamp},
+    kube::{
+        Resource,
+        core::{DeserializeGuard, ObjectMeta},
+        runtime::{
+            WatchStreamExt,
+            events::{Event, EventType, Recorder},
+            reflector::{self, ObjectRef},
+            watcher,
+        },
+    },
+};
+
+use super::{
+    ListenerOperatorNode, PublishVolumeError, local_listener_addresses_for_pod, pod_dir,
+    pod_dir_listener_info, publish_pod_listener, publish_volume_error, render_templates,
+};
+use crate::health;
+
+/// Bumped on the Pod whenever one of its listener volumes is updated, when using
+/// [`ListenerUpdateNotification::PodAnnotation`].
+pub const POD_ANNOTATION_LISTENER_VOLUME_UPDATED: &str =
+    "listeners.stackable.tech/listener-volume-updated";
+
+/// How workloads are notified when the contents of their listener volumes change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ListenerUpdateNotification {
+    /// The volume is updated silently.
+    None,
+
+    /// An Event is recorded on the Pod.
+    #[default]
+    Event,
+
+    /// An Event is recorded on the Pod, and the [`POD_ANNOTATION_LISTENER_VOLUME_UPDATED`] annotation is updated.
+    PodAnnotation,
+}
+
+/// A listener volume that has been published by this node.
+pub(super) struct PublishedVolume {
+    pub listener: ObjectRef<listener::v1alpha1::Listener>,
+    pub pod: ObjectRef<Pod>,
+    /// The UID of the Pod that the volume was published for, since a replacement Pod (such as one recreated by a
+    /// StatefulSet) may reuse its name.
+    pub pod_uid: String,
+    /// The name of the Pod's volume that the listener volume is mounted as.
+    pub pod_volume_name: String,
+    /// The addresses that were last written to the volume.
+    pub addresses: Vec<listener::v1alpha1::ListenerIngress>,
+    /// The user-defined templates of the volume, which are re-rendered whenever the addresses change.
+    pub templates: BTreeMap<String, String>,
+    /// Whether the volume was published read-only.
+    pub read_only: bool,
+}
+
+/// A listener volume's entry in [`PublishedVolumes`].
+#[derive(Clone)]
+struct RegisteredVolume {
+    /// The Listener of the volume, which never changes while it is published.
+    listener: ObjectRef<listener::v1alpha1::Listener>,
+    volume: Arc<tokio::sync::Mutex<Option<PublishedVolume>>>,
+}
+
+/// The listener volumes that are currently published by this node, keyed by their target path.
+///
+/// Each volume is guarded by its own lock, so that updates cannot race with the volume being unpublished.
+/// Unpublished volumes are marked by setting the lock's contents to [`None`].
+#[derive(Clone, Default)]
+pub struct PublishedVolumes {
+    volumes: Arc<Mutex<BTreeMap<PathBuf, RegisteredVolume>>>,
+    /// Notified whenever volumes are added or removed, so that the volumes of newly added Listeners can be brought up
+    /// to date.
+    changed: Arc<tokio::sync::Notify>,
+}
+
+impl PublishedVolumes {
+    pub(super) fn insert(&self, target_path: PathBuf, volume: PublishedVolume) {
+        self.volumes
+            .lock()
+            .expect("published volume registry lock was poisoned")
+            .insert(
+                target_path,
+                RegisteredVolume {
+                    listener: volume.listener.clone(),
+                    volume: Arc::new(tokio::sync::Mutex::new(Some(volume))),
+                },
+            );
+        self.changed.notify_one();
+    }
+
+    /// Runs `unpublish` for the volume at `target_path`, and unregisters the volume once it has succeeded.
+    ///
+    /// Updates to the volume are held off while `unpublish` is running. If it fails, then the volume stays registered
+    /// (and kept up to date) until kubelet retries.
+    pub(super) async fn unpublish<E>(
+        &self,
+        target_path: &Path,
+        unpublish: impl Future<Output = Result<(), E>>,
+    ) -> Result<(), E> {
+        let registered = self
+            .volumes
+            .lock()
+            .expect("published volume registry lock was poisoned")
+            .get(target_path)
+            .cloned();
+        let Some(registered) = registered else {
+            return unpublish.await;
+        };
+        let mut volume = registered.volume.lock().await;
+        unpublish.await?;
+        *volume = None;
+        {
+            let mut volumes = self
+                .volumes
+                .lock()
+                .expect("published volume registry lock was poisoned");
+            // The volume may have been published again in the meantime
+            if volumes
+                .get(target_path)
+                .is_some_and(|current| Arc::ptr_eq(&current.volume, &registered.volume))
+            {
+                volumes.remove(target_path);
+            }
+        }
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// Lists the Listeners that any volumes belong to.
+    fn listeners(&self) -> BTreeSet<ObjectRef<listener::v1alpha1::Listener>> {
+        self.volumes
+            .lock()
+            .expect("published volume registry lock was poisoned")
+            .values()
+            .map(|registered| registered.listener.clone())
+            .collect()
+    }
+
+    /// Lists all volumes that belong to `listener`.
+    fn for_listener(
+        &self,
+        listener: &ObjectRef<listener::v1alpha1::Listener>,
+    ) -> Vec<(PathBuf, Arc<tokio::sync::Mutex<Option<PublishedVolume>>>)> {
+        self.volumes
+            .lock()
+            .expect("published volume registry lock was poisoned")
+            .iter()
+            .filter(|(_, registered)| registered.listener == *listener)
+            .map(|(target_path, registered)| (target_path.clone(), registered.volume.clone()))
+            .collect()
+    }
+}
+
+impl ListenerOperatorNode {
+    /// Watches the Listeners of all published volumes, and updates the volumes when the Listener's addresses change.
+    ///
+    /// Volumes that were published before the node driver was (re)started are recovered from `kubelet_dir` first,
+    /// after which [`health::ReadinessCheck::InitialSync`] is completed.
+    pub async fn run_volume_updater(
+        self,
+        kubelet_dir: PathBuf,
+        readiness: health::Readiness,
+        shutdown_signal: impl Future<Output = ()>,
+    ) {
+        self.recover_published_volumes(&kubelet_dir).await;
+        readiness.complete(health::ReadinessCheck::InitialSync);
+
+        let event_recorder = self.event_recorder();
+        let (node, event_recorder) = (&self, &event_recorder);
+        // A single watch on all Listeners, rather than one per Listener that has volumes on this Node, so that each
+        // node driver only holds one watch, however many Listeners it serves
+        let (listeners, listener_writer) = reflector::store();
+        let listener_watcher = reflector::reflector(
+            listener_writer,
+            watcher(
+                self.client
+                    .get_all_api::<DeserializeGuard<listener::v1alpha1::Listener>>(),
+                watcher::Config::default(),
+            ),
+        )
+        .default_backoff()
+        .applied_objects()
+        .for_each(move |updated_listener| async move {
+            match updated_listener {
+                Ok(DeserializeGuard(Ok(updated_listener))) => {
+                    node.update_listener_volumes(event_recorder, &updated_listener)
+                        .await
+                }
+                // Invalid Listeners can't be used to update volumes
+                Ok(DeserializeGuard(Err(_))) => {}
+                Err(err) => tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to watch Listeners"
+                ),
+            }
+        });
+        // The Listener may have changed between a volume being published and it being registered, which the watch
+        // would not report again
+        let new_listener_updater = async {
+            if listeners.wait_until_ready().await.is_err() {
+                return;
+            }
+            let mut known_listeners = BTreeSet::new();
+            loop {
+                // Changes that happen while the volumes are being updated are stored as a permit by Notify, so they
+                // are not lost
+                let current_listeners = node.published_volumes.listeners();
+                for listener_ref in current_listeners.difference(&known_listeners) {
+                    let listener = listeners.get(
+                        &ObjectRef::new(&listener_ref.name)
+                            .within(listener_ref.namespace.as_deref().unwrap_or_default()),
+                    );
+                    if let Some(DeserializeGuard(Ok(listener))) = listener.as_deref() {
+                        node.update_listener_volumes(event_recorder, listener).await;
+                    }
+                }
+                known_listeners = current_listeners;
+                node.published_volumes.changed.notified().await;
+            }
+        };
+        tokio::select! {
+            () = listener_watcher => {},
+            () = new_listener_updater => {},
+            () = shutdown_signal => {},
+        }
+    }
+
+    /// Registers the volumes that were published before the node driver was (re)started, so that they are kept up to
+    /// date as well.
+    async fn recover_published_volumes(&self, kubelet_dir: &Path) {
+        let target_paths = match pod_dir::find_published_volumes(kubelet_dir).await {
+            Ok(target_paths) => target_paths,
+            Err(err) => {
+                tracing::warn!(
+                    ?kubelet_dir,
+                    error = &err as &dyn std::error::Error,
+                    "failed to find published listener volumes, they will not be updated until republished"
+                );
+                return;
+            }
+        };
+        for target_path in target_paths {
+            let published = async {
+                Ok::<_, pod_dir::Error>((
+                    pod_dir::read_volume_metadata(&target_path).await?,
+                    pod_dir::read_published_listener(&target_path).await?,
+                ))
+            };
+            let (metadata, listener) = match published.await {
+                Ok((Some(metadata), Some(listener))) => (metadata, listener),
+                // The volume was never fully published, so kubelet will retry publishing it
+                Ok(_) => continue,
+                Err(err) => {
+                    tracing::warn!(
+                        ?target_path,
+                        error = &err as &dyn std::error::Error,
+                        "failed to read published listener volume"
+                    );
+                    continue;
+                }
+            };
+            tracing::info!(?target_path, "recovered published listener volume");
+            self.published_volumes.insert(
+                target_path,
+                PublishedVolume {
+                    listener: ObjectRef::new(&listener.name).within(&listener.namespace),
+                    pod: ObjectRef::new(&metadata.pod_name).within(&metadata.pod_namespace),
+                    pod_uid: metadata.pod_uid,
+                    pod_volume_name: metadata.pod_volume_name,
+                    addresses: listener.addresses,
+                    templates: metadata.templates,
+                    read_only: metadata.read_only,
+                },
+            );
+        }
+    }
+
+    /// Updates the volumes of `listener` that are published by this node, if any.
+    async fn update_listener_volumes(
+        &self,
+        event_recorder: &Recorder,
+        listener: &listener::v1alpha1::Listener,
+    ) {
+        let listener_ref = ObjectRef::from_obj(listener);
+        for (target_path, volume) in self.published_volumes.for_listener(&listener_ref) {
+            let mut volume = volume.lock().await;
+            let Some(volume) = volume.as_mut().filter(|v| v.listener == listener_ref) else {
+                // The volume was unpublished in the meantime
+                continue;
+            };
+            match self
+                .update_volume(event_recorder, &target_path, volume, listener)
+                .await
+            {
+                Ok(true) => tracing::info!(
+                    listener = %listener_ref,
+                    ?target_path,
+                    "updated listener volume with new addresses"
+                ),
+                Ok(false) => {}
+                Err(err) => tracing::warn!(
+                    listener = %listener_ref,
+                    ?target_path,
+                    error = &err as &dyn std::error::Error,
+                    "failed to update listener volume"
+                ),
+            }
+        }
+    }
+
+    /// Rewrites the volume at `target_path` if `listener`'s addresses have changed.
+    ///
+    /// Returns whether the volume was updated.
+    async fn update_volume(
+        &self,
+        event_recorder: &Recorder,
+        target_path: &Path,
+        volume: &mut PublishedVolume,
+        listener: &listener::v1alpha1::Listener,
+    ) -> Result<bool, PublishVolumeError> {
+        use publish_volume_error::*;
+        let ns = volume.pod.namespace.as_deref().unwrap_or_default();
+        let pod = self
+            .client
+            .get_opt::<Pod>(&volume.pod.name, ns)
+            .await
+            .with_context(|_| GetObjectSnafu {
+                obj: volume.pod.clone().erase(),
+            })?;
+        // The volume belongs to a Pod that is gone (or has been replaced by another Pod with the same name), so it will
+        // be unpublished by kubelet soon. The replacement Pod's volumes are kept up to date by the node that it runs on.
+        let Some(pod) =
+            pod.filter(|pod| pod.metadata.uid.as_deref() == Some(volume.pod_uid.as_str()))
+        else {
+            tracing::debug!(
+                pod = %volume.pod,
+                pod.uid = volume.pod_uid,
+                ?target_path,
+                "Pod of listener volume is gone, not updating it"
+            );
+            return Ok(false);
+        };
+        let addresses =
+            local_listener_addresses_for_pod(&self.client, &self.address_resolver, listener, &pod)
+                .await?;
+        // Keep serving the old addresses rather than leaving the volume without any
+        if addresses.is_empty() || addresses_eq(&addresses, &volume.addresses) {
+            return Ok(false);
+        }
+
+        publish_pod_listener(
+            &self.client,
+            &pod,
+            &volume.pod_volume_name,
+            listener,
+            &addresses,
+        )
+        .await?;
+        let listener_info = pod_dir_listener_info(listener, &addresses);
+        pod_dir::write_listener_info_to_pod_dir(
+            target_path,
+            &listener_info,
+            &render_templates(&volume.templates, &listener_info)?,
+            volume.read_only,
+        )
+        .await
+        .context(PreparePodDirSnafu { target_path })?;
+        volume.addresses = addresses;
+
+        self.notify_volume_updated(event_recorder, &pod, volume)
+            .await;
+        Ok(true)
+    }
+
+    /// Notifies the workload that its volume was updated, according to [`Self::listener_update_notification`].
+    async fn notify_volume_updated(
+        &self,
+        event_recorder: &Recorder,
+        pod: &Pod,
+        volume: &PublishedVolume,
+    ) {
+        if self.listener_update_notification == ListenerUpdateNotification::None {
+            return;
+        }
+
+        let addresses = volume
+            .addresses
+            .iter()
+            .map(|addr| addr.address.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let event = Event {
+            type_: EventType::Normal,
+            reason: "ListenerVolumeUpdated".to_string(),
+            note: Some(format!(
+                "listener volume {pod_volume_name:?} was updated with the new addresses of {listener}: {addresses}",
+                pod_volume_name = volume.pod_volume_name,
+                listener = volume.listener,
+            )),
+            action: "UpdateVolume".to_string(),
+            secondary: Some(volume.listener.clone().into()),
+        };
+        if let Err(err) = event_recorder.publish(&event, &pod.object_ref(&())).await {
+            tracing::warn!(
+                pod = %volume.pod,
+                error = &err as &dyn std::error::Error,
+                "failed to publish listener volume update event"
+            );
+        }
+
+        if self.listener_update_notification == ListenerUpdateNotification::PodAnnotation {
+            let patch = Pod {
+                metadata: ObjectMeta {
+                    annotations: Some(
+                        [(
+                            POD_ANNOTATION_LISTENER_VOLUME_UPDATED.to_string(),
+                            Timestamp::now().to_string(),
+                        )]
+                        .into(),
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            if let Err(err) = self.client.merge_patch(pod, &patch).await {
+                tracing::warn!(
+                    pod = %volume.pod,
+                    error = &err as &dyn std::error::Error,
+                    "failed to annotate Pod with listener volume update"
+                );
+            }
+        }
+    }
+}
+
+/// Whether `a` and `b` contain the same addresses, in the same order.
+pub(super) fn addresses_eq(
+    a: &[listener::v1alpha1::ListenerIngress],
+    b: &[listener::v1alpha1::ListenerIngress],
+) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.address == b.address && a.address_type == b.address_type && a.ports == b.ports
+        })
+}
@@ -0,0 +1,650 @@
+//! An authoritative DNS server for the addresses of [`listener::v1alpha1::Listener`]s.
+//!
+//! For a zone such as `listeners.example.com`, the following names are served:
+//!
+//! - `<listener>.<namespace>.listeners.example.com`: `A`/`AAAA` records for the Listener's IP addresses, or a `CNAME`
+//!   record for its first hostname if it has no IP addresses
+//! - `_<port>._<protocol>.<listener>.<namespace>.listeners.example.com`: `SRV` records for each named port
+//! - `listeners.example.com`: The zone's `SOA` record, which is also included in negative responses
+//!
+//! The records are taken from the Listeners' statuses, as published by the [`crate::listener_controller`].
+
+use std::{
+    future::{Future, ready},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use hickory_proto::{
+    ProtoError,
+    op::{Header, Message, MessageType, OpCode, ResponseCode},
+    rr::{DNSClass, Name, RData, Record, RecordType, rdata::SOA},
+    serialize::binary::{BinDecodable, BinDecoder},
+};
+use snafu::{ResultExt, Snafu};
+use stackable_operator::{
+    client::Client,
+    crd::listener,
+    kube::{
+        core::DeserializeGuard,
+        runtime::{
+            WatchStreamExt,
+            reflector::{self, ObjectRef},
+            watcher,
+        },
+    },
+    shared::time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::Semaphore,
+};
+
+use self::records::{RecordName, listener_records, relative_to_origin};
+use crate::{health, utils::dns::MAX_UDP_MESSAGE_LEN};
+
+mod records;
+
+/// How long a TCP client may take to send each query and read its response, including any idle time before it.
+const TCP_EXCHANGE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The maximum number of TCP connections that are served at the same time, further connections are closed right away.
+const MAX_TCP_CONNECTIONS: usize = 256;
+
+/// The timers of the zone's SOA record. Zone transfers are not supported, so these are only informational.
+const SOA_REFRESH_SECS: i32 = 3600;
+const SOA_RETRY_SECS: i32 = 600;
+const SOA_EXPIRE_SECS: i32 = 86400;
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("failed to bind UDP socket to {address}"))]
+    BindUdp {
+        source: io::Error,
+        address: SocketAddr,
+    },
+
+    #[snafu(display("failed to bind TCP socket to {address}"))]
+    BindTcp {
+        source: io::Error,
+        address: SocketAddr,
+    },
+
+    #[snafu(display("invalid zone name {origin:?}"))]
+    InvalidZone { source: ProtoError, origin: String },
+
+    #[snafu(display("Listener watcher stopped before the initial sync completed"))]
+    InitialListenerSync {
+        source: reflector::store::WriterDropped,
+    },
+}
+
+/// The zone served by the DNS server.
+struct Zone {
+    /// The (lowercase) name of the zone, without a trailing dot.
+    origin: String,
+    /// The fully qualified name of the zone, which owns its SOA record.
+    origin_name: Name,
+    soa: SOA,
+    ttl: u32,
+    listeners: reflector::Store<DeserializeGuard<listener::v1alpha1::Listener>>,
+}
+
+impl Zone {
+    /// Creates the zone `origin`, serving the records of `listeners`.
+    fn new(
+        origin: &str,
+        ttl: u32,
+        listeners: reflector::Store<DeserializeGuard<listener::v1alpha1::Listener>>,
+    ) -> Result<Self, Error> {
+        use error::*;
+        let origin = origin.trim_end_matches('.').to_ascii_lowercase();
+        let mut origin_name =
+            Name::from_ascii(&origin).context(InvalidZoneSnafu { origin: &origin })?;
+        origin_name.set_fqdn(true);
+        let hostmaster = origin_name
+            .prepend_label("hostmaster")
+            .context(InvalidZoneSnafu { origin: &origin })?;
+        // Negative responses are cached for as long as positive ones
+        let soa = SOA::new(
+            origin_name.clone(),
+            hostmaster,
+            1,
+            SOA_REFRESH_SECS,
+            SOA_RETRY_SECS,
+            SOA_EXPIRE_SECS,
+            ttl,
+        );
+        Ok(Self {
+            origin,
+            origin_name,
+            soa,
+            ttl,
+            listeners,
+        })
+    }
+
+    /// Builds the response to the DNS message `request`, or [`None`] if it should not be replied to.
+    ///
+    /// If the full response would be larger than `max_len`, then all records are dropped and the response is marked
+    /// as truncated, so that the client retries over TCP.
+    fn respond(&self, request: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        let request = match Message::from_vec(request) {
+            Ok(request) => request,
+            Err(err) => {
+                tracing::debug!(error = &err as &dyn std::error::Error, "invalid DNS query");
+                // The client can only be told about the error if at least the header could be read
+                let header = Header::read(&mut BinDecoder::new(request)).ok()?;
+                return error_response(&header, ResponseCode::FormErr);
+            }
+        };
+        // Replying to responses could cause loops
+        if request.message_type() != MessageType::Query {
+            return None;
+        }
+        if request.op_code() != OpCode::Query {
+            return error_response(request.header(), ResponseCode::NotImp);
+        }
+        let [query] = request.queries() else {
+            return error_response(request.header(), ResponseCode::FormErr);
+        };
+
+        let (response_code, records) = match query.query_class() {
+            DNSClass::IN | DNSClass::ANY => self.resolve(
+                query.name().to_lowercase().to_ascii().trim_end_matches('.'),
+                query.query_type(),
+            ),
+            _ => (ResponseCode::Refused, Vec::new()),
+        };
+        // Negative responses (NXDOMAIN, and NODATA) must include the zone's SOA record, so that resolvers know how
+        // long they may cache them for (RFC 2308)
+        let is_negative = response_code == ResponseCode::NXDomain
+            || (response_code == ResponseCode::NoError && records.is_empty());
+        tracing::debug!(
+            name = %query.name(),
+            query_type = %query.query_type(),
+            ?response_code,
+            ?records,
+            "answering DNS query"
+        );
+
+        let mut response = Message::new();
+        response
+            .set_header(Header::response_from_request(request.header()))
+            .set_authoritative(true)
+            .set_response_code(response_code)
+            .add_query(query.clone());
+        let without_records = response.clone();
+        response.add_answers(
+            records
+                .into_iter()
+                .map(|data| Record::from_rdata(query.name().clone(), self.ttl, data)),
+        );
+        if is_negative {
+            response.add_name_server(Record::from_rdata(
+                self.origin_name.clone(),
+                self.ttl,
+                RData::SOA(self.soa.clone()),
+            ));
+        }
+        match response.to_vec() {
+            Ok(response) if response.len() <= max_len => Some(response),
+            Ok(_) => {
+                let mut response = without_records;
+                response.set_truncated(true);
+                response.to_vec().ok()
+            }
+            Err(err) => {
+                tracing::warn!(
+                    name = %query.name(),
+                    error = &err as &dyn std::error::Error,
+                    "failed to encode DNS response"
+                );
+                let mut response = without_records;
+                response.set_response_code(ResponseCode::ServFail);
+                response.to_vec().ok()
+            }
+        }
+    }
+
+    fn resolve(&self, name: &str, query_type: RecordType) -> (ResponseCode, Vec<RData>) {
+        let Some(relative_name) = relative_to_origin(name, &self.origin) else {
+            // We are not authoritative for anything outside of the zone, and don't recurse
+            return (ResponseCode::Refused, Vec::new());
+        };
+        if relative_name.is_empty() {
+            // The zone apex only has the SOA record
+            let records = match query_type {
+                RecordType::SOA | RecordType::ANY => vec![RData::SOA(self.soa.clone())],
+                _ => Vec::new(),
+            };
+            return (ResponseCode::NoError, records);
+        }
+        let Some(record_name) = RecordName::parse(relative_name) else {
+            return (ResponseCode::NXDomain, Vec::new());
+        };
+        let listener = self
+            .listeners
+            .get(&ObjectRef::new(record_name.listener_name()).within(record_name.namespace()));
+        let Some(listener) = listener
+            .as_deref()
+            .and_then(|listener| listener.0.as_ref().ok())
+        else {
+            return (ResponseCode::NXDomain, Vec::new());
+        };
+        let listener_dns_name = format!(
+            "{listener_name}.{namespace}.{origin}",
+            listener_name = record_name.listener_name(),
+            namespace = record_name.namespace(),
+            origin = self.origin,
+        );
+        match listener_records(listener, &record_name, query_type, &listener_dns_name) {
+            Some(records) => (ResponseCode::NoError, records),
+            None => (ResponseCode::NXDomain, Vec::new()),
+        }
+    }
+}
+
+/// Builds a response to the message with the header `request`, without any records.
+///
+/// Returns [`None`] if `request` is not a query.
+fn error_response(request: &Header, response_code: ResponseCode) -> Option<Vec<u8>> {
+    if request.message_type() != MessageType::Query {
+        return None;
+    }
+    let mut response = Message::new();
+    response
+        .set_header(Header::response_from_request(request))
+        .set_response_code(response_code);
+    response.to_vec().ok()
+}
+
+/// Serves DNS records for all Listeners in the cluster, for the zone `origin`.
+///
+/// Queries are accepted over both UDP and TCP on `listen_address`.
+pub async fn run<F>(
+    client: Client,
+    origin: &str,
+    ttl: Duration,
+    listen_address: SocketAddr,
+    readiness: health::Readiness,
+    shutdown_signal: F,
+) -> Result<(), Error>
+where
+    F: Future<Output = ()>,
+{
+    use error::*;
+    let (listeners, listener_writer) = reflector::store();
+    let listener_watcher = reflector::reflector(
+        listener_writer,
+        watcher(
+            client.get_all_api::<DeserializeGuard<listener::v1alpha1::Listener>>(),
+            watcher::Config::default(),
+        ),
+    )
+    .default_backoff()
+    .touched_objects()
+    .for_each(|res| {
+        if let Err(err) = res {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "failed to watch Listeners"
+            );
+        }
+        ready(())
+    });
+
+    let zone = Arc::new(Zone::new(
+        origin,
+        u32::try_from(ttl.as_secs()).unwrap_or(u32::MAX),
+        listeners,
+    )?);
+    let server = async {
+        // Don't start answering queries until we know about all Listeners, to avoid serving spurious NXDOMAINs
+        zone.listeners
+            .wait_until_ready()
+            .await
+            .context(InitialListenerSyncSnafu)?;
+        let udp_socket = UdpSocket::bind(listen_address)
+            .await
+            .context(BindUdpSnafu {
+                address: listen_address,
+            })?;
+        let tcp_listener = TcpListener::bind(listen_address)
+            .await
+            .context(BindTcpSnafu {
+                address: listen_address,
+            })?;
+        tracing::info!(%listen_address, zone = %zone.origin, "serving DNS");
+        readiness.complete(health::ReadinessCheck::InitialSync);
+        futures::join!(
+            serve_udp(&udp_socket, &zone),
+            serve_tcp(&tcp_listener, &zone)
+        );
+        Ok::<_, Error>(())
+    };
+
+    tokio::select! {
+        () = listener_watcher => Ok(()),
+        res = server => res,
+        () = shutdown_signal => Ok(()),
+    }
+}
+
+async fn serve_udp(socket: &UdpSocket, zone: &Zone) {
+    // Queries may be larger than our response size limit if they include EDNS options
+    let mut buf = [0; 4096];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to receive DNS query"
+                );
+                continue;
+            }
+        };
+        if let Some(response) = zone.respond(&buf[..len], MAX_UDP_MESSAGE_LEN)
+            && let Err(err) = socket.send_to(&response, peer).await
+        {
+            tracing::warn!(
+                %peer,
+                error = &err as &dyn std::error::Error,
+                "failed to send DNS response"
+            );
+        }
+    }
+}
+
+async fn serve_tcp(listener: &TcpListener, zone: &Arc<Zone>) {
+    let connections = Arc::new(Semaphore::new(MAX_TCP_CONNECTIONS));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to accept DNS connection"
+                );
+                continue;
+            }
+        };
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            tracing::debug!(%peer, "too many open DNS connections, closing new connection");
+            continue;
+        };
+        let zone = zone.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(err) = serve_tcp_connection(stream, &zone).await {
+                tracing::debug!(
+                    %peer,
+                    error = &err as &dyn std::error::Error,
+                    "DNS connection failed"
+                );
+            }
+        });
+    }
+}
+
+/// Serves a TCP connection, where each message is prefixed by its length
+/// ([RFC 1035 section 4.2.2](https://www.rfc-editor.org/rfc/rfc1035#section-4.2.2)).
+///
+/// The connection is closed once the client takes longer than [`TCP_EXCHANGE_TIMEOUT`] to complete a query.
+async fn serve_tcp_connection(mut stream: TcpStream, zone: &Zone) -> io::Result<()> {
+    loop {
+        match tokio::time::timeout(TCP_EXCHANGE_TIMEOUT, serve_tcp_query(&mut stream, zone)).await {
+            Ok(Ok(())) => {}
+            // The client closed the connection
+            Ok(Err(err)) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(err)) => return Err(err),
+            // The client has gone idle, or is too slow
+            Err(_) => return Ok(()),
+        }
+    }
+}
+
+/// Reads a single query from `stream`, and writes the response to it.
+async fn serve_tcp_query(stream: &mut TcpStream, zone: &Zone) -> io::Result<()> {
+    let len = stream.read_u16().await?;
+    let mut request = vec![0; len.into()];
+    stream.read_exact(&mut request).await?;
+    if let Some(response) = zone.respond(&request, u16::MAX.into()) {
+        // respond() guarantees that the response fits into max_len
+        stream.write_u16(response.len() as u16).await?;
+        stream.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use hickory_proto::{
+        op::Query,
+        rr::rdata::{A, SRV},
+    };
+    use stackable_operator::kube::api::ObjectMeta;
+
+    use super::*;
+
+    /// A Listener named `my-listener` in the namespace `default`, with the port `http` on each of `ips`.
+    fn listener(ips: impl IntoIterator<Item = Ipv4Addr>) -> listener::v1alpha1::Listener {
+        listener::v1alpha1::Listener {
+            metadata: ObjectMeta {
+                name: Some("my-listener".to_string()),
+                namespace: Some("default".to_string()),
+                ..Default::default()
+            },
+            spec: listener::v1alpha1::ListenerSpec {
+                ports: Some(vec![listener::v1alpha1::ListenerPort {
+                    name: "http".to_string(),
+                    port: 80,
+                    protocol: None,
+                }]),
+                ..Default::default()
+            },
+            status: Some(listener::v1alpha1::ListenerStatus {
+                service_name: None,
+                ingress_addresses: Some(
+                    ips.into_iter()
+                        .map(|ip| listener::v1alpha1::ListenerIngress {
+                            address: ip.to_string(),
+                            address_type: listener::v1alpha1::AddressType::Ip,
+                            ports: [("http".to_string(), 30080)].into(),
+                        })
+                        .collect(),
+                ),
+                node_ports: None,
+            }),
+        }
+    }
+
+    fn zone(listeners: impl IntoIterator<Item = listener::v1alpha1::Listener>) -> Zone {
+        let (store, mut writer) = reflector::store();
+        for listener in listeners {
+            writer.apply_watcher_event(&watcher::Event::Apply(DeserializeGuard(Ok(listener))));
+        }
+        Zone::new("Listeners.example.com.", 30, store).unwrap()
+    }
+
+    fn query(name: &str, record_type: RecordType) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(42)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        message
+    }
+
+    fn respond(zone: &Zone, request: &Message, max_len: usize) -> Message {
+        let response = zone.respond(&request.to_vec().unwrap(), max_len).unwrap();
+        assert!(response.len() <= max_len);
+        Message::from_vec(&response).unwrap()
+    }
+
+    #[test]
+    fn answer_listener_queries() {
+        let zone = zone([listener([Ipv4Addr::new(10, 0, 0, 1)])]);
+        // Names are matched case-insensitively, but echoed back as they were queried
+        let request = query("My-Listener.default.listeners.example.com.", RecordType::A);
+        let response = respond(&zone, &request, MAX_UDP_MESSAGE_LEN);
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.authoritative());
+        assert!(response.recursion_desired());
+        assert!(!response.recursion_available());
+        assert_eq!(response.queries(), request.queries());
+        assert_eq!(
+            response.answers(),
+            [Record::from_rdata(
+                request.queries()[0].name().clone(),
+                30,
+                RData::A(A::new(10, 0, 0, 1)),
+            )]
+        );
+        assert!(response.name_servers().is_empty());
+
+        let response = respond(
+            &zone,
+            &query(
+                "_http._tcp.my-listener.default.listeners.example.com.",
+                RecordType::SRV,
+            ),
+            MAX_UDP_MESSAGE_LEN,
+        );
+        assert_eq!(
+            response
+                .answers()
+                .iter()
+                .map(|record| record.data().clone())
+                .collect::<Vec<_>>(),
+            [RData::SRV(SRV::new(
+                0,
+                0,
+                30080,
+                Name::from_ascii("my-listener.default.listeners.example.com.").unwrap(),
+            ))]
+        );
+    }
+
+    #[test]
+    fn negative_responses_include_soa() {
+        let zone = zone([listener([Ipv4Addr::new(10, 0, 0, 1)])]);
+        let origin = Name::from_ascii("listeners.example.com.").unwrap();
+        let expected_soa = RData::SOA(SOA::new(
+            origin.clone(),
+            Name::from_ascii("hostmaster.listeners.example.com.").unwrap(),
+            1,
+            SOA_REFRESH_SECS,
+            SOA_RETRY_SECS,
+            SOA_EXPIRE_SECS,
+            30,
+        ));
+        for (name, response_code) in [
+            (
+                "other-listener.default.listeners.example.com.",
+                ResponseCode::NXDomain,
+            ),
+            // The name exists, but has no records of the queried type (NODATA)
+            (
+                "my-listener.default.listeners.example.com.",
+                ResponseCode::NoError,
+            ),
+        ] {
+            let response = respond(&zone, &query(name, RecordType::AAAA), MAX_UDP_MESSAGE_LEN);
+            assert_eq!(response.response_code(), response_code, "{name}");
+            assert!(response.answers().is_empty(), "{name}");
+            assert_eq!(
+                response.name_servers(),
+                [Record::from_rdata(origin.clone(), 30, expected_soa.clone())],
+                "{name}"
+            );
+        }
+
+        let response = respond(
+            &zone,
+            &query("listeners.example.com.", RecordType::SOA),
+            MAX_UDP_MESSAGE_LEN,
+        );
+        assert_eq!(
+            response.answers(),
+            [Record::from_rdata(origin, 30, expected_soa)]
+        );
+    }
+
+    #[test]
+    fn refuse_names_outside_of_zone() {
+        let zone = zone([]);
+        let response = respond(
+            &zone,
+            &query("example.com.", RecordType::A),
+            MAX_UDP_MESSAGE_LEN,
+        );
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(response.name_servers().is_empty());
+    }
+
+    #[test]
+    fn oversized_responses_are_truncated() {
+        let zone = zone([listener((0..100).map(|i| Ipv4Addr::new(10, 0, 0, i)))]);
+        let request = query("my-listener.default.listeners.example.com.", RecordType::A);
+        let response = respond(&zone, &request, MAX_UDP_MESSAGE_LEN);
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+        assert_eq!(response.queries(), request.queries());
+
+        // Retrying over TCP returns the full response
+        let response = respond(&zone, &request, u16::MAX.into());
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 100);
+    }
+
+    #[test]
+    fn reject_invalid_queries() {
+        let zone = zone([]);
+        let error_response = |request: &[u8]| {
+            Message::from_vec(&zone.respond(request, MAX_UDP_MESSAGE_LEN)?)
+                .ok()
+                .map(|response| (response.id(), response.response_code()))
+        };
+
+        // Not even the header can be read, so there is no ID to respond to
+        assert_eq!(error_response(&[0, 1, 2]), None);
+        // The header claims a question that is missing
+        let mut request = query("example.com.", RecordType::A).to_vec().unwrap();
+        request.truncate(Header::len());
+        assert_eq!(error_response(&request), Some((42, ResponseCode::FormErr)));
+
+        let mut request = query("example.com.", RecordType::A);
+        request.set_op_code(OpCode::Update);
+        assert_eq!(
+            error_response(&request.to_vec().unwrap()),
+            Some((42, ResponseCode::NotImp))
+        );
+
+        let mut request = query("example.com.", RecordType::A);
+        request.add_query(Query::query(
+            Name::from_ascii("example.org.").unwrap(),
+            RecordType::A,
+        ));
+        assert_eq!(
+            error_response(&request.to_vec().unwrap()),
+            Some((42, ResponseCode::FormErr))
+        );
+
+        // Replying to responses could cause loops
+        let mut request = query("example.com.", RecordType::A);
+        request.set_message_type(MessageType::Response);
+        assert_eq!(error_response(&request.to_vec().unwrap()), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Maps DNS names to the records served for them, based on [`listener::v1alpha1::Listener`] statuses.
+
+use std::{collections::BTreeSet, net::IpAddr};
+
+use hickory_proto::rr::{
+    Name, RData, RecordType,
+    rdata::{A, AAAA, CNAME, SRV},
+};
+use stackable_operator::crd::listener;
+
+/// The protocol of Listener ports that do not specify one explicitly, matching Kubernetes' default.
+const DEFAULT_PORT_PROTOCOL: &str = "TCP";
+
+/// A DNS name within the served zone that could refer to a Listener.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecordName<'a> {
+    /// `<listener>.<namespace>.<zone>`
+    Listener {
+        listener_name: &'a str,
+        namespace: &'a str,
+    },
+
+    /// `_<port>._<protocol>.<listener>.<namespace>.<zone>`
+    Port {
+        port_name: &'a str,
+        protocol: &'a str,
+        listener_name: &'a str,
+        namespace: &'a str,
+    },
+}
+
+impl<'a> RecordName<'a> {
+    /// Parses a (lowercase) name, relative to the zone origin.
+    ///
+    /// Returns [`None`] if the name cannot refer to any Listener.
+    pub fn parse(relative_name: &'a str) -> Option<Self> {
+        let labels = relative_name.split('.').collect::<Vec<_>>();
+        match labels.as_slice() {
+            [listener_name, namespace] => Some(Self::Listener {
+                listener_name,
+                namespace,
+            }),
+            [port_name, protocol, listener_name, namespace] => Some(Self::Port {
+                port_name: port_name.strip_prefix('_')?,
+                protocol: protocol.strip_prefix('_')?,
+                listener_name,
+                namespace,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn listener_name(&self) -> &'a str {
+        match self {
+            Self::Listener { listener_name, .. } | Self::Port { listener_name, .. } => {
+                listener_name
+            }
+        }
+    }
+
+    pub fn namespace(&self) -> &'a str {
+        match self {
+            Self::Listener { namespace, .. } | Self::Port { namespace, .. } => namespace,
+        }
+    }
+}
+
+/// Parses `name` as a fully qualified DNS name.
+///
+/// Returns [`None`] if `name` can't be encoded in a DNS message (such as if it has labels longer than 63 bytes).
+pub fn parse_name(name: &str) -> Option<Name> {
+    let mut name = Name::from_ascii(name).ok()?;
+    name.set_fqdn(true);
+    Some(name)
+}
+
+/// Strips the zone `origin` from `name`.
+///
+/// Returns [`None`] if `name` is not within the zone, or an empty string for the zone apex.
+pub fn relative_to_origin<'a>(name: &'a str, origin: &str) -> Option<&'a str> {
+    if name == origin {
+        Some("")
+    } else {
+        name.strip_suffix(origin)?.strip_suffix('.')
+    }
+}
+
+/// Calculates the records for `name`, which must refer to `listener`.
+///
+/// `listener_dns_name` is the fully qualified name of the Listener's [`RecordName::Listener`], which is used as the
+/// `SRV` target for IP addresses.
+///
+/// Returns [`None`] if `name` does not exist (such as when the Listener has no such port), or an empty list if it
+/// exists but has no records of the requested type.
+pub fn listener_records(
+    listener: &listener::v1alpha1::Listener,
+    name: &RecordName,
+    query_type: RecordType,
+    listener_dns_name: &str,
+) -> Option<Vec<RData>> {
+    let ingress_addresses = listener
+        .status
+        .as_ref()
+        .and_then(|status| status.ingress_addresses.as_deref())
+        .unwrap_or_default();
+    match name {
+        RecordName::Listener { .. } => {
+            let ips = ingress_addresses
+                .iter()
+                .filter(|ingress| ingress.address_type == listener::v1alpha1::AddressType::Ip)
+                .filter_map(|ingress| ingress.address.parse::<IpAddr>().ok())
+                .collect::<BTreeSet<_>>();
+            if !ips.is_empty() {
+                return Some(
+                    ips.into_iter()
+                        .filter_map(|ip| match (ip, query_type) {
+                            (IpAddr::V4(ip), RecordType::A | RecordType::ANY) => {
+                                Some(RData::A(A(ip)))
+                            }
+                            (IpAddr::V6(ip), RecordType::AAAA | RecordType::ANY) => {
+                                Some(RData::AAAA(AAAA(ip)))
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                );
+            }
+
+            // Hostnames can only be aliased, and a name with a CNAME record may not have any other records
+            let hostname = ingress_addresses.iter().find_map(|ingress| {
+                if ingress.address_type != listener::v1alpha1::AddressType::Hostname
+                    // Avoid pointing the name at itself, in case it is also published as a hostname (by ExternalDNS)
+                    || ingress
+                        .address
+                        .trim_end_matches('.')
+                        .eq_ignore_ascii_case(listener_dns_name)
+                {
+                    return None;
+                }
+                // Skip hostnames that can't be encoded, rather than failing the whole response
+                parse_name(&ingress.address)
+            });
+            Some(
+                hostname
+                    .map(|hostname| RData::CNAME(CNAME(hostname)))
+                    .into_iter()
+                    .collect(),
+            )
+        }
+
+        RecordName::Port {
+            port_name,
+            protocol,
+            ..
+        } => {
+            listener.spec.ports.iter().flatten().find(|port| {
+                port.name == *port_name
+                    && port
+                        .protocol
+                        .as_deref()
+                        .unwrap_or(DEFAULT_PORT_PROTOCOL)
+                        .eq_ignore_ascii_case(protocol)
+            })?;
+            if !matches!(query_type, RecordType::SRV | RecordType::ANY) {
+                return Some(Vec::new());
+            }
+            Some(
+                ingress_addresses
+                    .iter()
+                    .filter_map(|ingress| {
+                        let port = u16::try_from(*ingress.ports.get(*port_name)?).ok()?;
+                        let target = match ingress.address_type {
+                            listener::v1alpha1::AddressType::Ip => listener_dns_name,
+                            listener::v1alpha1::AddressType::Hostname => &ingress.address,
+                        };
+                        Some((port, parse_name(target)?))
+                    })
+                    // IP addresses all share the same target, so deduplicate them
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .map(|(port, target)| RData::SRV(SRV::new(0, 0, port, target)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn fqdn(name: &str) -> Name {
+        parse_name(name).unwrap()
+    }
+
+    fn listener(
+        addresses: &[(&str, listener::v1alpha1::AddressType)],
+    ) -> listener::v1alpha1::Listener {
+        listener::v1alpha1::Listener {
+            metadata: Default::default(),
+            spec: listener::v1alpha1::ListenerSpec {
+                ports: Some(vec![listener::v1alpha1::ListenerPort {
+                    name: "http".to_string(),
+                    port: 80,
+                    protocol: None,
+                }]),
+                ..Default::default()
+            },
+            status: Some(listener::v1alpha1::ListenerStatus {
+                service_name: None,
+                ingress_addresses: Some(
+                    addresses
+                        .iter()
+                        .map(
+                            |(address, address_type)| listener::v1alpha1::ListenerIngress {
+                                address: address.to_string(),
+                                address_type: *address_type,
+                                ports: [("http".to_string(), 30080)].into(),
+                            },
+                        )
+                        .collect(),
+                ),
+                node_ports: None,
+            }),
+        }
+    }
+
+    const LISTENER_DNS_NAME: &str = "my-listener.default.listeners.example.com";
+
+    #[test]
+    fn parse_names() {
+        let origin = "listeners.example.com";
+        assert_eq!(
+            relative_to_origin("listeners.example.com", origin),
+            Some("")
+        );
+        assert_eq!(relative_to_origin("example.com", origin), None);
+        assert_eq!(
+            relative_to_origin("otherlisteners.example.com", origin),
+            None
+        );
+        assert_eq!(
+            relative_to_origin(LISTENER_DNS_NAME, origin),
+            Some("my-listener.default")
+        );
+
+        assert_eq!(
+            RecordName::parse("my-listener.default"),
+            Some(RecordName::Listener {
+                listener_name: "my-listener",
+                namespace: "default",
+            })
+        );
+        assert_eq!(
+            RecordName::parse("_http._tcp.my-listener.default"),
+            Some(RecordName::Port {
+                port_name: "http",
+                protocol: "tcp",
+                listener_name: "my-listener",
+                namespace: "default",
+            })
+        );
+        assert_eq!(RecordName::parse(""), None);
+        assert_eq!(RecordName::parse("default"), None);
+        assert_eq!(RecordName::parse("http.tcp.my-listener.default"), None);
+    }
+
+    #[test]
+    fn ip_addresses_are_served_as_address_records() {
+        let listener = listener(&[
+            ("10.0.0.1", listener::v1alpha1::AddressType::Ip),
+            ("fd00::1", listener::v1alpha1::AddressType::Ip),
+            (
+                "node-0.example.com",
+                listener::v1alpha1::AddressType::Hostname,
+            ),
+        ]);
+        let name = RecordName::parse("my-listener.default").unwrap();
+        assert_eq!(
+            listener_records(&listener, &name, RecordType::A, LISTENER_DNS_NAME),
+            Some(vec![RData::A(A(Ipv4Addr::new(10, 0, 0, 1)))])
+        );
+        assert_eq!(
+            listener_records(&listener, &name, RecordType::AAAA, LISTENER_DNS_NAME),
+            Some(vec![RData::AAAA(AAAA(Ipv6Addr::new(
+                0xfd00, 0, 0, 0, 0, 0, 0, 1
+            )))])
+        );
+        assert_eq!(
+            listener_records(&listener, &name, RecordType::SRV, LISTENER_DNS_NAME),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn hostnames_are_served_as_cname() {
+        // Labels may not be longer than 63 bytes, so this hostname can't be served
+        let invalid_hostname = format!("{}.example.com", "a".repeat(64));
+        let listener = listener(&[
+            (LISTENER_DNS_NAME, listener::v1alpha1::AddressType::Hostname),
+            (&invalid_hostname, listener::v1alpha1::AddressType::Hostname),
+            ("lb.example.com", listener::v1alpha1::AddressType::Hostname),
+        ]);
+        let name = RecordName::parse("my-listener.default").unwrap();
+        assert_eq!(
+            listener_records(&listener, &name, RecordType::A, LISTENER_DNS_NAME),
+            Some(vec![RData::CNAME(CNAME(fqdn("lb.example.com")))])
+        );
+    }
+
+    #[test]
+    fn ports_are_served_as_srv() {
+        let listener = listener(&[
+            ("10.0.0.1", listener::v1alpha1::AddressType::Ip),
+            ("10.0.0.2", listener::v1alpha1::AddressType::Ip),
+            ("lb.example.com", listener::v1alpha1::AddressType::Hostname),
+        ]);
+        assert_eq!(
+            listener_records(
+                &listener,
+                &RecordName::parse("_http._tcp.my-listener.default").unwrap(),
+                RecordType::SRV,
+                LISTENER_DNS_NAME
+            ),
+            Some(vec![
+                RData::SRV(SRV::new(0, 0, 30080, fqdn("lb.example.com"))),
+                RData::SRV(SRV::new(0, 0, 30080, fqdn(LISTENER_DNS_NAME))),
+            ])
+        );
+        assert_eq!(
+            listener_records(
+                &listener,
+                &RecordName::parse("_http._udp.my-listener.default").unwrap(),
+                RecordType::SRV,
+                LISTENER_DNS_NAME
+            ),
+            None
+        );
+        assert_eq!(
+            listener_records(
+                &listener,
+                &RecordName::parse("_https._tcp.my-listener.default").unwrap(),
+                RecordType::SRV,
+                LISTENER_DNS_NAME
+            ),
+            None
+        );
+    }
+}
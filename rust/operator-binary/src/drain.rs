@@ -0,0 +1,243 @@
+//! Migrates pinned listener volumes off a Node ahead of planned maintenance.
+//!
+//! Lists the listener volumes that are pinned to the Node (along with the Pods using them, and the addresses that will
+//! change), and optionally releases them so that they are pinned to another Node (see [`pinned_volumes`]).
+
+use std::collections::BTreeSet;
+
+use snafu::{ResultExt, Snafu};
+use stackable_operator::{
+    crd::listener,
+    k8s_openapi::{
+        api::core::v1::{Node, PersistentVolume},
+        apimachinery::pkg::apis::meta::v1::LabelSelector,
+    },
+    kube::{ResourceExt, runtime::reflector::ObjectRef},
+    utils::cluster_info::KubernetesClusterInfoOptions,
+};
+
+use crate::{
+    OPERATOR_KEY,
+    csi_server::controller::{NamespacePods, is_listener_volume},
+    listener_controller::{PV_LABEL_LISTENER_NAME, PV_LABEL_LISTENER_NAMESPACE},
+    pinned_volumes::{self, PinnedClaim, PodRemoval, pinned_node_name},
+    reachability::{ProbeTarget, probe_targets},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct DrainArguments {
+    /// The Node to migrate pinned listener volumes off.
+    node: String,
+
+    /// Release the pinned volumes, rather than only listing them.
+    ///
+    /// Releasing a volume deletes its PersistentVolumeClaim and evicts the Pods using it, so that the workload's
+    /// controller recreates them, and the volume is pinned to another Node. Claims that the workload doesn't recreate
+    /// are skipped.
+    /// The Node must be cordoned first, so that the volumes are not pinned to it again.
+    #[arg(long)]
+    release: bool,
+
+    #[command(flatten)]
+    cluster_info: KubernetesClusterInfoOptions,
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("failed to initialize Kubernetes client"))]
+    InitializeClient {
+        source: stackable_operator::client::Error,
+    },
+
+    #[snafu(display("failed to get Node {node:?}"))]
+    GetNode {
+        source: stackable_operator::client::Error,
+        node: String,
+    },
+
+    #[snafu(display(
+        "Node {node:?} must be cordoned before releasing its volumes (hint: run `kubectl cordon {node}`)"
+    ))]
+    NodeNotCordoned { node: String },
+
+    #[snafu(display("failed to list PersistentVolumes"))]
+    ListPersistentVolumes {
+        source: stackable_operator::client::Error,
+    },
+
+    #[snafu(display("failed to get {listener}"))]
+    GetListener {
+        source: stackable_operator::client::Error,
+        listener: ObjectRef<listener::v1alpha1::Listener>,
+    },
+
+    #[snafu(display("failed to find the claim of {pv}"))]
+    FindClaim {
+        source: pinned_volumes::Error,
+        pv: ObjectRef<PersistentVolume>,
+    },
+
+    #[snafu(display("failed to release {pv}"))]
+    Release {
+        source: pinned_volumes::Error,
+        pv: ObjectRef<PersistentVolume>,
+    },
+}
+
+/// Lists (and optionally releases) the listener volumes that are pinned to [`DrainArguments::node`].
+pub async fn run(args: DrainArguments) -> Result<(), Error> {
+    use error::*;
+    let DrainArguments {
+        node: node_name,
+        release,
+        cluster_info,
+    } = args;
+    let client = stackable_operator::client::initialize_operator(
+        Some(OPERATOR_KEY.to_string()),
+        &cluster_info,
+    )
+    .await
+    .context(InitializeClientSnafu)?;
+
+    // The Node may already have been deleted
+    let node = client
+        .get_opt::<Node>(&node_name, &())
+        .await
+        .with_context(|_| GetNodeSnafu { node: &node_name })?;
+    let is_cordoned = node.as_ref().is_none_or(|node| {
+        node.spec
+            .as_ref()
+            .and_then(|spec| spec.unschedulable)
+            .unwrap_or(false)
+    });
+    if release && !is_cordoned {
+        return NodeNotCordonedSnafu { node: node_name }.fail();
+    }
+    let node_addresses = node
+        .iter()
+        .filter_map(|node| node.status.as_ref()?.addresses.as_ref())
+        .flatten()
+        .map(|addr| addr.address.as_str())
+        .collect::<BTreeSet<_>>();
+
+    let pvs = client
+        .list_with_label_selector::<PersistentVolume>(&(), &LabelSelector::default())
+        .await
+        .context(ListPersistentVolumesSnafu)?
+        .into_iter()
+        .filter(|pv| is_listener_volume(pv) && pinned_node_name(pv) == Some(node_name.as_str()))
+        .collect::<Vec<_>>();
+    if pvs.is_empty() {
+        println!("No listener volumes are pinned to Node {node_name:?}");
+        return Ok(());
+    }
+
+    let mut claims = Vec::new();
+    let mut pods = NamespacePods::default();
+    for pv in pvs {
+        let pv_ref = ObjectRef::from_obj(&pv);
+        let Some(claim) = PinnedClaim::for_pv(&client, &pv, &mut pods)
+            .await
+            .with_context(|_| FindClaimSnafu { pv: pv_ref.clone() })?
+        else {
+            println!("{pv_ref}: not bound to a PersistentVolumeClaim, skipping");
+            continue;
+        };
+
+        println!("{pvc}", pvc = ObjectRef::from_obj(&claim.pvc));
+        println!("  Pods: {pods}", pods = claim.pod_names());
+        let is_releasable = claim.is_releasing() || claim.is_recreated_by_workload();
+        if !is_releasable {
+            println!(
+                "  Cannot be released: the PersistentVolumeClaim is not recreated by its workload, \
+                only claims of StatefulSets and ephemeral volumes can be released"
+            );
+        }
+        let labels = pv.labels();
+        if let Some((ns, name)) = labels
+            .get(PV_LABEL_LISTENER_NAMESPACE)
+            .zip(labels.get(PV_LABEL_LISTENER_NAME))
+        {
+            let listener_ref = ObjectRef::<listener::v1alpha1::Listener>::new(name).within(ns);
+            let listener = client
+                .get_opt::<listener::v1alpha1::Listener>(name, ns)
+                .await
+                .with_context(|_| GetListenerSnafu {
+                    listener: listener_ref.clone(),
+                })?;
+            println!("  Listener: {listener_ref}");
+            let changed_endpoints = listener
+                .as_ref()
+                .and_then(|listener| listener.status.as_ref())
+                .map(|status| endpoints_on_node(&probe_targets(status), &node_addresses))
+                .unwrap_or_default();
+            if changed_endpoints.is_empty() {
+                println!("  Addresses that will change: unknown");
+            } else {
+                println!(
+                    "  Addresses that will change: {}",
+                    changed_endpoints.join(", ")
+                );
+            }
+        }
+        if is_releasable {
+            claims.push((pv_ref, claim));
+        }
+    }
+
+    if !release {
+        println!();
+        println!("Rerun with --release to move these volumes to another Node");
+        return Ok(());
+    }
+    for (pv_ref, claim) in claims {
+        // The Node is still healthy, so the Pods can be evicted gracefully (respecting their PodDisruptionBudgets)
+        claim
+            .release(&client, PodRemoval::Evict)
+            .await
+            .with_context(|_| ReleaseSnafu { pv: pv_ref })?;
+        println!("Released {pvc}", pvc = ObjectRef::from_obj(&claim.pvc));
+    }
+    Ok(())
+}
+
+/// The endpoints (out of `targets`) that are served by the Node with the addresses `node_addresses`.
+fn endpoints_on_node(targets: &[ProbeTarget], node_addresses: &BTreeSet<&str>) -> Vec<String> {
+    targets
+        .iter()
+        .filter(|target| node_addresses.contains(target.address.as_str()))
+        .map(ProbeTarget::endpoint)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_on_node_only_include_node_addresses() {
+        let target = |address: &str, port| ProbeTarget {
+            address: address.to_string(),
+            port,
+        };
+        let targets = [
+            target("10.0.0.1", 30000),
+            target("node-1.example.com", 30000),
+            target("10.0.0.2", 30000),
+            target("fd00::1", 30001),
+        ];
+        assert_eq!(
+            endpoints_on_node(
+                &targets,
+                &["10.0.0.1", "node-1.example.com", "fd00::1"].into()
+            ),
+            [
+                "10.0.0.1:30000",
+                "node-1.example.com:30000",
+                "[fd00::1]:30001"
+            ]
+        );
+        assert!(endpoints_on_node(&targets, &BTreeSet::new()).is_empty());
+    }
+}
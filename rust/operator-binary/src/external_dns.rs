@@ -0,0 +1,480 @@
+//! Publishes DNS records for [`listener::v1alpha1::Listener`]s using [ExternalDNS](https://kubernetes-sigs.github.io/external-dns/).
+//!
+//! ExternalDNS is configured per [`listener::v1alpha1::ListenerClass`], using the annotations
+//! [`LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_HOSTNAME`] and [`LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_MODE`].
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use serde::Serialize;
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
+use stackable_operator::{
+    builder::meta::ObjectMetaBuilder,
+    crd::listener,
+    kube::{
+        Api, ResourceExt,
+        api::{
+            ApiResource, DeleteParams, DynamicObject, GroupVersionKind, Patch, PatchParams,
+            Preconditions,
+        },
+    },
+    kvp::Labels,
+};
+
+use crate::{
+    FIELD_MANAGER,
+    listener_controller::{ListenerAddress, is_owned_by_listener},
+};
+
+/// The DNS name template for a [`listener::v1alpha1::ListenerClass`]'s Listeners.
+///
+/// Setting this annotation enables ExternalDNS integration for the ListenerClass. See [`render_hostname`] for the
+/// supported placeholders.
+pub const LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_HOSTNAME: &str =
+    "listeners.stackable.tech/external-dns-hostname";
+
+/// How the DNS records should be published, see [`ExternalDnsMode`].
+pub const LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_MODE: &str =
+    "listeners.stackable.tech/external-dns-mode";
+
+/// The Service annotation that ExternalDNS' `service` source reads the desired DNS name from.
+pub const SERVICE_ANNOTATION_EXTERNAL_DNS_HOSTNAME: &str =
+    "external-dns.alpha.kubernetes.io/hostname";
+
+/// Marks the Services of Listeners that have a `DNSEndpoint` (see [`apply_dns_endpoint`]), containing its name.
+///
+/// This lets the controller know when [`delete_stale_dns_endpoint`] needs to run, without looking up
+/// `DNSEndpoint`s for all other Listeners.
+pub const SERVICE_ANNOTATION_DNS_ENDPOINT: &str = "listeners.stackable.tech/dns-endpoint";
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display(
+        "invalid ExternalDNS mode {mode:?} in annotation {LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_MODE:?}"
+    ))]
+    InvalidMode {
+        source: strum::ParseError,
+        mode: String,
+    },
+
+    #[snafu(display("unterminated placeholder in DNS name template {template:?}"))]
+    UnterminatedPlaceholder { template: String },
+
+    #[snafu(display("unknown placeholder {{{placeholder}}} in DNS name template"))]
+    UnknownPlaceholder { placeholder: String },
+
+    #[snafu(display(
+        "placeholder {{node}} is only supported for NodePort Listeners when using the dns-endpoint mode"
+    ))]
+    NodePlaceholderUnavailable,
+
+    #[snafu(display("object has no name"))]
+    NoName,
+
+    #[snafu(display("object has no namespace"))]
+    NoNamespace,
+
+    #[snafu(display("object has no uid"))]
+    NoUid,
+
+    #[snafu(display("failed to build DNSEndpoint metadata"))]
+    BuildDnsEndpointMetadata {
+        source: stackable_operator::builder::meta::Error,
+    },
+
+    #[snafu(display("failed to get DNSEndpoint {name:?}"))]
+    GetDnsEndpoint {
+        source: stackable_operator::kube::Error,
+        name: String,
+    },
+
+    #[snafu(display("DNSEndpoint {name:?} already exists and is not owned by the Listener"))]
+    ForeignDnsEndpoint { name: String },
+
+    #[snafu(display("failed to apply DNSEndpoint {name:?}"))]
+    ApplyDnsEndpoint {
+        source: stackable_operator::kube::Error,
+        name: String,
+    },
+
+    #[snafu(display("failed to delete DNSEndpoint {name:?}"))]
+    DeleteDnsEndpoint {
+        source: stackable_operator::kube::Error,
+        name: String,
+    },
+}
+
+/// How DNS records are handed over to ExternalDNS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ExternalDnsMode {
+    /// Annotate the Listener's Service, and let ExternalDNS' `service` source publish its addresses.
+    #[default]
+    ServiceAnnotation,
+
+    /// Create a `DNSEndpoint` object (for ExternalDNS' `crd` source) containing the addresses published in the
+    /// Listener's status.
+    DnsEndpoint,
+}
+
+/// The ExternalDNS configuration of a [`listener::v1alpha1::ListenerClass`].
+#[derive(Debug, Clone)]
+pub struct ExternalDnsConfig {
+    pub mode: ExternalDnsMode,
+    pub hostname_template: String,
+}
+
+impl ExternalDnsConfig {
+    /// Reads the ExternalDNS configuration from `listener_class`'s annotations.
+    ///
+    /// Returns [`None`] if ExternalDNS is not enabled for `listener_class`.
+    pub fn from_listener_class(
+        listener_class: &listener::v1alpha1::ListenerClass,
+    ) -> Result<Option<Self>, Error> {
+        use error::*;
+        let annotations = listener_class.annotations();
+        let Some(hostname_template) =
+            annotations.get(LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_HOSTNAME)
+        else {
+            return Ok(None);
+        };
+        let mode = match annotations.get(LISTENER_CLASS_ANNOTATION_EXTERNAL_DNS_MODE) {
+            Some(mode) => mode.parse().context(InvalidModeSnafu { mode })?,
+            None => ExternalDnsMode::default(),
+        };
+        Ok(Some(Self {
+            mode,
+            hostname_template: hostname_template.clone(),
+        }))
+    }
+
+    /// Calculates the DNS records to publish for `listener`, grouped by DNS name.
+    ///
+    /// Only DNS names that have at least one address are returned.
+    pub fn records<'a>(
+        &self,
+        listener: &listener::v1alpha1::Listener,
+        addresses: &'a [ListenerAddress],
+    ) -> Result<BTreeMap<String, Vec<&'a ListenerAddress>>, Error> {
+        use error::*;
+        let listener_name = listener.metadata.name.as_deref().context(NoNameSnafu)?;
+        let namespace = listener
+            .metadata
+            .namespace
+            .as_deref()
+            .context(NoNamespaceSnafu)?;
+        let mut records = BTreeMap::<String, Vec<_>>::new();
+        for address in addresses {
+            let node_name = match self.mode {
+                ExternalDnsMode::ServiceAnnotation => None,
+                ExternalDnsMode::DnsEndpoint => address.node_name.as_deref(),
+            };
+            let hostname =
+                render_hostname(&self.hostname_template, listener_name, namespace, node_name)?;
+            records.entry(hostname).or_default().push(address);
+        }
+        Ok(records)
+    }
+}
+
+/// Renders a DNS name from `template`.
+///
+/// The following placeholders are supported:
+///
+/// - `{listener}`: The name of the Listener
+/// - `{namespace}`: The namespace of the Listener
+/// - `{node}`: The name of the Node backing the address (only for NodePort Listeners, when using [`ExternalDnsMode::DnsEndpoint`])
+pub fn render_hostname(
+    template: &str,
+    listener_name: &str,
+    namespace: &str,
+    node_name: Option<&str>,
+) -> Result<String, Error> {
+    use error::*;
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(placeholder_start) = rest.find('{') {
+        rendered.push_str(&rest[..placeholder_start]);
+        let placeholder_len = rest[placeholder_start..]
+            .find('}')
+            .context(UnterminatedPlaceholderSnafu { template })?;
+        let placeholder = &rest[placeholder_start + 1..placeholder_start + placeholder_len];
+        rendered.push_str(match placeholder {
+            "listener" => listener_name,
+            "namespace" => namespace,
+            "node" => node_name.context(NodePlaceholderUnavailableSnafu)?,
+            _ => return UnknownPlaceholderSnafu { placeholder }.fail(),
+        });
+        rest = &rest[placeholder_start + placeholder_len + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+fn dns_endpoint_api_resource() -> ApiResource {
+    ApiResource::from_gvk_with_plural(
+        &GroupVersionKind::gvk("externaldns.k8s.io", "v1alpha1", "DNSEndpoint"),
+        "dnsendpoints",
+    )
+}
+
+/// A record set in a `DNSEndpoint`.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoint<'a> {
+    dns_name: &'a str,
+    record_type: &'static str,
+    targets: Vec<&'a str>,
+}
+
+/// Converts `records` into the record sets of a `DNSEndpoint`.
+///
+/// A DNS name with a CNAME record may not have any other records, and the CNAME may only have a single target.
+/// So names that have any IP addresses are only published as A/AAAA records, and names that only have hostnames
+/// are published as a CNAME to the (alphabetically) first hostname.
+fn dns_endpoints<'a>(records: &'a BTreeMap<String, Vec<&'a ListenerAddress>>) -> Vec<Endpoint<'a>> {
+    let mut endpoints = Vec::new();
+    for (dns_name, addresses) in records {
+        let mut v4_targets = Vec::new();
+        let mut v6_targets = Vec::new();
+        let mut hostname_targets = Vec::new();
+        for address in addresses {
+            let targets = match address.address_type {
+                listener::v1alpha1::AddressType::Hostname => &mut hostname_targets,
+                listener::v1alpha1::AddressType::Ip => match address.address.parse::<IpAddr>() {
+                    Ok(IpAddr::V6(_)) => &mut v6_targets,
+                    Ok(IpAddr::V4(_)) | Err(_) => &mut v4_targets,
+                },
+            };
+            targets.push(address.address.as_str());
+        }
+
+        if v4_targets.is_empty() && v6_targets.is_empty() {
+            if let Some(&cname_target) = hostname_targets.iter().min() {
+                if hostname_targets.len() > 1 {
+                    tracing::info!(
+                        dns_name,
+                        cname_target,
+                        ?hostname_targets,
+                        "DNS name has multiple hostnames, but a CNAME can only point at one of them"
+                    );
+                }
+                endpoints.push(Endpoint {
+                    dns_name,
+                    record_type: "CNAME",
+                    targets: vec![cname_target],
+                });
+            }
+            continue;
+        }
+        for (record_type, targets) in [("A", v4_targets), ("AAAA", v6_targets)] {
+            if !targets.is_empty() {
+                endpoints.push(Endpoint {
+                    dns_name,
+                    record_type,
+                    targets,
+                });
+            }
+        }
+    }
+    endpoints
+}
+
+/// Applies a `DNSEndpoint` object containing `records`, named after and owned by `listener`.
+///
+/// Fails if a `DNSEndpoint` with the same name already exists, but is not owned by `listener`.
+pub async fn apply_dns_endpoint(
+    client: &stackable_operator::client::Client,
+    listener: &listener::v1alpha1::Listener,
+    labels: Labels,
+    records: &BTreeMap<String, Vec<&ListenerAddress>>,
+) -> Result<(), Error> {
+    use error::*;
+    let name = listener.metadata.name.as_deref().context(NoNameSnafu)?;
+    let namespace = listener
+        .metadata
+        .namespace
+        .as_deref()
+        .context(NoNamespaceSnafu)?;
+    let listener_uid = listener.metadata.uid.as_deref().context(NoUidSnafu)?;
+
+    let api_resource = dns_endpoint_api_resource();
+    let api =
+        Api::<DynamicObject>::namespaced_with(client.as_kube_client(), namespace, &api_resource);
+    // Applying with force would otherwise take over DNSEndpoints that were created by someone else
+    if let Some(existing) = api
+        .get_opt(name)
+        .await
+        .context(GetDnsEndpointSnafu { name })?
+    {
+        ensure!(
+            is_owned_by_listener(existing.owner_references(), listener_uid),
+            ForeignDnsEndpointSnafu { name }
+        );
+    }
+
+    let mut dns_endpoint = DynamicObject::new(name, &api_resource)
+        .within(namespace)
+        .data(serde_json::json!({
+            "spec": {
+                "endpoints": dns_endpoints(records),
+            },
+        }));
+    dns_endpoint.metadata = ObjectMetaBuilder::new()
+        .name(name)
+        .namespace(namespace)
+        .ownerreference_from_resource(listener, Some(true), Some(true))
+        .context(BuildDnsEndpointMetadataSnafu)?
+        .with_labels(labels)
+        .build();
+
+    api.patch(
+        name,
+        &PatchParams::apply(FIELD_MANAGER).force(),
+        &Patch::Apply(&dns_endpoint),
+    )
+    .await
+    .context(ApplyDnsEndpointSnafu { name })?;
+    Ok(())
+}
+
+/// Deletes the `DNSEndpoint` applied by [`apply_dns_endpoint`] for `listener`, if there is one.
+///
+/// This cleans up after ListenerClasses that stop using [`ExternalDnsMode::DnsEndpoint`] (or Listeners that
+/// switch to another ListenerClass), since the `DNSEndpoint` is otherwise only garbage collected along with
+/// the Listener.
+pub async fn delete_stale_dns_endpoint(
+    client: &stackable_operator::client::Client,
+    listener: &listener::v1alpha1::Listener,
+) -> Result<(), Error> {
+    use error::*;
+    let name = listener.metadata.name.as_deref().context(NoNameSnafu)?;
+    let namespace = listener
+        .metadata
+        .namespace
+        .as_deref()
+        .context(NoNamespaceSnafu)?;
+    let listener_uid = listener.metadata.uid.as_deref().context(NoUidSnafu)?;
+
+    let api = Api::<DynamicObject>::namespaced_with(
+        client.as_kube_client(),
+        namespace,
+        &dns_endpoint_api_resource(),
+    );
+    let existing = match api.get_opt(name).await {
+        Ok(existing) => existing,
+        // The DNSEndpoint CRD is not installed, so there can't be any DNSEndpoints to clean up
+        Err(stackable_operator::kube::Error::Api(err)) if err.code == 404 => None,
+        Err(err) => return Err(err).context(GetDnsEndpointSnafu { name }),
+    };
+    let Some(existing) = existing else {
+        return Ok(());
+    };
+    if !is_owned_by_listener(existing.owner_references(), listener_uid) {
+        return Ok(());
+    }
+    tracing::info!(dns_endpoint = name, "deleting stale DNSEndpoint");
+    api.delete(
+        name,
+        &DeleteParams {
+            // Don't delete the DNSEndpoint if it has been replaced in the meantime
+            preconditions: Some(Preconditions {
+                uid: existing.metadata.uid.clone(),
+                resource_version: None,
+            }),
+            ..DeleteParams::default()
+        },
+    )
+    .await
+    .context(DeleteDnsEndpointSnafu { name })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_hostname_replaces_placeholders() {
+        assert_eq!(
+            render_hostname(
+                "{listener}.{namespace}.listeners.example.com",
+                "my-listener",
+                "my-ns",
+                None
+            )
+            .unwrap(),
+            "my-listener.my-ns.listeners.example.com"
+        );
+        assert_eq!(
+            render_hostname(
+                "{node}-{listener}.example.com",
+                "my-listener",
+                "my-ns",
+                Some("node-0")
+            )
+            .unwrap(),
+            "node-0-my-listener.example.com"
+        );
+        assert_eq!(
+            render_hostname("static.example.com", "my-listener", "my-ns", None).unwrap(),
+            "static.example.com"
+        );
+    }
+
+    #[test]
+    fn render_hostname_rejects_invalid_placeholders() {
+        assert!(render_hostname("{pod}.example.com", "my-listener", "my-ns", None).is_err());
+        assert!(render_hostname("{listener.example.com", "my-listener", "my-ns", None).is_err());
+        assert!(render_hostname("{node}.example.com", "my-listener", "my-ns", None).is_err());
+    }
+
+    #[test]
+    fn dns_endpoints_publish_at_most_one_cname_per_name() {
+        let address = |address: &str, address_type| ListenerAddress {
+            address: address.to_string(),
+            address_type,
+            node_name: None,
+        };
+        let hostname_a = address(
+            "b.lb.example.com",
+            listener::v1alpha1::AddressType::Hostname,
+        );
+        let hostname_b = address(
+            "a.lb.example.com",
+            listener::v1alpha1::AddressType::Hostname,
+        );
+        let ip_v4 = address("10.0.0.1", listener::v1alpha1::AddressType::Ip);
+        let ip_v6 = address("fd00::1", listener::v1alpha1::AddressType::Ip);
+        let records = BTreeMap::from([
+            (
+                "hostnames.example.com".to_string(),
+                vec![&hostname_a, &hostname_b],
+            ),
+            (
+                "mixed.example.com".to_string(),
+                vec![&hostname_a, &ip_v4, &ip_v6],
+            ),
+        ]);
+        assert_eq!(
+            dns_endpoints(&records),
+            vec![
+                Endpoint {
+                    dns_name: "hostnames.example.com",
+                    record_type: "CNAME",
+                    targets: vec!["a.lb.example.com"],
+                },
+                Endpoint {
+                    dns_name: "mixed.example.com",
+                    record_type: "A",
+                    targets: vec!["10.0.0.1"],
+                },
+                Endpoint {
+                    dns_name: "mixed.example.com",
+                    record_type: "AAAA",
+                    targets: vec!["fd00::1"],
+                },
+            ]
+        );
+    }
+}
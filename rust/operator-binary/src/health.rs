@@ -0,0 +1,261 @@
+//! Serves the liveness (`/healthz`) and readiness (`/readyz`) of the operator over HTTP, for use by Kubernetes probes.
+
+use std::{
+    collections::BTreeSet,
+    future::{Future, IntoFuture},
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{Router, extract::State, http::StatusCode, routing::get};
+use snafu::{ResultExt, Snafu};
+use tokio::net::{TcpListener, UnixStream};
+
+use crate::utils::error::error_full_message;
+
+/// How long the CSI socket may take to accept a connection before the operator is considered to be unhealthy.
+const CSI_SOCKET_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("failed to bind TCP socket to {address}"))]
+    BindTcp {
+        source: io::Error,
+        address: SocketAddr,
+    },
+
+    #[snafu(display("failed to serve health checks"))]
+    Serve { source: io::Error },
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum LivenessError {
+    #[snafu(display("failed to connect to the CSI socket at {path:?}"))]
+    ConnectCsiSocket { source: io::Error, path: PathBuf },
+
+    #[snafu(display("timed out connecting to the CSI socket at {path:?}"))]
+    ConnectCsiSocketTimeout { path: PathBuf },
+}
+
+/// A condition that must be met before the operator reports itself as ready.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ReadinessCheck {
+    /// The CSI socket has been bound.
+    CsiSocket,
+
+    /// The initial list of Listeners has been received (or, for the node driver, the volumes that were already
+    /// published have been recovered, and for the DNS server, its sockets have been bound).
+    InitialSync,
+
+    /// The webhook server is ready, and has reconciled the CRDs.
+    WebhookServer,
+}
+
+/// Tracks which [`ReadinessCheck`]s are still pending.
+#[derive(Clone, Debug)]
+pub struct Readiness {
+    pending: Arc<Mutex<BTreeSet<ReadinessCheck>>>,
+}
+
+impl Readiness {
+    /// Creates a tracker that is ready once all `checks` have been completed.
+    pub fn new(checks: impl IntoIterator<Item = ReadinessCheck>) -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(checks.into_iter().collect())),
+        }
+    }
+
+    /// Marks `check` as completed.
+    pub fn complete(&self, check: ReadinessCheck) {
+        let mut pending = self.pending.lock().expect("readiness lock was poisoned");
+        if pending.remove(&check) {
+            tracing::info!(%check, remaining = pending.len(), "readiness check completed");
+        }
+    }
+
+    /// Whether `check` has not been completed yet.
+    fn is_pending(&self, check: ReadinessCheck) -> bool {
+        self.pending
+            .lock()
+            .expect("readiness lock was poisoned")
+            .contains(&check)
+    }
+
+    /// The checks that have not been completed yet.
+    fn pending(&self) -> Vec<ReadinessCheck> {
+        self.pending
+            .lock()
+            .expect("readiness lock was poisoned")
+            .iter()
+            .copied()
+            .collect()
+    }
+}
+
+/// The state that the health checks are answered from.
+#[derive(Clone, Debug)]
+struct Health {
+    readiness: Readiness,
+
+    /// The path of the CSI socket, which must accept connections for the operator to be considered alive.
+    ///
+    /// Not set for run modes that don't serve CSI, which are considered alive as long as the health checks are served.
+    csi_endpoint: Option<PathBuf>,
+}
+
+impl Health {
+    /// Checks whether the operator is still able to serve CSI requests.
+    async fn check_liveness(&self) -> Result<(), LivenessError> {
+        use liveness_error::*;
+        let Some(csi_endpoint) = &self.csi_endpoint else {
+            return Ok(());
+        };
+        // The socket is only bound once the operator has started up
+        if self.readiness.is_pending(ReadinessCheck::CsiSocket) {
+            return Ok(());
+        }
+        match tokio::time::timeout(
+            CSI_SOCKET_CONNECT_TIMEOUT,
+            UnixStream::connect(csi_endpoint),
+        )
+        .await
+        {
+            Ok(stream) => stream
+                .map(drop)
+                .context(ConnectCsiSocketSnafu { path: csi_endpoint }),
+            Err(_) => ConnectCsiSocketTimeoutSnafu { path: csi_endpoint }.fail(),
+        }
+    }
+}
+
+/// Serves `/healthz` and `/readyz` on `listen_address` until `shutdown_signal` completes.
+///
+/// The operator is considered alive as long as the CSI socket at `csi_endpoint` (if any) accepts connections, and ready
+/// once all checks of `readiness` have been completed.
+pub async fn run<F>(
+    listen_address: SocketAddr,
+    readiness: Readiness,
+    csi_endpoint: Option<PathBuf>,
+    shutdown_signal: F,
+) -> Result<(), Error>
+where
+    F: Future<Output = ()>,
+{
+    use error::*;
+    let listener = TcpListener::bind(listen_address)
+        .await
+        .context(BindTcpSnafu {
+            address: listen_address,
+        })?;
+    tracing::info!(%listen_address, "serving health checks");
+    let router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(Health {
+            readiness,
+            csi_endpoint,
+        });
+    tokio::select! {
+        result = axum::serve(listener, router).into_future() => result.context(ServeSnafu),
+        () = shutdown_signal => Ok(()),
+    }
+}
+
+async fn healthz(State(health): State<Health>) -> (StatusCode, String) {
+    match health.check_liveness().await {
+        Ok(()) => (StatusCode::OK, "ok\n".to_string()),
+        Err(err) => {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "liveness check failed"
+            );
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("{}\n", error_full_message(&err)),
+            )
+        }
+    }
+}
+
+async fn readyz(State(health): State<Health>) -> (StatusCode, String) {
+    let pending = health.readiness.pending();
+    if pending.is_empty() {
+        (StatusCode::OK, "ok\n".to_string())
+    } else {
+        let pending = pending
+            .iter()
+            .map(ReadinessCheck::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("waiting for: {pending}\n"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(readiness: &Readiness, csi_endpoint: Option<PathBuf>) -> State<Health> {
+        State(Health {
+            readiness: readiness.clone(),
+            csi_endpoint,
+        })
+    }
+
+    #[tokio::test]
+    async fn readyz_waits_for_all_checks() {
+        let readiness = Readiness::new([ReadinessCheck::CsiSocket, ReadinessCheck::InitialSync]);
+        let state = || health(&readiness, Some(PathBuf::from("/nonexistent/csi.sock")));
+        assert_eq!(
+            readyz(state()).await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "waiting for: csi-socket, initial-sync\n".to_string()
+            )
+        );
+        readiness.complete(ReadinessCheck::CsiSocket);
+        readiness.complete(ReadinessCheck::InitialSync);
+        assert_eq!(readyz(state()).await, (StatusCode::OK, "ok\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn healthz_requires_csi_socket_to_accept_connections() {
+        let csi_endpoint = std::env::temp_dir().join(format!(
+            "listener-operator-health-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&csi_endpoint);
+        let readiness = Readiness::new([ReadinessCheck::CsiSocket, ReadinessCheck::InitialSync]);
+        let state = || health(&readiness, Some(csi_endpoint.clone()));
+
+        // The socket has not been bound yet
+        assert_eq!(healthz(state()).await.0, StatusCode::OK);
+        readiness.complete(ReadinessCheck::CsiSocket);
+        assert_eq!(healthz(state()).await.0, StatusCode::SERVICE_UNAVAILABLE);
+
+        // Liveness doesn't wait for the other readiness checks
+        let csi_listener = tokio::net::UnixListener::bind(&csi_endpoint).unwrap();
+        assert_eq!(healthz(state()).await, (StatusCode::OK, "ok\n".to_string()));
+
+        drop(csi_listener);
+        assert_eq!(healthz(state()).await.0, StatusCode::SERVICE_UNAVAILABLE);
+        let _ = std::fs::remove_file(&csi_endpoint);
+    }
+
+    #[tokio::test]
+    async fn healthz_without_csi_socket_is_always_alive() {
+        let readiness = Readiness::new([ReadinessCheck::InitialSync]);
+        assert_eq!(
+            healthz(health(&readiness, None)).await,
+            (StatusCode::OK, "ok\n".to_string())
+        );
+    }
+}
@@ -9,6 +9,7 @@ use futures::{
     StreamExt,
     future::{try_join, try_join_all},
 };
+use serde::{Deserialize, de::IntoDeserializer};
 use snafu::{OptionExt, ResultExt, Snafu};
 #[cfg(doc)]
 use stackable_operator::k8s_openapi::api::core::v1::Pod;
@@ -42,15 +43,27 @@ use strum::IntoStaticStr;
 use crate::{
     APP_NAME, OPERATOR_KEY,
     csi_server::node::NODE_TOPOLOGY_LABEL_HOSTNAME,
-    utils::address::{AddressCandidates, node_primary_addresses},
+    external_dns::{
+        self, ExternalDnsConfig, ExternalDnsMode, SERVICE_ANNOTATION_DNS_ENDPOINT,
+        SERVICE_ANNOTATION_EXTERNAL_DNS_HOSTNAME,
+    },
+    health,
+    utils::{
+        address::{AddressCandidates, node_primary_addresses},
+        dns::{AddressResolver, MAX_CONCURRENT_LOOKUPS},
+    },
 };
 
 const OPERATOR_NAME: &str = "listeners.stackable.tech";
 const CONTROLLER_NAME: &str = "listener";
 pub const FULL_CONTROLLER_NAME: &str = concatcp!(CONTROLLER_NAME, '.', OPERATOR_NAME);
 
-pub async fn run<F>(client: stackable_operator::client::Client, shutdown_signal: F)
-where
+pub async fn run<F>(
+    client: stackable_operator::client::Client,
+    address_resolver: AddressResolver,
+    readiness: health::Readiness,
+    shutdown_signal: F,
+) where
     F: Future<Output = ()> + Send + Sync + 'static,
 {
     let controller = controller::Controller::new(
@@ -58,6 +71,15 @@ where
         watcher::Config::default(),
     );
     let listener_store = controller.store();
+    let initial_sync = {
+        let listener_store = listener_store.clone();
+        async move {
+            // The store is only dropped if the controller stops before the initial sync completes
+            if listener_store.wait_until_ready().await.is_ok() {
+                readiness.complete(health::ReadinessCheck::InitialSync);
+            }
+        }
+    };
     let event_recorder = Arc::new(Recorder::new(
         client.as_kube_client(),
         Reporter {
@@ -65,7 +87,7 @@ where
             instance: None,
         },
     ));
-    controller
+    let controller = controller
         .owns(
             client.get_all_api::<DeserializeGuard<Service>>(),
             watcher::Config::default(),
@@ -124,7 +146,14 @@ where
             },
         )
         .graceful_shutdown_on(shutdown_signal)
-        .run(reconcile, error_policy, Arc::new(Ctx { client }))
+        .run(
+            reconcile,
+            error_policy,
+            Arc::new(Ctx {
+                client,
+                address_resolver,
+            }),
+        )
         // We can let the reporting happen in the background
         .for_each_concurrent(
             16, // concurrency limit
@@ -137,12 +166,21 @@ where
                         .await;
                 }
             },
-        )
-        .await;
+        );
+    futures::join!(controller, initial_sync);
+}
+
+/// An address that a [`listener::v1alpha1::Listener`] can be reached on.
+pub struct ListenerAddress {
+    pub address: String,
+    pub address_type: listener::v1alpha1::AddressType,
+    /// The [`Node`] that the address belongs to, for [`NodePort`](`listener::v1alpha1::ServiceType::NodePort`) Listeners.
+    pub node_name: Option<String>,
 }
 
 pub struct Ctx {
     pub client: stackable_operator::client::Client,
+    pub address_resolver: AddressResolver,
 }
 
 #[derive(Debug, Snafu, IntoStaticStr)]
@@ -179,6 +217,11 @@ pub enum Error {
         source: ListenerMountedPodLabelError,
     },
 
+    #[snafu(display("failed to resolve Listener's preferred address type"))]
+    ListenerPreferredAddressType {
+        source: ListenerPreferredAddressTypeError,
+    },
+
     #[snafu(display("failed to get PersistentVolumes for Listener"))]
     GetListenerPvs {
         source: stackable_operator::client::Error,
@@ -195,6 +238,18 @@ pub enum Error {
         listener_class: ObjectRef<listener::v1alpha1::ListenerClass>,
     },
 
+    #[snafu(display("failed to resolve the node selector of {listener_class}"))]
+    ListenerClassNodeSelector {
+        source: ListenerClassNodeSelectorError,
+        listener_class: ObjectRef<listener::v1alpha1::ListenerClass>,
+    },
+
+    #[snafu(display("failed to publish DNS records as configured by {listener_class}"))]
+    ExternalDns {
+        source: external_dns::Error,
+        listener_class: ObjectRef<listener::v1alpha1::ListenerClass>,
+    },
+
     #[snafu(display("failed to build cluster resource labels"))]
     BuildClusterResourcesLabels {
         source: stackable_operator::kvp::LabelError,
@@ -254,12 +309,21 @@ impl ReconcilerError for Error {
             Self::NoListenerClass => None,
             Self::ListenerPvSelector { source: _ } => None,
             Self::ListenerPodSelector { source: _ } => None,
+            Self::ListenerPreferredAddressType { source: _ } => None,
             Self::GetListenerPvs { source: _ } => None,
             Self::ValidateListenerLabels { source: _ } => None,
             Self::ValidateListenerClassAnnotations {
                 source: _,
                 listener_class,
             } => Some(listener_class.clone().erase()),
+            Self::ListenerClassNodeSelector {
+                source: _,
+                listener_class,
+            } => Some(listener_class.clone().erase()),
+            Self::ExternalDns {
+                source: _,
+                listener_class,
+            } => Some(listener_class.clone().erase()),
             Self::BuildClusterResourcesLabels { source: _ } => None,
             Self::GetObject { source: _, obj } => Some(obj.clone()),
             Self::BuildListenerOwnerRef { .. } => None,
@@ -308,6 +372,10 @@ pub async fn reconcile(
         .with_context(|_| GetObjectSnafu {
             obj: ObjectRef::<listener::v1alpha1::ListenerClass>::new(listener_class_name).erase(),
         })?;
+    let external_dns_config =
+        ExternalDnsConfig::from_listener_class(&listener_class).context(ExternalDnsSnafu {
+            listener_class: ObjectRef::from_obj(&listener_class),
+        })?;
     let pod_ports = listener
         .spec
         .ports
@@ -333,6 +401,25 @@ pub async fn reconcile(
         // Deduplicate ports by (protocol, name)
         .collect::<BTreeMap<_, ServicePort>>();
     let svc_name = listener.metadata.name.clone().context(NoNameSnafu)?;
+    let uses_dns_endpoint = external_dns_config
+        .as_ref()
+        .is_some_and(|config| config.mode == ExternalDnsMode::DnsEndpoint);
+    let external_dns_service_annotations = match &external_dns_config {
+        Some(config) if config.mode == ExternalDnsMode::ServiceAnnotation => {
+            vec![(
+                SERVICE_ANNOTATION_EXTERNAL_DNS_HOSTNAME,
+                external_dns::render_hostname(&config.hostname_template, &svc_name, ns, None)
+                    .context(ExternalDnsSnafu {
+                        listener_class: ObjectRef::from_obj(&listener_class),
+                    })?,
+            )]
+        }
+        // The DNSEndpoint is named after the Listener (and its Service)
+        Some(config) if config.mode == ExternalDnsMode::DnsEndpoint => {
+            vec![(SERVICE_ANNOTATION_DNS_ENDPOINT, svc_name.clone())]
+        }
+        _ => Vec::new(),
+    };
     let mut pod_selector = listener.spec.extra_pod_selector_labels.clone();
     pod_selector.extend([listener_mounted_pod_label(listener).context(ListenerPodSelectorSnafu)?]);
 
@@ -394,6 +481,13 @@ pub async fn reconcile(
                     },
                 )?,
             )
+            .with_annotations(
+                Annotations::try_from_iter(external_dns_service_annotations).context(
+                    ValidateListenerClassAnnotationsSnafu {
+                        listener_class: ObjectRef::from_obj(&listener_class),
+                    },
+                )?,
+            )
             .build(),
         spec: Some(ServiceSpec {
             // We explicitly match here and do not implement `ToString` as there might be more (non vanilla k8s Service
@@ -432,21 +526,43 @@ pub async fn reconcile(
     // in the namespace, hijack its selector/ports and cascade-delete it via the owner reference.
     // Refuse the reconciliation if a same-named Service already exists but is not owned by us.
     let listener_uid = listener.metadata.uid.as_deref().context(NoUidSnafu)?;
-    ensure_existing_service_is_not_foreign(&ctx.client, &svc_name, ns, listener_uid, &svc_ref)
-        .await?;
+    let existing_svc =
+        ensure_existing_service_is_not_foreign(&ctx.client, &svc_name, ns, listener_uid, &svc_ref)
+            .await?;
+
+    // The Service records whether the Listener had a DNSEndpoint, so it is only looked up once the Listener stops
+    // using one. It is deleted before the Service is updated, so that the deletion is retried if it fails.
+    if !uses_dns_endpoint
+        && existing_svc.as_ref().is_some_and(|svc| {
+            svc.annotations()
+                .contains_key(SERVICE_ANNOTATION_DNS_ENDPOINT)
+        })
+    {
+        external_dns::delete_stale_dns_endpoint(&ctx.client, listener)
+            .await
+            .context(ExternalDnsSnafu {
+                listener_class: ObjectRef::from_obj(&listener_class),
+            })?;
+    }
 
     let svc = cluster_resources
         .add(&ctx.client, svc)
         .await
         .context(ApplyServiceSnafu { svc: svc_ref })?;
-    let preferred_address_type = listener_class.spec.resolve_preferred_address_type();
+    let preferred_address_type = listener_preferred_address_type_override(listener)
+        .context(ListenerPreferredAddressTypeSnafu)?
+        .unwrap_or_else(|| listener_class.spec.resolve_preferred_address_type());
 
     let nodes: Vec<Node>;
-    let kubernetes_service_fqdn: String;
-    let addresses: Vec<(&str, listener::v1alpha1::AddressType)>;
+    let addresses: Vec<ListenerAddress>;
     let ports: BTreeMap<String, i32>;
     match listener_class.spec.service_type {
         listener::v1alpha1::ServiceType::NodePort => {
+            let node_selector = listener_class_node_selector(&listener_class).context(
+                ListenerClassNodeSelectorSnafu {
+                    listener_class: ObjectRef::from_obj(&listener_class),
+                },
+            )?;
             let node_names =
                 node_names_for_nodeport_listener(&ctx.client, listener, ns, &svc_name).await?;
             nodes = try_join_all(node_names.iter().map(|node_name| async {
@@ -457,11 +573,24 @@ pub async fn reconcile(
                         obj: ObjectRef::<Node>::new(node_name).erase(),
                     })
             }))
-            .await?;
-            addresses = nodes
-                .iter()
-                .flat_map(|node| node_primary_addresses(node).pick(preferred_address_type))
-                .collect::<Vec<_>>();
+            .await?
+            .into_iter()
+            // Nodes outside of the selector are not expected to be reachable by clients, so don't advertise them
+            // (this should only happen for volumes that were provisioned before the selector was configured)
+            .filter(|node| {
+                node_selector
+                    .as_ref()
+                    .is_none_or(|selector| node_matches_selector(node.labels(), selector))
+            })
+            .collect();
+            addresses = pick_addresses(
+                &ctx.address_resolver,
+                nodes
+                    .iter()
+                    .map(|node| (Some(node.name_any()), node_primary_addresses(node))),
+                preferred_address_type,
+            )
+            .await;
             ports = svc
                 .spec
                 .as_ref()
@@ -472,19 +601,24 @@ pub async fn reconcile(
                 .collect();
         }
         listener::v1alpha1::ServiceType::LoadBalancer => {
-            addresses = svc
-                .status
-                .iter()
-                .flat_map(|ss| ss.load_balancer.as_ref()?.ingress.as_ref())
-                .flatten()
-                .flat_map(|ingress| {
-                    AddressCandidates {
-                        ip: ingress.ip.as_deref(),
-                        hostname: ingress.hostname.as_deref(),
-                    }
-                    .pick(preferred_address_type)
-                })
-                .collect();
+            addresses = pick_addresses(
+                &ctx.address_resolver,
+                svc.status
+                    .iter()
+                    .flat_map(|ss| ss.load_balancer.as_ref()?.ingress.as_ref())
+                    .flatten()
+                    .map(|ingress| {
+                        (
+                            None,
+                            AddressCandidates {
+                                ip: ingress.ip.as_deref(),
+                                hostname: ingress.hostname.as_deref(),
+                            },
+                        )
+                    }),
+                preferred_address_type,
+            )
+            .await;
             ports = svc
                 .spec
                 .as_ref()
@@ -502,14 +636,18 @@ pub async fn reconcile(
                     .iter()
                     .flat_map(|s| &s.cluster_ips)
                     .flatten()
-                    .map(|addr| (&**addr, listener::v1alpha1::AddressType::Ip))
+                    .map(|addr| ListenerAddress {
+                        address: addr.clone(),
+                        address_type: listener::v1alpha1::AddressType::Ip,
+                        node_name: None,
+                    })
                     .collect::<Vec<_>>(),
                 listener::v1alpha1::AddressType::Hostname => {
-                    kubernetes_service_fqdn = format!("{svc_name}.{ns}.svc.{cluster_domain}");
-                    vec![(
-                        &kubernetes_service_fqdn,
-                        listener::v1alpha1::AddressType::Hostname,
-                    )]
+                    vec![ListenerAddress {
+                        address: format!("{svc_name}.{ns}.svc.{cluster_domain}"),
+                        address_type: listener::v1alpha1::AddressType::Hostname,
+                        node_name: None,
+                    }]
                 }
             };
             ports = svc
@@ -533,18 +671,50 @@ pub async fn reconcile(
         spec: listener::v1alpha1::ListenerSpec::default(),
         status: None,
     };
+    let external_dns_records = external_dns_config
+        .as_ref()
+        .map(|config| config.records(listener, &addresses))
+        .transpose()
+        .context(ExternalDnsSnafu {
+            listener_class: ObjectRef::from_obj(&listener_class),
+        })?
+        .unwrap_or_default();
+    if uses_dns_endpoint {
+        external_dns::apply_dns_endpoint(
+            &ctx.client,
+            listener,
+            cluster_resources
+                .get_required_labels()
+                .context(BuildClusterResourcesLabelsSnafu)?,
+            &external_dns_records,
+        )
+        .await
+        .context(ExternalDnsSnafu {
+            listener_class: ObjectRef::from_obj(&listener_class),
+        })?;
+    }
+    // The published DNS names are also valid addresses for the Listener, but only list them after the
+    // "primary" addresses since they are only resolvable once ExternalDNS has caught up
+    let external_dns_addresses = external_dns_records
+        .into_keys()
+        .map(|dns_name| ListenerAddress {
+            address: dns_name,
+            address_type: listener::v1alpha1::AddressType::Hostname,
+            node_name: None,
+        })
+        .collect::<Vec<_>>();
+
     let listener_status = listener::v1alpha1::ListenerStatus {
         service_name: svc.metadata.name,
         ingress_addresses: Some(
             addresses
                 .into_iter()
-                .map(
-                    |(address, address_type)| listener::v1alpha1::ListenerIngress {
-                        address: address.to_string(),
-                        address_type,
-                        ports: ports.clone(),
-                    },
-                )
+                .chain(external_dns_addresses)
+                .map(|address| listener::v1alpha1::ListenerIngress {
+                    address: address.address,
+                    address_type: address.address_type,
+                    ports: ports.clone(),
+                })
                 .collect(),
         ),
         node_ports: (listener_class.spec.service_type == listener::v1alpha1::ServiceType::NodePort)
@@ -573,12 +743,37 @@ pub fn error_policy<T>(_obj: Arc<T>, error: &Error, _ctx: Arc<Ctx>) -> controlle
     }
 }
 
+/// Picks the preferred address of each entity (such as a [`Node`] or load balancer ingress point), skipping entities
+/// that have no usable address.
+async fn pick_addresses<'a>(
+    address_resolver: &AddressResolver,
+    candidates: impl IntoIterator<Item = (Option<String>, AddressCandidates<'a>)>,
+    preferred_address_type: listener::v1alpha1::AddressType,
+) -> Vec<ListenerAddress> {
+    futures::stream::iter(candidates)
+        .map(|(node_name, candidates)| async move {
+            let (address, address_type) = address_resolver
+                .pick(candidates, preferred_address_type)
+                .await?;
+            Some(ListenerAddress {
+                address,
+                address_type,
+                node_name,
+            })
+        })
+        // Each pick may require DNS lookups, don't flood the resolver for Listeners with many Nodes
+        .buffered(MAX_CONCURRENT_LOOKUPS)
+        .filter_map(futures::future::ready)
+        .collect()
+        .await
+}
+
 /// Returns `true` if `existing_owners` contain a controller [`OwnerReference`] that points to the
 /// [`listener::v1alpha1::Listener`] with the given UID. Used to ensure we only overwrite output
 /// Services that we previously created ourselves; refusing otherwise prevents the Listener
 /// primitive from being abused to clobber foreign same-named Services via the operator's elevated
 /// cluster-wide write permissions.
-fn is_owned_by_listener(existing_owners: &[OwnerReference], listener_uid: &str) -> bool {
+pub fn is_owned_by_listener(existing_owners: &[OwnerReference], listener_uid: &str) -> bool {
     let listener_kind = <listener::v1alpha1::Listener as Resource>::kind(&());
     existing_owners.iter().any(|owner| {
         owner.controller == Some(true)
@@ -591,13 +786,15 @@ fn is_owned_by_listener(existing_owners: &[OwnerReference], listener_uid: &str)
 /// Looks up a pre-existing Service with the same name/namespace as the Listener output and fails if
 /// it exists but is not controlled by this Listener. See [`is_owned_by_listener`] for the security
 /// rationale.
+///
+/// Returns the existing Service, if any.
 async fn ensure_existing_service_is_not_foreign(
     client: &stackable_operator::client::Client,
     name: &str,
     namespace: &str,
     listener_uid: &str,
     svc_ref: &ObjectRef<Service>,
-) -> Result<()> {
+) -> Result<Option<Service>> {
     let existing =
         client
             .get_opt::<Service>(name, namespace)
@@ -605,7 +802,7 @@ async fn ensure_existing_service_is_not_foreign(
             .context(GetExistingServiceSnafu {
                 svc: svc_ref.clone(),
             })?;
-    if let Some(existing) = existing {
+    if let Some(existing) = &existing {
         let owners = existing.metadata.owner_references.as_deref().unwrap_or(&[]);
         if !is_owned_by_listener(owners, listener_uid) {
             return RefuseToOverwriteForeignServiceSnafu {
@@ -614,7 +811,7 @@ async fn ensure_existing_service_is_not_foreign(
             .fail();
         }
     }
-    Ok(())
+    Ok(existing)
 }
 
 /// Lists the names of the [`Node`]s backing this [`listener::v1alpha1::Listener`].
@@ -653,16 +850,7 @@ async fn node_names_for_nodeport_listener(
     )
     .await?;
 
-    let pv_node_names = pvs
-        .into_iter()
-        .filter_map(|pv| pv.spec?.node_affinity?.required)
-        .flat_map(|affinity| affinity.node_selector_terms)
-        .filter_map(|terms| terms.match_expressions)
-        .flatten()
-        .filter(|expr| expr.key == NODE_TOPOLOGY_LABEL_HOSTNAME && expr.operator == "In")
-        .filter_map(|expr| expr.values)
-        .flatten()
-        .collect::<BTreeSet<_>>();
+    let pv_node_names = pvs.iter().flat_map(pv_node_names).collect::<BTreeSet<_>>();
 
     // Old objects that haven't been mounted before the PV lookup mechanism was added will
     // not have the correct labels, so we also look up using Endpoints.
@@ -692,6 +880,26 @@ async fn node_names_for_nodeport_listener(
     Ok(node_names)
 }
 
+/// Lists the names of the [`Node`]s that `pv` is backed by.
+///
+/// PVs that were published before [`PV_ANNOTATION_LISTENER_NODE`] was added only record their Node in their node
+/// affinity, which is only possible if they are pinned to a single Node (rather than a zone).
+fn pv_node_names(pv: &PersistentVolume) -> Vec<String> {
+    if let Some(node_name) = pv.annotations().get(PV_ANNOTATION_LISTENER_NODE) {
+        return vec![node_name.clone()];
+    }
+    pv.spec
+        .iter()
+        .filter_map(|spec| spec.node_affinity.as_ref()?.required.as_ref())
+        .flat_map(|affinity| &affinity.node_selector_terms)
+        .filter_map(|terms| terms.match_expressions.as_ref())
+        .flatten()
+        .filter(|expr| expr.key == NODE_TOPOLOGY_LABEL_HOSTNAME && expr.operator == "In")
+        .filter_map(|expr| expr.values.clone())
+        .flatten()
+        .collect()
+}
+
 #[derive(Snafu, Debug)]
 #[snafu(module)]
 pub enum ListenerMountedPodLabelError {
@@ -731,8 +939,14 @@ pub enum ListenerPersistentVolumeLabelError {
     NoNamespace,
 }
 
-const PV_LABEL_LISTENER_NAMESPACE: &str = "listeners.stackable.tech/listener-namespace";
-const PV_LABEL_LISTENER_NAME: &str = "listeners.stackable.tech/listener-name";
+/// Label on [`PersistentVolume`]s that identifies the namespace of their [`listener::v1alpha1::Listener`].
+pub const PV_LABEL_LISTENER_NAMESPACE: &str = "listeners.stackable.tech/listener-namespace";
+/// Label on [`PersistentVolume`]s that identifies the name of their [`listener::v1alpha1::Listener`].
+pub const PV_LABEL_LISTENER_NAME: &str = "listeners.stackable.tech/listener-name";
+/// Annotation on [`PersistentVolume`]s that identifies the [`Node`] that they were last published on.
+///
+/// This is an annotation rather than a label, since Node names can be longer than label values.
+pub const PV_ANNOTATION_LISTENER_NODE: &str = "listeners.stackable.tech/node-name";
 
 /// A label that identifies which [`listener::v1alpha1::Listener`] corresponds to a given [`PersistentVolume`].
 pub fn listener_persistent_volume_label(
@@ -756,9 +970,214 @@ pub fn listener_persistent_volume_label(
     .into())
 }
 
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum ListenerPreferredAddressTypeError {
+    #[snafu(display(
+        "invalid address type in annotation {LISTENER_ANNOTATION_PREFERRED_ADDRESS_TYPE:?}"
+    ))]
+    InvalidAnnotation { source: serde::de::value::Error },
+}
+
+/// Annotation that overrides the [`listener::v1alpha1::ListenerClass`]'s preferred address type for a single
+/// [`listener::v1alpha1::Listener`].
+pub const LISTENER_ANNOTATION_PREFERRED_ADDRESS_TYPE: &str =
+    "listeners.stackable.tech/preferred-address-type";
+
+/// The [`listener::v1alpha1::AddressType`] that `listener` requests for itself, if any.
+///
+/// Callers should fall back to the [`listener::v1alpha1::ListenerClass`]'s preference if this returns [`None`].
+pub fn listener_preferred_address_type_override(
+    listener: &listener::v1alpha1::Listener,
+) -> Result<Option<listener::v1alpha1::AddressType>, ListenerPreferredAddressTypeError> {
+    use listener_preferred_address_type_error::*;
+    listener
+        .annotations()
+        .get(LISTENER_ANNOTATION_PREFERRED_ADDRESS_TYPE)
+        .map(|address_type| {
+            listener::v1alpha1::AddressType::deserialize(
+                IntoDeserializer::<serde::de::value::Error>::into_deserializer(
+                    address_type.as_str(),
+                ),
+            )
+            .context(InvalidAnnotationSnafu)
+        })
+        .transpose()
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum ListenerClassNodeSelectorError {
+    #[snafu(display(
+        "invalid requirement {requirement:?} in annotation {LISTENER_CLASS_ANNOTATION_NODE_SELECTOR:?}, expected key=value"
+    ))]
+    InvalidRequirement { requirement: String },
+}
+
+/// Annotation that restricts which [`Node`]s may serve the traffic of a [`listener::v1alpha1::ListenerClass`].
+///
+/// Only takes effect for [`listener::v1alpha1::ServiceType::NodePort`] classes.
+/// Formatted as a comma-separated list of `key=value` label requirements, all of which must match.
+pub const LISTENER_CLASS_ANNOTATION_NODE_SELECTOR: &str = "listeners.stackable.tech/node-selector";
+
+/// The Node labels that `listener_class` requires of the Nodes that serve its traffic, if any.
+pub fn listener_class_node_selector(
+    listener_class: &listener::v1alpha1::ListenerClass,
+) -> Result<Option<BTreeMap<String, String>>, ListenerClassNodeSelectorError> {
+    listener_class
+        .annotations()
+        .get(LISTENER_CLASS_ANNOTATION_NODE_SELECTOR)
+        .map(|selector| parse_node_selector(selector))
+        .transpose()
+        .map(|selector| selector.filter(|selector| !selector.is_empty()))
+}
+
+fn parse_node_selector(
+    selector: &str,
+) -> Result<BTreeMap<String, String>, ListenerClassNodeSelectorError> {
+    use listener_class_node_selector_error::*;
+    selector
+        .split(',')
+        .map(str::trim)
+        .filter(|requirement| !requirement.is_empty())
+        .map(|requirement| {
+            let (key, value) = requirement
+                .split_once('=')
+                .filter(|(key, _)| !key.trim().is_empty())
+                .context(InvalidRequirementSnafu { requirement })?;
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Whether a Node with the labels `node_labels` matches all requirements of `selector`.
+pub fn node_matches_selector(
+    node_labels: &BTreeMap<String, String>,
+    selector: &BTreeMap<String, String>,
+) -> bool {
+    selector
+        .iter()
+        .all(|(key, value)| node_labels.get(key) == Some(value))
+}
+
 #[cfg(test)]
 mod tests {
+    use stackable_operator::k8s_openapi::api::core::v1::{
+        NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, PersistentVolumeSpec,
+        VolumeNodeAffinity,
+    };
+
     use super::*;
+    use crate::csi_server::node::NODE_TOPOLOGY_LABEL_ZONE;
+
+    fn listener_with_annotations<'a>(
+        annotations: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> listener::v1alpha1::Listener {
+        listener::v1alpha1::Listener {
+            metadata: ObjectMeta {
+                annotations: Some(
+                    annotations
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            spec: listener::v1alpha1::ListenerSpec::default(),
+            status: None,
+        }
+    }
+
+    #[test]
+    fn preferred_address_type_override_is_optional() {
+        let listener = listener_with_annotations([]);
+        assert_eq!(
+            listener_preferred_address_type_override(&listener).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn preferred_address_type_override_is_parsed() {
+        let listener =
+            listener_with_annotations([(LISTENER_ANNOTATION_PREFERRED_ADDRESS_TYPE, "IP")]);
+        assert_eq!(
+            listener_preferred_address_type_override(&listener).unwrap(),
+            Some(listener::v1alpha1::AddressType::Ip)
+        );
+        let listener =
+            listener_with_annotations([(LISTENER_ANNOTATION_PREFERRED_ADDRESS_TYPE, "Hostname")]);
+        assert_eq!(
+            listener_preferred_address_type_override(&listener).unwrap(),
+            Some(listener::v1alpha1::AddressType::Hostname)
+        );
+    }
+
+    #[test]
+    fn invalid_preferred_address_type_override_is_rejected() {
+        let listener = listener_with_annotations([(
+            LISTENER_ANNOTATION_PREFERRED_ADDRESS_TYPE,
+            "HostnameConservative",
+        )]);
+        assert!(listener_preferred_address_type_override(&listener).is_err());
+    }
+
+    #[test]
+    fn node_selector_is_parsed() {
+        assert_eq!(parse_node_selector("").unwrap(), BTreeMap::new());
+        assert_eq!(
+            parse_node_selector("example.com/dmz=true, topology.kubernetes.io/zone=zone-a")
+                .unwrap(),
+            BTreeMap::from([
+                ("example.com/dmz".to_string(), "true".to_string()),
+                (
+                    "topology.kubernetes.io/zone".to_string(),
+                    "zone-a".to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_node_selector_is_rejected() {
+        for selector in ["example.com/dmz", "=true", "example.com/dmz=true,zone"] {
+            assert!(
+                parse_node_selector(selector).is_err(),
+                "{selector:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn node_selector_requires_all_labels() {
+        let selector = [
+            ("example.com/dmz".to_string(), "true".to_string()),
+            ("example.com/rack".to_string(), "1".to_string()),
+        ]
+        .into();
+        let labels = |labels: &[(&str, &str)]| {
+            labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert!(node_matches_selector(
+            &labels(&[
+                ("example.com/dmz", "true"),
+                ("example.com/rack", "1"),
+                ("kubernetes.io/os", "linux"),
+            ]),
+            &selector
+        ));
+        assert!(!node_matches_selector(
+            &labels(&[("example.com/dmz", "true")]),
+            &selector
+        ));
+        assert!(!node_matches_selector(
+            &labels(&[("example.com/dmz", "false"), ("example.com/rack", "1")]),
+            &selector
+        ));
+    }
 
     fn owner_ref(
         api_version: &str,
@@ -841,4 +1260,60 @@ mod tests {
         )];
         assert!(!is_owned_by_listener(&owners, "my-uid"));
     }
+
+    fn pv_pinned_to(
+        key: &str,
+        value: &str,
+        annotations: impl IntoIterator<Item = (&'static str, &'static str)>,
+    ) -> PersistentVolume {
+        PersistentVolume {
+            metadata: ObjectMeta {
+                annotations: Some(
+                    annotations
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(PersistentVolumeSpec {
+                node_affinity: Some(VolumeNodeAffinity {
+                    required: Some(NodeSelector {
+                        node_selector_terms: vec![NodeSelectorTerm {
+                            match_expressions: Some(vec![NodeSelectorRequirement {
+                                key: key.to_string(),
+                                operator: "In".to_string(),
+                                values: Some(vec![value.to_string()]),
+                            }]),
+                            ..Default::default()
+                        }],
+                    }),
+                }),
+                ..Default::default()
+            }),
+            status: None,
+        }
+    }
+
+    #[test]
+    fn node_pinned_pv_is_backed_by_its_node() {
+        let pv = pv_pinned_to(NODE_TOPOLOGY_LABEL_HOSTNAME, "node-1", []);
+        assert_eq!(pv_node_names(&pv), ["node-1"]);
+    }
+
+    #[test]
+    fn zone_pinned_pv_is_backed_by_its_publishing_node() {
+        let pv = pv_pinned_to(
+            NODE_TOPOLOGY_LABEL_ZONE,
+            "zone-a",
+            [(PV_ANNOTATION_LISTENER_NODE, "node-2")],
+        );
+        assert_eq!(pv_node_names(&pv), ["node-2"]);
+    }
+
+    #[test]
+    fn unpublished_zone_pinned_pv_has_no_nodes() {
+        let pv = pv_pinned_to(NODE_TOPOLOGY_LABEL_ZONE, "zone-a", []);
+        assert!(pv_node_names(&pv).is_empty());
+    }
 }
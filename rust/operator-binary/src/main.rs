@@ -1,7 +1,7 @@
 // TODO: Look into how to properly resolve `clippy::result_large_err`.
 // This will need changes in our and upstream error types.
 #![allow(clippy::result_large_err)]
-use std::{os::unix::prelude::FileTypeExt, path::PathBuf};
+use std::{net::SocketAddr, os::unix::prelude::FileTypeExt, path::PathBuf, sync::Arc};
 
 use anyhow::anyhow;
 use clap::Parser;
@@ -9,8 +9,9 @@ use csi_grpc::v1::{
     controller_server::ControllerServer, identity_server::IdentityServer, node_server::NodeServer,
 };
 use csi_server::{
-    controller::ListenerOperatorController, identity::ListenerOperatorIdentity,
-    node::ListenerOperatorNode,
+    controller::ListenerOperatorController,
+    identity::ListenerOperatorIdentity,
+    node::{ListenerOperatorNode, ListenerUpdateNotification},
 };
 use futures::{FutureExt, TryFutureExt, TryStreamExt};
 use stackable_operator::{
@@ -23,19 +24,27 @@ use stackable_operator::{
     },
     eos::EndOfSupportChecker,
     kube::{CustomResourceExt, ResourceExt},
-    shared::yaml::SerializeOptions,
+    shared::{time::Duration, yaml::SerializeOptions},
     telemetry::Tracing,
     utils::signal::{self, SignalWatcher},
 };
-use tokio::sync::oneshot;
 use tokio_stream::wrappers::UnixListenerStream;
-use tonic::transport::Server;
-use utils::unix_stream::{TonicUnixStream, uds_bind_private};
+use tonic::transport::{Server, server::Router};
+use utils::{
+    dns::{AddressResolver, HostnameResolution, NameserverResolver, Resolver, SystemResolver},
+    unix_stream::{TonicUnixStream, uds_bind_private},
+};
 
 use crate::webhooks::conversion::create_webhook_server;
 
 mod csi_server;
+mod dns_server;
+mod drain;
+mod external_dns;
+mod health;
 mod listener_controller;
+mod pinned_volumes;
+mod reachability;
 mod utils;
 mod webhooks;
 
@@ -47,13 +56,29 @@ const FIELD_MANAGER: &str = "listener-operator";
 #[clap(author, version)]
 struct Cli {
     #[clap(subcommand)]
-    cmd: Command<ListenerOperatorRun>,
+    cmd: ListenerOperatorCommand,
+}
+
+#[derive(clap::Subcommand)]
+enum ListenerOperatorCommand {
+    #[command(flatten)]
+    Operator(Command<ListenerOperatorRun>),
+
+    /// List the listener volumes that are pinned to a Node, and optionally move them to other Nodes
+    Drain(drain::DrainArguments),
 }
 
 #[derive(clap::Parser)]
 struct ListenerOperatorRun {
+    /// The path of the CSI socket, required for the CSI run modes.
     #[arg(long, env)]
-    csi_endpoint: PathBuf,
+    csi_endpoint: Option<PathBuf>,
+
+    /// The address to serve the `/healthz` and `/readyz` health checks on over HTTP.
+    ///
+    /// Health checks are not served if not set.
+    #[arg(long, env)]
+    health_listen_address: Option<SocketAddr>,
 
     #[clap(subcommand)]
     mode: RunMode,
@@ -76,13 +101,132 @@ enum RunMode {
     Controller(ControllerArguments),
 
     /// CSI Node Service
-    Node,
+    Node(NodeArguments),
+
+    /// Authoritative DNS server for Listener addresses
+    DnsServer(DnsServerArguments),
 }
 
 #[derive(Debug, clap::Args)]
 struct ControllerArguments {
     #[arg(long, env, default_value_t)]
     listener_class_preset: ListenerClassPreset,
+
+    #[command(flatten)]
+    hostname_resolution: HostnameResolutionArguments,
+
+    /// How often the published addresses of all Listeners are probed for reachability.
+    ///
+    /// Reachability probing is disabled if not set.
+    #[arg(long, env)]
+    reachability_probe_interval: Option<Duration>,
+
+    /// How long each reachability probe may take to connect.
+    #[arg(long, env, default_value = "5s")]
+    reachability_probe_timeout: Duration,
+
+    /// Whether pinned listener volumes are released when the Node that they are pinned to is gone.
+    ///
+    /// Releasing a volume deletes its PersistentVolumeClaim and the Pods using it, so that the volume can be pinned to
+    /// another Node.
+    #[arg(long, env, default_value_t)]
+    pinned_volume_release: pinned_volumes::ReleaseMode,
+
+    /// How long a Node must be NotReady before the volumes pinned to it are released.
+    ///
+    /// Volumes pinned to Nodes that have been deleted are released immediately.
+    #[arg(long, env, default_value = "15m")]
+    pinned_volume_release_grace_period: Duration,
+
+    /// No pinned volumes are released while more than this percentage of Nodes are not Ready.
+    #[arg(long, env, default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pinned_volume_release_max_unavailable_nodes_percent: u8,
+
+    /// The maximum number of pinned volumes that are released per minute.
+    #[arg(long, env, default_value_t = 5)]
+    pinned_volume_release_max_per_minute: usize,
+}
+
+#[derive(Debug, clap::Args)]
+struct NodeArguments {
+    /// How workloads are notified when their listener volumes are updated with new addresses.
+    #[arg(long, env, default_value_t)]
+    listener_update_notification: ListenerUpdateNotification,
+
+    /// Whether listener volumes are backed by a tmpfs, rather than a plain directory on the Node's disk.
+    ///
+    /// Requires the node driver to be privileged, and to propagate mounts back to the host.
+    /// Volumes fall back to plain directories if the tmpfs cannot be mounted.
+    #[arg(long, env)]
+    tmpfs_volumes: bool,
+
+    /// The maximum number of listener volumes that may be mounted on each Node.
+    ///
+    /// Unlimited if not set.
+    #[arg(long, env, value_parser = clap::value_parser!(i64).range(1..))]
+    max_volumes_per_node: Option<i64>,
+
+    /// Node labels that are reported as CSI topology segments (in addition to the zone and region), as a
+    /// comma-separated list of label keys.
+    ///
+    /// Labels that a Node doesn't have are not reported for it.
+    #[arg(long, env, value_delimiter = ',')]
+    node_topology_labels: Vec<String>,
+
+    #[command(flatten)]
+    hostname_resolution: HostnameResolutionArguments,
+
+    /// The kubelet's root directory, which is searched for published volumes when the node driver starts.
+    #[arg(long, env, default_value = "/var/lib/kubelet")]
+    kubelet_dir: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct HostnameResolutionArguments {
+    /// Whether DNS should be used to find or verify hostnames for published addresses.
+    ///
+    /// Only applies to Listeners that prefer hostnames.
+    #[arg(long, env, default_value_t)]
+    hostname_resolution: HostnameResolution,
+
+    /// How long DNS lookups for `--hostname-resolution` are cached for.
+    #[arg(long, env, default_value = "5m")]
+    hostname_resolution_cache_ttl: Duration,
+
+    /// The nameserver to send DNS lookups for `--hostname-resolution` to, over UDP.
+    ///
+    /// The system resolver configuration is used if not set.
+    #[arg(long, env)]
+    hostname_resolution_nameserver: Option<SocketAddr>,
+}
+
+impl HostnameResolutionArguments {
+    fn address_resolver(self) -> AddressResolver {
+        let resolver: Arc<dyn Resolver> = match self.hostname_resolution_nameserver {
+            Some(nameserver) => Arc::new(NameserverResolver::new(nameserver)),
+            None => Arc::new(SystemResolver::default()),
+        };
+        AddressResolver::new(
+            self.hostname_resolution,
+            resolver,
+            self.hostname_resolution_cache_ttl,
+        )
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct DnsServerArguments {
+    /// The DNS zone to serve, Listeners are published as `<listener>.<namespace>.<zone>`.
+    #[arg(long, env)]
+    dns_zone: String,
+
+    /// The address to accept DNS queries on, over both UDP and TCP.
+    #[arg(long, env, default_value = "0.0.0.0:5353")]
+    dns_listen_address: SocketAddr,
+
+    /// How long resolvers may cache served records for.
+    #[arg(long, env, default_value = "30s")]
+    dns_ttl: Duration,
 }
 
 #[derive(Clone, Debug, Default, clap::Parser, strum::Display, strum::EnumString)]
@@ -112,7 +256,7 @@ pub const ENV_VAR_CONSOLE_LOG: &str = "LISTENER_OPERATOR_LOG";
 async fn main() -> anyhow::Result<()> {
     let opts = Cli::parse();
     match opts.cmd {
-        Command::Crd => {
+        ListenerOperatorCommand::Operator(Command::Crd) => {
             ListenerClass::merged_crd(ListenerClassVersion::V1Alpha1)?
                 .print_yaml_schema(built_info::PKG_VERSION, &SerializeOptions::default())?;
             Listener::merged_crd(ListenerVersion::V1Alpha1)?
@@ -120,13 +264,14 @@ async fn main() -> anyhow::Result<()> {
             PodListeners::merged_crd(PodListenersVersion::V1Alpha1)?
                 .print_yaml_schema(built_info::PKG_VERSION, &SerializeOptions::default())?;
         }
-        Command::Run(ListenerOperatorRun {
+        ListenerOperatorCommand::Operator(Command::Run(ListenerOperatorRun {
             operator_environment,
             csi_endpoint,
+            health_listen_address,
             maintenance,
             common,
             mode,
-        }) => {
+        })) => {
             // NOTE (@NickLarsenNZ): Before stackable-telemetry was used:
             // - The console log level was set by `LISTENER_OPERATOR_LOG`, and is now `CONSOLE_LOG` (when using Tracing::pre_configured).
             // - The file log level was (maybe?) set by `LISTENER_OPERATOR_LOG`, and is now set via `FILE_LOG` (when using Tracing::pre_configured).
@@ -160,29 +305,26 @@ async fn main() -> anyhow::Result<()> {
             )
             .await?;
 
-            if csi_endpoint
-                .symlink_metadata()
-                .is_ok_and(|meta| meta.file_type().is_socket())
-            {
-                let _ = std::fs::remove_file(&csi_endpoint);
-            }
-
-            let csi_listener =
-                UnixListenerStream::new(uds_bind_private(csi_endpoint)?).map_ok(TonicUnixStream);
-
-            let csi_server = Server::builder()
-                .add_service(
-                    tonic_reflection::server::Builder::configure()
-                        .include_reflection_service(true)
-                        .register_encoded_file_descriptor_set(csi_grpc::FILE_DESCRIPTOR_SET_BYTES)
-                        .build_v1()?,
-                )
-                .add_service(IdentityServer::new(ListenerOperatorIdentity));
-
             match mode {
                 RunMode::Controller(ControllerArguments {
                     listener_class_preset,
+                    hostname_resolution,
+                    reachability_probe_interval,
+                    reachability_probe_timeout,
+                    pinned_volume_release,
+                    pinned_volume_release_grace_period,
+                    pinned_volume_release_max_unavailable_nodes_percent,
+                    pinned_volume_release_max_per_minute,
                 }) => {
+                    let csi_endpoint = csi_endpoint.ok_or_else(|| {
+                        anyhow!("--csi-endpoint is required for the controller run mode")
+                    })?;
+                    let readiness = health::Readiness::new([
+                        health::ReadinessCheck::CsiSocket,
+                        health::ReadinessCheck::InitialSync,
+                        health::ReadinessCheck::WebhookServer,
+                    ]);
+
                     let (webhook_server, initial_reconcile_rx) = create_webhook_server(
                         &operator_environment,
                         maintenance.disable_crd_maintenance,
@@ -194,15 +336,23 @@ async fn main() -> anyhow::Result<()> {
                         .run(sigterm_watcher.handle())
                         .map_err(|err| anyhow!(err).context("failed to run webhook server"));
 
-                    let listener_classes = create_listener_classes(
-                        initial_reconcile_rx,
-                        listener_class_preset,
-                        client.clone(),
-                    )
+                    let listener_classes = async {
+                        initial_reconcile_rx.await?;
+                        readiness.complete(health::ReadinessCheck::WebhookServer);
+                        create_listener_classes(listener_class_preset, client.clone()).await
+                    }
                     .map_err(|err| {
                         anyhow!(err).context("failed to apply listener classes selected by preset")
                     });
 
+                    let (csi_server, csi_listener) = csi_server(
+                        csi_endpoint.clone(),
+                        ListenerOperatorIdentity {
+                            client: client.clone(),
+                            node_name: None,
+                        },
+                    )?;
+                    readiness.complete(health::ReadinessCheck::CsiSocket);
                     let csi_server = csi_server
                         .add_service(ControllerServer::new(ListenerOperatorController {
                             client: client.clone(),
@@ -210,50 +360,204 @@ async fn main() -> anyhow::Result<()> {
                         .serve_with_incoming_shutdown(csi_listener, sigterm_watcher.handle())
                         .map_err(|err| anyhow!(err).context("failed to run csi server"));
 
-                    let controller =
-                        listener_controller::run(client.clone(), sigterm_watcher.handle())
-                            .map(anyhow::Ok);
+                    let controller = listener_controller::run(
+                        client.clone(),
+                        hostname_resolution.address_resolver(),
+                        readiness.clone(),
+                        sigterm_watcher.handle(),
+                    )
+                    .map(anyhow::Ok);
+
+                    let reachability_prober = async {
+                        if let Some(interval) = reachability_probe_interval {
+                            reachability::run(
+                                client.clone(),
+                                interval,
+                                reachability_probe_timeout,
+                                sigterm_watcher.handle(),
+                            )
+                            .await;
+                        }
+                    };
+
+                    let pinned_volume_releaser = async {
+                        if pinned_volume_release != pinned_volumes::ReleaseMode::Disabled {
+                            pinned_volumes::run(
+                                client.clone(),
+                                pinned_volume_release,
+                                pinned_volume_release_grace_period,
+                                pinned_volumes::ReleaseLimits {
+                                    max_unavailable_nodes_percent:
+                                        pinned_volume_release_max_unavailable_nodes_percent,
+                                    max_releases_per_check: pinned_volume_release_max_per_minute,
+                                },
+                                sigterm_watcher.handle(),
+                            )
+                            .await;
+                        }
+                    };
 
                     let delayed_controller = async {
                         signal::crd_established(&client, v1alpha1::Listener::crd_name(), None)
                             .await?;
-                        controller.await
+                        futures::join!(controller, reachability_prober, pinned_volume_releaser).0
                     };
 
+                    let health_server = health_server(
+                        health_listen_address,
+                        readiness.clone(),
+                        Some(csi_endpoint),
+                        sigterm_watcher.handle(),
+                    );
+
                     futures::try_join!(
                         delayed_controller,
                         listener_classes,
                         webhook_server,
                         eos_checker,
                         csi_server,
+                        health_server,
                     )?;
                 }
-                RunMode::Node => {
+                RunMode::Node(NodeArguments {
+                    listener_update_notification,
+                    tmpfs_volumes,
+                    max_volumes_per_node,
+                    node_topology_labels,
+                    hostname_resolution,
+                    kubelet_dir,
+                }) => {
+                    let csi_endpoint = csi_endpoint.ok_or_else(|| {
+                        anyhow!("--csi-endpoint is required for the node run mode")
+                    })?;
                     let node_name = &common.cluster_info.kubernetes_node_name;
-                    let csi_server = csi_server
-                        .add_service(NodeServer::new(ListenerOperatorNode {
+                    let node = ListenerOperatorNode {
+                        client: client.clone(),
+                        node_name: node_name.to_owned(),
+                        published_volumes: Default::default(),
+                        listener_update_notification,
+                        tmpfs_volumes,
+                        max_volumes_per_node,
+                        topology_labels: node_topology_labels,
+                        address_resolver: hostname_resolution.address_resolver(),
+                    };
+                    let readiness = health::Readiness::new([
+                        health::ReadinessCheck::CsiSocket,
+                        health::ReadinessCheck::InitialSync,
+                    ]);
+                    let volume_updater = node
+                        .clone()
+                        .run_volume_updater(
+                            kubelet_dir,
+                            readiness.clone(),
+                            sigterm_watcher.handle(),
+                        )
+                        .map(anyhow::Ok);
+
+                    let (csi_server, csi_listener) = csi_server(
+                        csi_endpoint.clone(),
+                        ListenerOperatorIdentity {
                             client: client.clone(),
-                            node_name: node_name.to_owned(),
-                        }))
+                            node_name: Some(node_name.to_owned()),
+                        },
+                    )?;
+                    readiness.complete(health::ReadinessCheck::CsiSocket);
+                    let csi_server = csi_server
+                        .add_service(NodeServer::new(node))
                         .serve_with_incoming_shutdown(csi_listener, sigterm_watcher.handle())
                         .map_err(|err| anyhow!(err).context("failed to run csi server"));
 
-                    futures::try_join!(csi_server, eos_checker)?;
+                    let health_server = health_server(
+                        health_listen_address,
+                        readiness,
+                        Some(csi_endpoint),
+                        sigterm_watcher.handle(),
+                    );
+
+                    futures::try_join!(csi_server, volume_updater, eos_checker, health_server)?;
+                }
+                RunMode::DnsServer(DnsServerArguments {
+                    dns_zone,
+                    dns_listen_address,
+                    dns_ttl,
+                }) => {
+                    let readiness = health::Readiness::new([health::ReadinessCheck::InitialSync]);
+                    let dns_server = dns_server::run(
+                        client,
+                        &dns_zone,
+                        dns_ttl,
+                        dns_listen_address,
+                        readiness.clone(),
+                        sigterm_watcher.handle(),
+                    )
+                    .map_err(|err| anyhow!(err).context("failed to run dns server"));
+
+                    let health_server = health_server(
+                        health_listen_address,
+                        readiness,
+                        None,
+                        sigterm_watcher.handle(),
+                    );
+
+                    futures::try_join!(dns_server, eos_checker, health_server)?;
                 }
             }
         }
+        ListenerOperatorCommand::Drain(args) => drain::run(args).await?,
+    }
+
+    Ok(())
+}
+
+/// Binds the CSI socket, and prepares the CSI services that are shared by all CSI run modes.
+fn csi_server(
+    csi_endpoint: PathBuf,
+    identity: ListenerOperatorIdentity,
+) -> anyhow::Result<(
+    Router,
+    impl futures::Stream<Item = std::io::Result<TonicUnixStream>>,
+)> {
+    if csi_endpoint
+        .symlink_metadata()
+        .is_ok_and(|meta| meta.file_type().is_socket())
+    {
+        let _ = std::fs::remove_file(&csi_endpoint);
     }
 
+    let csi_listener =
+        UnixListenerStream::new(uds_bind_private(csi_endpoint)?).map_ok(TonicUnixStream);
+
+    let csi_server = Server::builder()
+        .add_service(
+            tonic_reflection::server::Builder::configure()
+                .include_reflection_service(true)
+                .register_encoded_file_descriptor_set(csi_grpc::FILE_DESCRIPTOR_SET_BYTES)
+                .build_v1()?,
+        )
+        .add_service(IdentityServer::new(identity));
+
+    Ok((csi_server, csi_listener))
+}
+
+/// Serves the health checks of `readiness` and the CSI socket at `csi_endpoint` (if any) on `listen_address`, if set.
+async fn health_server(
+    listen_address: Option<SocketAddr>,
+    readiness: health::Readiness,
+    csi_endpoint: Option<PathBuf>,
+    shutdown_signal: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    if let Some(listen_address) = listen_address {
+        health::run(listen_address, readiness, csi_endpoint, shutdown_signal)
+            .await
+            .map_err(|err| anyhow!(err).context("failed to run health server"))?;
+    }
     Ok(())
 }
 
 async fn create_listener_classes(
-    initial_reconcile_rx: oneshot::Receiver<()>,
     listener_class_preset: ListenerClassPreset,
     client: Client,
 ) -> anyhow::Result<()> {
-    initial_reconcile_rx.await?;
-
     tracing::info!(
         preset = %listener_class_preset,
         "apply listener class preset"
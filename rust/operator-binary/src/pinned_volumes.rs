@@ -0,0 +1,812 @@
+//! Releases pinned listener volumes whose Node is gone.
+//!
+//! Volumes of ListenerClasses that enable `pinnedNodePorts` are pinned to the Node that they were first mounted on. If
+//! that Node is deleted, or stays NotReady for longer than the grace period, then the workload can never be scheduled
+//! again. Such volumes are released by deleting their PersistentVolumeClaim and the Pods that use it, so that the
+//! workload's controller recreates them, and the new volume is pinned to a Node that is available.
+//!
+//! The Pods are force-deleted (see [`PodRemoval::ForceDelete`]), since the kubelet of an unavailable Node can never
+//! confirm that they have terminated. Until then, StatefulSets don't recreate the Pod, and the PersistentVolumeClaim
+//! is kept alive by its `kubernetes.io/pvc-protection` finalizer.
+//!
+//! Only claims that the workload recreates are released (see [`PinnedClaim::is_recreated_by_workload`]), any other
+//! claim would be deleted for good.
+//!
+//! Since releasing a volume may change its address, releases are limited by [`ReleaseLimits`], so that an outage
+//! that affects many Nodes at once (such as a network partition) doesn't move all workloads.
+
+use std::{fmt::Display, future::Future};
+
+use snafu::{ResultExt, Snafu, ensure};
+use stackable_operator::{
+    client::Client,
+    k8s_openapi::{
+        api::core::v1::{Node, PersistentVolume, PersistentVolumeClaim, Pod},
+        apimachinery::pkg::apis::meta::v1::LabelSelector,
+        jiff::Timestamp,
+    },
+    kube::{
+        Resource, ResourceExt,
+        api::{DeleteParams, EvictParams},
+        runtime::{
+            events::{Event, EventType, Recorder, Reporter},
+            reflector::ObjectRef,
+        },
+    },
+    shared::time::Duration,
+};
+
+use crate::csi_server::{
+    controller::{NamespacePods, is_listener_volume, pod_uses_claim},
+    node::NODE_TOPOLOGY_LABEL_HOSTNAME,
+};
+
+const RELEASER_NAME: &str = "pinned-volume-release.listeners.stackable.tech";
+
+/// How often all pinned volumes are checked.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Whether pinned volumes should be released when their Node is gone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ReleaseMode {
+    /// Pinned volumes are never released.
+    #[default]
+    Disabled,
+
+    /// Volumes that would be released are only reported, as Events on their PersistentVolumeClaim.
+    DryRun,
+
+    /// Volumes are released, and reported as Events on their PersistentVolumeClaim.
+    Enabled,
+}
+
+/// Limits how many volumes are released, see [`run`].
+#[derive(Clone, Copy, Debug)]
+pub struct ReleaseLimits {
+    /// No volumes are released while more than this percentage of Nodes are not Ready.
+    pub max_unavailable_nodes_percent: u8,
+
+    /// The maximum number of volumes that are released per check.
+    pub max_releases_per_check: usize,
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("failed to list PersistentVolumes"))]
+    ListPersistentVolumes {
+        source: stackable_operator::client::Error,
+    },
+
+    #[snafu(display("failed to list Nodes"))]
+    ListNodes {
+        source: stackable_operator::client::Error,
+    },
+
+    #[snafu(display("failed to get {pvc}"))]
+    GetPvc {
+        source: stackable_operator::client::Error,
+        pvc: ObjectRef<PersistentVolumeClaim>,
+    },
+
+    #[snafu(display("failed to list Pods in namespace {namespace:?}"))]
+    ListPods {
+        source: stackable_operator::client::Error,
+        namespace: String,
+    },
+
+    #[snafu(display(
+        "{pvc} would not be recreated by its workload, only claims of StatefulSets and ephemeral volumes can be released"
+    ))]
+    ClaimNotRecreated {
+        pvc: ObjectRef<PersistentVolumeClaim>,
+    },
+
+    #[snafu(display("failed to delete {pvc}"))]
+    DeletePvc {
+        source: stackable_operator::kube::Error,
+        pvc: ObjectRef<PersistentVolumeClaim>,
+    },
+
+    #[snafu(display("failed to evict {pod}"))]
+    EvictPod {
+        source: stackable_operator::kube::Error,
+        pod: ObjectRef<Pod>,
+    },
+
+    #[snafu(display("failed to delete {pod}"))]
+    DeletePod {
+        source: stackable_operator::kube::Error,
+        pod: ObjectRef<Pod>,
+    },
+}
+
+/// How [`PinnedClaim::release`] removes the Pods that use the claim.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PodRemoval {
+    /// Pods are evicted, respecting their PodDisruptionBudgets.
+    ///
+    /// Only suitable for healthy Nodes, since the eviction only completes once the kubelet has confirmed that the Pod
+    /// has terminated.
+    Evict,
+
+    /// Pods are deleted immediately (without a grace period), including Pods that are already terminating.
+    ///
+    /// Used for Nodes that are gone, whose kubelet will never confirm that the Pods have terminated.
+    ForceDelete,
+}
+
+impl PodRemoval {
+    /// Whether `pod` still needs to be removed.
+    pub fn applies_to(self, pod: &Pod) -> bool {
+        match self {
+            // Evicting a terminating Pod again wouldn't make it terminate any sooner
+            PodRemoval::Evict => pod.metadata.deletion_timestamp.is_none(),
+            // A terminating Pod is stuck until it is force-deleted, unless it already was
+            PodRemoval::ForceDelete => pod.metadata.deletion_grace_period_seconds != Some(0),
+        }
+    }
+}
+
+/// Why the Node that a volume is pinned to is considered to be gone.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NodeUnavailability {
+    Deleted,
+    NotReady { since: Timestamp },
+}
+
+impl Display for NodeUnavailability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeUnavailability::Deleted => write!(f, "has been deleted"),
+            NodeUnavailability::NotReady { since } => write!(f, "has been NotReady since {since}"),
+        }
+    }
+}
+
+/// The name of the Node that `pv` is pinned to, if any.
+///
+/// Volumes that are pinned to a zone (or not pinned at all) are not pinned to any single Node.
+pub fn pinned_node_name(pv: &PersistentVolume) -> Option<&str> {
+    pv.spec
+        .as_ref()?
+        .node_affinity
+        .as_ref()?
+        .required
+        .as_ref()?
+        .node_selector_terms
+        .iter()
+        .flat_map(|term| term.match_expressions.iter().flatten())
+        .find(|expr| expr.key == NODE_TOPOLOGY_LABEL_HOSTNAME && expr.operator == "In")
+        .and_then(|expr| match expr.values.as_deref() {
+            Some([node_name]) => Some(node_name.as_str()),
+            _ => None,
+        })
+}
+
+/// Checks whether `node` is unavailable at `now`, where `node` is [`None`] if the Node does not exist.
+///
+/// NotReady Nodes are only considered to be unavailable once they have been NotReady for `grace_period`.
+pub fn node_unavailability(
+    node: Option<&Node>,
+    now: Timestamp,
+    grace_period: std::time::Duration,
+) -> Option<NodeUnavailability> {
+    let Some(node) = node else {
+        return Some(NodeUnavailability::Deleted);
+    };
+    let ready_condition = node
+        .status
+        .as_ref()?
+        .conditions
+        .iter()
+        .flatten()
+        .find(|condition| condition.type_ == "Ready")?;
+    if ready_condition.status == "True" {
+        return None;
+    }
+    let since = ready_condition.last_transition_time.as_ref()?.0;
+    let not_ready_for = now.duration_since(since);
+    (not_ready_for.is_positive() && not_ready_for.unsigned_abs() >= grace_period)
+        .then_some(NodeUnavailability::NotReady { since })
+}
+
+/// Whether more than `max_unavailable_percent` of `nodes` are not Ready, regardless of how long they have been
+/// NotReady for.
+pub fn too_many_unavailable_nodes(nodes: &[Node], max_unavailable_percent: u8) -> bool {
+    let unavailable = nodes
+        .iter()
+        .filter(|node| {
+            node.status
+                .iter()
+                .flat_map(|status| status.conditions.iter().flatten())
+                .any(|condition| condition.type_ == "Ready" && condition.status != "True")
+        })
+        .count();
+    unavailable * 100 > nodes.len() * usize::from(max_unavailable_percent)
+}
+
+/// Periodically releases pinned volumes whose Node is gone, according to `mode` and `limits`.
+pub async fn run<F>(
+    client: Client,
+    mode: ReleaseMode,
+    grace_period: Duration,
+    limits: ReleaseLimits,
+    shutdown_signal: F,
+) where
+    F: Future<Output = ()>,
+{
+    let event_recorder = Recorder::new(
+        client.as_kube_client(),
+        Reporter {
+            controller: RELEASER_NAME.to_string(),
+            instance: None,
+        },
+    );
+
+    let releaser = async {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) =
+                release_unavailable_volumes(&client, &event_recorder, mode, *grace_period, limits)
+                    .await
+            {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to check pinned volumes"
+                );
+            }
+        }
+    };
+
+    tokio::select! {
+        () = releaser => {},
+        () = shutdown_signal => {},
+    }
+}
+
+/// The PersistentVolumeClaim of a pinned volume, along with the Pods that use it.
+pub struct PinnedClaim {
+    pub pvc: PersistentVolumeClaim,
+    pub pods: Vec<Pod>,
+}
+
+impl PinnedClaim {
+    /// Looks up the claim that `pv` is bound to, finding the Pods that use it in `pods`.
+    ///
+    /// Returns [`None`] if `pv` is not bound, or if its PersistentVolumeClaim has already been deleted.
+    pub async fn for_pv(
+        client: &Client,
+        pv: &PersistentVolume,
+        pods: &mut NamespacePods,
+    ) -> Result<Option<Self>, Error> {
+        use error::*;
+        let Some(claim_ref) = pv.spec.as_ref().and_then(|spec| spec.claim_ref.as_ref()) else {
+            return Ok(None);
+        };
+        let (Some(ns), Some(pvc_name)) =
+            (claim_ref.namespace.as_deref(), claim_ref.name.as_deref())
+        else {
+            return Ok(None);
+        };
+        let Some(pvc) = client
+            .get_opt::<PersistentVolumeClaim>(pvc_name, ns)
+            .await
+            .with_context(|_| GetPvcSnafu {
+                pvc: ObjectRef::<PersistentVolumeClaim>::new(pvc_name).within(ns),
+            })?
+        else {
+            return Ok(None);
+        };
+        let pods = pods
+            .get(client, ns)
+            .await
+            .context(ListPodsSnafu { namespace: ns })?
+            .iter()
+            .filter(|pod| pod_uses_claim(pod, pvc_name))
+            .cloned()
+            .collect();
+        Ok(Some(Self { pvc, pods }))
+    }
+
+    /// The names of [`Self::pods`], as a comma-separated list.
+    pub fn pod_names(&self) -> String {
+        self.pods
+            .iter()
+            .map(|pod| pod.name_any())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Whether the PersistentVolumeClaim is already being deleted.
+    pub fn is_releasing(&self) -> bool {
+        self.pvc.metadata.deletion_timestamp.is_some()
+    }
+
+    /// Whether the workload recreates the PersistentVolumeClaim once it has been deleted.
+    ///
+    /// This is the case for claims that were created from a StatefulSet's `volumeClaimTemplates`, and for the claims
+    /// of generic ephemeral volumes whose Pod is managed by a controller.
+    pub fn is_recreated_by_workload(&self) -> bool {
+        claim_is_recreated_by_workload(&self.pvc, &self.pods)
+    }
+
+    /// Deletes the PersistentVolumeClaim and removes the Pods that use it (according to `pod_removal`), so that the
+    /// workload's controller recreates them.
+    ///
+    /// Removals that fail (such as evictions that are rejected by a PodDisruptionBudget) can be retried by releasing
+    /// the claim again.
+    pub async fn release(&self, client: &Client, pod_removal: PodRemoval) -> Result<(), Error> {
+        use error::*;
+        let ns = self.pvc.namespace().unwrap_or_default();
+        if !self.is_releasing() {
+            ensure!(
+                self.is_recreated_by_workload(),
+                ClaimNotRecreatedSnafu {
+                    pvc: ObjectRef::from_obj(&self.pvc),
+                }
+            );
+            ignore_not_found(
+                client
+                    .get_api::<PersistentVolumeClaim>(&ns)
+                    .delete(&self.pvc.name_any(), &DeleteParams::default())
+                    .await,
+            )
+            .with_context(|_| DeletePvcSnafu {
+                pvc: ObjectRef::from_obj(&self.pvc),
+            })?;
+        }
+        // The PVC is only deleted once no Pods use it anymore, including Pods that are still Pending
+        let pods = client.get_api::<Pod>(&ns);
+        for pod in self.pods.iter().filter(|pod| pod_removal.applies_to(pod)) {
+            match pod_removal {
+                PodRemoval::Evict => {
+                    ignore_not_found(pods.evict(&pod.name_any(), &EvictParams::default()).await)
+                        .with_context(|_| EvictPodSnafu {
+                            pod: ObjectRef::from_obj(pod),
+                        })?
+                }
+                PodRemoval::ForceDelete => ignore_not_found(
+                    pods.delete(&pod.name_any(), &DeleteParams::default().grace_period(0))
+                        .await,
+                )
+                .with_context(|_| DeletePodSnafu {
+                    pod: ObjectRef::from_obj(pod),
+                })?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `pvc` is recreated by the workload of the `pods` that use it, once it has been deleted.
+fn claim_is_recreated_by_workload(pvc: &PersistentVolumeClaim, pods: &[Pod]) -> bool {
+    let pvc_name = pvc.name_any();
+    pods.iter().any(|pod| {
+        let pod_name = pod.name_any();
+        let pod_controller = pod
+            .owner_references()
+            .iter()
+            .find(|owner| owner.controller == Some(true));
+        // Ephemeral volumes are recreated along with their Pod, which must in turn be recreated by its controller
+        let is_ephemeral_of_pod = pod_controller.is_some()
+            && pvc
+                .owner_references()
+                .iter()
+                .any(|owner| owner.kind == "Pod" && owner.name == pod_name);
+        // StatefulSets name the claims of their volumeClaimTemplates `<template>-<pod>`
+        let is_template_of_statefulset = pod_controller
+            .is_some_and(|owner| owner.kind == "StatefulSet")
+            && pvc_name
+                .strip_suffix(&pod_name)
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('-'));
+        is_ephemeral_of_pod || is_template_of_statefulset
+    })
+}
+
+/// Releases the listener volumes that are pinned to an unavailable Node, within `limits`.
+async fn release_unavailable_volumes(
+    client: &Client,
+    event_recorder: &Recorder,
+    mode: ReleaseMode,
+    grace_period: std::time::Duration,
+    limits: ReleaseLimits,
+) -> Result<(), Error> {
+    use error::*;
+    let pvs = client
+        .list_with_label_selector::<PersistentVolume>(&(), &LabelSelector::default())
+        .await
+        .context(ListPersistentVolumesSnafu)?;
+    let nodes = client
+        .list_with_label_selector::<Node>(&(), &LabelSelector::default())
+        .await
+        .context(ListNodesSnafu)?;
+    // Many Nodes going down at once is more likely to be a problem with the cluster (or the operator's view of it) than
+    // with the Nodes themselves, and releasing all of their volumes would only make it worse
+    if too_many_unavailable_nodes(&nodes, limits.max_unavailable_nodes_percent) {
+        tracing::warn!(
+            max_unavailable_nodes_percent = limits.max_unavailable_nodes_percent,
+            "too many Nodes are not Ready, not releasing any pinned volumes"
+        );
+        return Ok(());
+    }
+    let now = Timestamp::now();
+    let mut pods = NamespacePods::default();
+    let mut releases = 0;
+    for pv in pvs.iter().filter(|pv| is_listener_volume(pv)) {
+        let Some(node_name) = pinned_node_name(pv) else {
+            continue;
+        };
+        let node = nodes.iter().find(|node| node.name_any() == node_name);
+        let Some(unavailability) = node_unavailability(node, now, grace_period) else {
+            continue;
+        };
+        let may_release = releases < limits.max_releases_per_check;
+        match release_volume(
+            client,
+            event_recorder,
+            mode,
+            may_release,
+            pv,
+            node_name,
+            &unavailability,
+            &mut pods,
+        )
+        .await
+        {
+            Ok(true) => releases += 1,
+            Ok(false) => {}
+            Err(err) => tracing::warn!(
+                pv = %ObjectRef::from_obj(pv),
+                node = node_name,
+                error = &err as &dyn std::error::Error,
+                "failed to release pinned volume"
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Releases `pv`, which is pinned to the unavailable Node `node_name`.
+///
+/// Releases that are already in progress are always finished, but new releases are only started if `may_release` is
+/// set. Returns whether a new release was started.
+#[allow(clippy::too_many_arguments)]
+async fn release_volume(
+    client: &Client,
+    event_recorder: &Recorder,
+    mode: ReleaseMode,
+    may_release: bool,
+    pv: &PersistentVolume,
+    node_name: &str,
+    unavailability: &NodeUnavailability,
+    pods: &mut NamespacePods,
+) -> Result<bool, Error> {
+    let Some(claim) = PinnedClaim::for_pv(client, pv, pods).await? else {
+        // The volume has already been released
+        return Ok(false);
+    };
+    let pvc_ref = ObjectRef::from_obj(&claim.pvc);
+    let pod_names = claim.pod_names();
+    let is_new_release = !claim.is_releasing();
+
+    if is_new_release && !claim.is_recreated_by_workload() {
+        tracing::warn!(
+            pvc = %pvc_ref,
+            node = node_name,
+            %unavailability,
+            "pinned volume cannot be released, since its PersistentVolumeClaim would not be recreated by its workload"
+        );
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "PinnedVolumeNotReleasable".to_string(),
+            note: Some(format!(
+                "volume is pinned to Node {node_name:?}, which {unavailability}; \
+                the PersistentVolumeClaim is not recreated by its workload, so it must be released manually"
+            )),
+            action: "ReleasePinnedVolume".to_string(),
+            secondary: None,
+        };
+        if let Err(err) = event_recorder
+            .publish(&event, &claim.pvc.object_ref(&()))
+            .await
+        {
+            tracing::warn!(
+                pvc = %pvc_ref,
+                error = &err as &dyn std::error::Error,
+                "failed to publish pinned volume release event"
+            );
+        }
+        return Ok(false);
+    }
+    if is_new_release && mode == ReleaseMode::Enabled && !may_release {
+        tracing::info!(
+            pvc = %pvc_ref,
+            node = node_name,
+            "too many pinned volumes are being released at once, postponing until the next check"
+        );
+        return Ok(false);
+    }
+    if is_new_release {
+        let event = match mode {
+            ReleaseMode::Disabled => return Ok(false),
+            ReleaseMode::DryRun => Event {
+                type_: EventType::Warning,
+                reason: "PinnedNodeUnavailable".to_string(),
+                note: Some(format!(
+                    "volume is pinned to Node {node_name:?}, which {unavailability}; \
+                    would delete the PersistentVolumeClaim and force-delete the Pods using it ({pod_names}) (dry run)"
+                )),
+                action: "ReleasePinnedVolume".to_string(),
+                secondary: None,
+            },
+            ReleaseMode::Enabled => Event {
+                type_: EventType::Warning,
+                reason: "ReleasingPinnedVolume".to_string(),
+                note: Some(format!(
+                    "volume is pinned to Node {node_name:?}, which {unavailability}; \
+                    deleting the PersistentVolumeClaim and force-deleting the Pods using it ({pod_names}), \
+                    so that it is pinned to another Node (which may change its address)"
+                )),
+                action: "ReleasePinnedVolume".to_string(),
+                secondary: None,
+            },
+        };
+        if let Err(err) = event_recorder
+            .publish(&event, &claim.pvc.object_ref(&()))
+            .await
+        {
+            tracing::warn!(
+                pvc = %pvc_ref,
+                error = &err as &dyn std::error::Error,
+                "failed to publish pinned volume release event"
+            );
+        }
+    }
+    if mode != ReleaseMode::Enabled {
+        return Ok(false);
+    }
+
+    tracing::info!(
+        pvc = %pvc_ref,
+        node = node_name,
+        %unavailability,
+        pods = pod_names,
+        "releasing pinned volume"
+    );
+    // The Node can't confirm that the Pods have terminated, so evicting them would never complete
+    claim.release(client, PodRemoval::ForceDelete).await?;
+    Ok(is_new_release)
+}
+
+/// Treats deleting an object that is already gone as a success.
+fn ignore_not_found<T>(
+    result: Result<T, stackable_operator::kube::Error>,
+) -> Result<(), stackable_operator::kube::Error> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(stackable_operator::kube::Error::Api(err)) if err.code == 404 => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stackable_operator::{
+        k8s_openapi::{
+            api::core::v1::{
+                NodeCondition, NodeSelector, NodeSelectorRequirement, NodeSelectorTerm, NodeStatus,
+                PersistentVolumeSpec, VolumeNodeAffinity,
+            },
+            apimachinery::pkg::apis::meta::v1::{OwnerReference, Time},
+        },
+        kube::core::ObjectMeta,
+    };
+
+    use super::*;
+
+    fn pv_pinned_to(key: &str, values: &[&str]) -> PersistentVolume {
+        PersistentVolume {
+            metadata: ObjectMeta::default(),
+            spec: Some(PersistentVolumeSpec {
+                node_affinity: Some(VolumeNodeAffinity {
+                    required: Some(NodeSelector {
+                        node_selector_terms: vec![NodeSelectorTerm {
+                            match_expressions: Some(vec![NodeSelectorRequirement {
+                                key: key.to_string(),
+                                operator: "In".to_string(),
+                                values: Some(values.iter().map(|v| v.to_string()).collect()),
+                            }]),
+                            ..Default::default()
+                        }],
+                    }),
+                }),
+                ..Default::default()
+            }),
+            status: None,
+        }
+    }
+
+    fn node_with_ready_status(status: &str, since: Timestamp) -> Node {
+        Node {
+            status: Some(NodeStatus {
+                conditions: Some(vec![NodeCondition {
+                    type_: "Ready".to_string(),
+                    status: status.to_string(),
+                    last_transition_time: Some(Time(since)),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pinned_node_name_requires_single_node() {
+        assert_eq!(
+            pinned_node_name(&pv_pinned_to(NODE_TOPOLOGY_LABEL_HOSTNAME, &["node-1"])),
+            Some("node-1")
+        );
+        assert_eq!(
+            pinned_node_name(&pv_pinned_to(
+                NODE_TOPOLOGY_LABEL_HOSTNAME,
+                &["node-1", "node-2"]
+            )),
+            None
+        );
+        assert_eq!(
+            pinned_node_name(&pv_pinned_to("topology.kubernetes.io/zone", &["zone-a"])),
+            None
+        );
+        assert_eq!(pinned_node_name(&PersistentVolume::default()), None);
+    }
+
+    #[test]
+    fn node_unavailability_respects_grace_period() {
+        let grace_period = std::time::Duration::from_secs(15 * 60);
+        let now: Timestamp = "2024-01-01T12:00:00Z".parse().unwrap();
+        let recently: Timestamp = "2024-01-01T11:55:00Z".parse().unwrap();
+        let long_ago: Timestamp = "2024-01-01T11:00:00Z".parse().unwrap();
+
+        assert_eq!(
+            node_unavailability(None, now, grace_period),
+            Some(NodeUnavailability::Deleted)
+        );
+        assert_eq!(
+            node_unavailability(
+                Some(&node_with_ready_status("True", long_ago)),
+                now,
+                grace_period
+            ),
+            None
+        );
+        assert_eq!(
+            node_unavailability(
+                Some(&node_with_ready_status("Unknown", recently)),
+                now,
+                grace_period
+            ),
+            None
+        );
+        assert_eq!(
+            node_unavailability(
+                Some(&node_with_ready_status("False", long_ago)),
+                now,
+                grace_period
+            ),
+            Some(NodeUnavailability::NotReady { since: long_ago })
+        );
+        // Nodes that don't report readiness yet are assumed to be available
+        assert_eq!(
+            node_unavailability(Some(&Node::default()), now, grace_period),
+            None
+        );
+    }
+
+    #[test]
+    fn releases_are_skipped_if_many_nodes_are_unavailable() {
+        let since: Timestamp = "2024-01-01T12:00:00Z".parse().unwrap();
+        let nodes = |ready: usize, not_ready: usize| {
+            std::iter::repeat_n(node_with_ready_status("True", since), ready)
+                .chain(std::iter::repeat_n(
+                    node_with_ready_status("False", since),
+                    not_ready,
+                ))
+                .collect::<Vec<_>>()
+        };
+        assert!(!too_many_unavailable_nodes(&nodes(10, 0), 50));
+        assert!(!too_many_unavailable_nodes(&nodes(5, 5), 50));
+        assert!(too_many_unavailable_nodes(&nodes(4, 6), 50));
+        assert!(too_many_unavailable_nodes(&nodes(9, 1), 0));
+        assert!(!too_many_unavailable_nodes(&nodes(0, 10), 100));
+        assert!(!too_many_unavailable_nodes(&[], 50));
+    }
+
+    #[test]
+    fn force_deletion_includes_terminating_pods() {
+        let pod = |deletion_timestamp: Option<&str>, deletion_grace_period_seconds| Pod {
+            metadata: ObjectMeta {
+                deletion_timestamp: deletion_timestamp.map(|ts| Time(ts.parse().unwrap())),
+                deletion_grace_period_seconds,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let running = pod(None, None);
+        let terminating = pod(Some("2024-01-01T12:00:00Z"), Some(30));
+        let force_deleted = pod(Some("2024-01-01T12:00:00Z"), Some(0));
+
+        assert!(PodRemoval::Evict.applies_to(&running));
+        assert!(!PodRemoval::Evict.applies_to(&terminating));
+        assert!(!PodRemoval::Evict.applies_to(&force_deleted));
+        // Pods on an unavailable Node stay terminating until they are force-deleted
+        assert!(PodRemoval::ForceDelete.applies_to(&running));
+        assert!(PodRemoval::ForceDelete.applies_to(&terminating));
+        assert!(!PodRemoval::ForceDelete.applies_to(&force_deleted));
+    }
+
+    #[test]
+    fn only_claims_recreated_by_workload_are_released() {
+        let owner = |kind: &str, name: &str, controller: bool| OwnerReference {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            controller: Some(controller),
+            ..Default::default()
+        };
+        let pvc = |name: &str, owners: Vec<OwnerReference>| PersistentVolumeClaim {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                owner_references: Some(owners),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let pod = |name: &str, owners: Vec<OwnerReference>| Pod {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                owner_references: Some(owners),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sts_pod = pod("web-0", vec![owner("StatefulSet", "web", true)]);
+        let rs_pod = pod("web-abc12", vec![owner("ReplicaSet", "web-abc", true)]);
+        let bare_pod = pod("web", vec![]);
+
+        // volumeClaimTemplates of StatefulSets
+        assert!(claim_is_recreated_by_workload(
+            &pvc("listener-web-0", vec![]),
+            std::slice::from_ref(&sts_pod)
+        ));
+        assert!(!claim_is_recreated_by_workload(
+            &pvc("listener", vec![]),
+            std::slice::from_ref(&sts_pod)
+        ));
+        assert!(!claim_is_recreated_by_workload(
+            &pvc("listener-web-0", vec![]),
+            &[pod("web-0", vec![owner("StatefulSet", "web", false)])]
+        ));
+        // Ephemeral volumes
+        assert!(claim_is_recreated_by_workload(
+            &pvc("web-abc12-listener", vec![owner("Pod", "web-abc12", true)]),
+            std::slice::from_ref(&rs_pod)
+        ));
+        assert!(!claim_is_recreated_by_workload(
+            &pvc("web-listener", vec![owner("Pod", "web", true)]),
+            std::slice::from_ref(&bare_pod)
+        ));
+        // Standalone claims
+        assert!(!claim_is_recreated_by_workload(
+            &pvc("listener", vec![]),
+            &[rs_pod, bare_pod]
+        ));
+        assert!(!claim_is_recreated_by_workload(
+            &pvc("listener-web-0", vec![]),
+            &[]
+        ));
+    }
+}
@@ -0,0 +1,682 @@
+//! Actively probes whether the addresses published by [`listener::v1alpha1::Listener`]s are reachable.
+//!
+//! Each (address, port) pair in `Listener.status.ingressAddresses` is probed by opening a TCP connection to it.
+//! The reachability of each endpoint is recorded in the [`LISTENER_ANNOTATION_REACHABILITY`] annotation on the
+//! Listener, and changes in reachability are reported as Events on the Listener. Each write to the Listener triggers a
+//! reconcile of it (and of all volumes that use it), so the annotation is only written when an endpoint's reachability
+//! changes.
+//!
+//! Whether any endpoint is unreachable is recorded as the [`SERVICE_CONDITION_DEGRADED`] condition on the Listener's
+//! Service (since the Listener's own status has no conditions), and the time of the last probe is recorded in the
+//! [`SERVICE_ANNOTATION_LAST_PROBE_TIME`] annotation on the Service.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::{Future, ready},
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use stackable_operator::{
+    client::Client,
+    crd::listener,
+    k8s_openapi::{
+        api::core::v1::{Service, ServiceStatus},
+        apimachinery::pkg::apis::meta::v1::{Condition, Time},
+        jiff::Timestamp,
+    },
+    kube::{
+        Resource, ResourceExt,
+        api::ObjectMeta,
+        core::DeserializeGuard,
+        runtime::{
+            WatchStreamExt,
+            events::{Event, EventType, Recorder, Reporter},
+            reflector::{self, ObjectRef},
+            watcher,
+        },
+    },
+    shared::time::Duration,
+};
+use tokio::net::TcpStream;
+
+use crate::utils::error::error_full_message;
+
+/// The Service condition that is `True` while any of the Listener's endpoints are unreachable.
+pub const SERVICE_CONDITION_DEGRADED: &str = "listeners.stackable.tech/Degraded";
+
+/// The Listener annotation that records the [`EndpointStatus`] of each of the Listener's endpoints.
+pub const LISTENER_ANNOTATION_REACHABILITY: &str = "listeners.stackable.tech/reachability";
+
+/// The Service annotation that records when the Listener's endpoints were last probed.
+///
+/// This is kept on the Service rather than the Listener, since it is written on every probe. The Listener controller
+/// still reconciles the Listener when its Service changes, but that doesn't modify the Listener or its volumes.
+pub const SERVICE_ANNOTATION_LAST_PROBE_TIME: &str = "listeners.stackable.tech/last-probe-time";
+
+const PROBER_NAME: &str = "reachability.listeners.stackable.tech";
+
+/// How many Listeners are probed concurrently.
+const LISTENER_CONCURRENCY: usize = 16;
+
+/// How many endpoints of a single Listener are probed concurrently.
+const ENDPOINT_CONCURRENCY: usize = 16;
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum ProbeError {
+    #[snafu(display("timed out after {timeout:?}"))]
+    Timeout { timeout: std::time::Duration },
+
+    #[snafu(display("failed to connect"))]
+    Connect { source: io::Error },
+}
+
+/// A single (address, port) pair published by a [`listener::v1alpha1::Listener`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeTarget {
+    pub address: String,
+    pub port: u16,
+}
+
+impl ProbeTarget {
+    /// The `host:port` string identifying this target.
+    pub fn endpoint(&self) -> String {
+        match self.address.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{}]:{}", self.address, self.port),
+            _ => format!("{}:{}", self.address, self.port),
+        }
+    }
+}
+
+/// Lists all (address, port) pairs published in `status`.
+pub fn probe_targets(status: &listener::v1alpha1::ListenerStatus) -> Vec<ProbeTarget> {
+    status
+        .ingress_addresses
+        .iter()
+        .flatten()
+        .flat_map(|ingress| {
+            ingress.ports.values().filter_map(|port| {
+                Some(ProbeTarget {
+                    address: ingress.address.clone(),
+                    port: u16::try_from(*port).ok()?,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Checks whether a TCP connection to `target` can be established within `timeout`.
+pub async fn probe(target: &ProbeTarget, timeout: std::time::Duration) -> Result<(), ProbeError> {
+    use probe_error::*;
+    tokio::time::timeout(
+        timeout,
+        TcpStream::connect((target.address.as_str(), target.port)),
+    )
+    .await
+    .map_err(|_| ProbeError::Timeout { timeout })?
+    .context(ConnectSnafu)?;
+    Ok(())
+}
+
+/// The result of probing a single endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointReachability {
+    pub reachable: bool,
+    pub error: Option<String>,
+}
+
+/// The reachability of each of a Listener's endpoints, keyed by [`ProbeTarget::endpoint`].
+pub type ReachabilityReport = BTreeMap<String, EndpointReachability>;
+
+/// The reachability of an endpoint, as recorded in [`LISTENER_ANNOTATION_REACHABILITY`].
+///
+/// This deliberately has no probe time (see [`SERVICE_ANNOTATION_LAST_PROBE_TIME`] instead), so that probing an
+/// endpoint again without any change doesn't write the annotation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointStatus {
+    pub reachable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When `reachable` last changed.
+    pub last_transition_time: Time,
+}
+
+/// The [`EndpointStatus`] of each of a Listener's endpoints, keyed by [`ProbeTarget::endpoint`].
+pub type EndpointStatuses = BTreeMap<String, EndpointStatus>;
+
+/// Reads the [`EndpointStatuses`] that were last recorded on `listener`.
+///
+/// Missing or invalid annotations are treated as if no endpoint had been probed yet.
+pub fn recorded_endpoint_statuses(listener: &listener::v1alpha1::Listener) -> EndpointStatuses {
+    listener
+        .annotations()
+        .get(LISTENER_ANNOTATION_REACHABILITY)
+        .and_then(|statuses| serde_json::from_str(statuses).ok())
+        .unwrap_or_default()
+}
+
+/// Builds the [`EndpointStatuses`] for `report`, probed at `now`.
+///
+/// The transition time of each `previous` status is kept, unless the endpoint's reachability has changed.
+pub fn endpoint_statuses(
+    report: &ReachabilityReport,
+    previous: &EndpointStatuses,
+    now: Time,
+) -> EndpointStatuses {
+    report
+        .iter()
+        .map(|(endpoint, reachability)| {
+            let last_transition_time = previous
+                .get(endpoint)
+                .filter(|previous| previous.reachable == reachability.reachable)
+                .map_or_else(
+                    || now.clone(),
+                    |previous| previous.last_transition_time.clone(),
+                );
+            (
+                endpoint.clone(),
+                EndpointStatus {
+                    reachable: reachability.reachable,
+                    error: reachability.error.clone(),
+                    last_transition_time,
+                },
+            )
+        })
+        .collect()
+}
+
+/// A change in an endpoint's reachability compared to the previous probe.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReachabilityChange<'a> {
+    Unreachable { endpoint: &'a str, error: &'a str },
+    Recovered { endpoint: &'a str },
+}
+
+/// What the prober last observed and recorded for a Listener.
+#[derive(Debug, Default)]
+struct ListenerState {
+    /// The results of the previous probe, used to detect transitions.
+    report: ReachabilityReport,
+    /// The [`SERVICE_CONDITION_DEGRADED`] condition that was last written to the Listener's Service.
+    condition: Option<Condition>,
+}
+
+/// Builds the [`SERVICE_CONDITION_DEGRADED`] condition for `report`.
+///
+/// The transition time of the `previous` condition is kept, unless the condition's status has changed.
+pub fn degraded_condition(
+    report: &ReachabilityReport,
+    previous: Option<&Condition>,
+    now: Time,
+) -> Condition {
+    let unreachable = report
+        .iter()
+        .filter(|(_, reachability)| !reachability.reachable)
+        .map(|(endpoint, _)| endpoint.as_str())
+        .collect::<Vec<_>>();
+    let (status, reason, message) = if report.is_empty() {
+        (
+            "False",
+            "NoEndpoints",
+            "the Listener has no addresses to probe".to_string(),
+        )
+    } else if unreachable.is_empty() {
+        (
+            "False",
+            "EndpointsReachable",
+            format!("all {total} endpoints are reachable", total = report.len()),
+        )
+    } else {
+        (
+            "True",
+            "EndpointsUnreachable",
+            format!(
+                "{count} of {total} endpoints are unreachable: {endpoints}",
+                count = unreachable.len(),
+                total = report.len(),
+                endpoints = unreachable.join(", "),
+            ),
+        )
+    };
+    let last_transition_time = previous
+        .filter(|previous| previous.status == status)
+        .map_or(now, |previous| previous.last_transition_time.clone());
+    Condition {
+        type_: SERVICE_CONDITION_DEGRADED.to_string(),
+        status: status.to_string(),
+        reason: reason.to_string(),
+        message,
+        last_transition_time,
+        observed_generation: None,
+    }
+}
+
+/// Whether `current` needs to be written, ignoring the transition time (which only changes along with the status).
+pub fn condition_changed(previous: Option<&Condition>, current: &Condition) -> bool {
+    previous.is_none_or(|previous| {
+        (&previous.status, &previous.reason, &previous.message)
+            != (&current.status, &current.reason, &current.message)
+    })
+}
+
+/// Compares two [`ReachabilityReport`]s.
+///
+/// Endpoints that were not probed before are only reported if they are unreachable.
+pub fn reachability_changes<'a>(
+    previous: &ReachabilityReport,
+    current: &'a ReachabilityReport,
+) -> Vec<ReachabilityChange<'a>> {
+    current
+        .iter()
+        .filter_map(|(endpoint, reachability)| {
+            let was_reachable = previous.get(endpoint).map(|prev| prev.reachable);
+            match (was_reachable, reachability.reachable) {
+                (Some(true) | None, false) => Some(ReachabilityChange::Unreachable {
+                    endpoint,
+                    error: reachability.error.as_deref().unwrap_or_default(),
+                }),
+                (Some(false), true) => Some(ReachabilityChange::Recovered { endpoint }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Periodically probes all Listeners in the cluster, every `interval`.
+pub async fn run<F>(client: Client, interval: Duration, timeout: Duration, shutdown_signal: F)
+where
+    F: Future<Output = ()>,
+{
+    let (listeners, listener_writer) = reflector::store();
+    let listener_watcher = reflector::reflector(
+        listener_writer,
+        watcher(
+            client.get_all_api::<DeserializeGuard<listener::v1alpha1::Listener>>(),
+            watcher::Config::default(),
+        ),
+    )
+    .default_backoff()
+    .touched_objects()
+    .for_each(|res| {
+        if let Err(err) = res {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "failed to watch Listeners"
+            );
+        }
+        ready(())
+    });
+    let event_recorder = Recorder::new(
+        client.as_kube_client(),
+        Reporter {
+            controller: PROBER_NAME.to_string(),
+            instance: None,
+        },
+    );
+
+    // Only kept in memory, so after a restart the first probe is compared against an empty state (which only
+    // reports unreachable endpoints, and rewrites the condition once)
+    let states = Mutex::new(HashMap::<
+        ObjectRef<DeserializeGuard<listener::v1alpha1::Listener>>,
+        ListenerState,
+    >::new());
+    let prober = async {
+        if listeners.wait_until_ready().await.is_err() {
+            return;
+        }
+        let mut ticker = tokio::time::interval(*interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let current_listeners = listeners.state();
+            // Forget about Listeners that have been deleted
+            states
+                .lock()
+                .expect("reachability state lock was poisoned")
+                .retain(|listener_ref, _| {
+                    current_listeners
+                        .iter()
+                        .any(|listener| ObjectRef::from_obj(&**listener) == *listener_ref)
+                });
+            futures::stream::iter(current_listeners)
+                .for_each_concurrent(LISTENER_CONCURRENCY, |listener| {
+                    probe_listener(&client, &event_recorder, &states, listener, *timeout)
+                })
+                .await;
+        }
+    };
+
+    tokio::select! {
+        () = listener_watcher => {},
+        () = prober => {},
+        () = shutdown_signal => {},
+    }
+}
+
+/// Probes all endpoints of `listener`, and records the results.
+async fn probe_listener(
+    client: &Client,
+    event_recorder: &Recorder,
+    states: &Mutex<
+        HashMap<ObjectRef<DeserializeGuard<listener::v1alpha1::Listener>>, ListenerState>,
+    >,
+    listener_guard: Arc<DeserializeGuard<listener::v1alpha1::Listener>>,
+    timeout: std::time::Duration,
+) {
+    let Ok(listener) = &listener_guard.0 else {
+        return;
+    };
+    let Some(status) = &listener.status else {
+        return;
+    };
+    let report = futures::stream::iter(probe_targets(status))
+        .map(|target| async move {
+            let result = probe(&target, timeout).await;
+            (
+                target.endpoint(),
+                EndpointReachability {
+                    reachable: result.is_ok(),
+                    error: result.err().map(|err| error_full_message(&err)),
+                },
+            )
+        })
+        .buffer_unordered(ENDPOINT_CONCURRENCY)
+        .collect::<ReachabilityReport>()
+        .await;
+    let now = Time(Timestamp::now());
+
+    let listener_ref = ObjectRef::from_obj(&*listener_guard);
+    let (previous_report, previous_condition) = {
+        let states = states.lock().expect("reachability state lock was poisoned");
+        let state = states.get(&listener_ref);
+        (
+            state.map(|state| state.report.clone()).unwrap_or_default(),
+            state.and_then(|state| state.condition.clone()),
+        )
+    };
+    for change in reachability_changes(&previous_report, &report) {
+        let event = match change {
+            ReachabilityChange::Unreachable { endpoint, error } => Event {
+                type_: EventType::Warning,
+                reason: "Unreachable".to_string(),
+                note: Some(format!("{endpoint} is unreachable: {error}")),
+                action: "Probe".to_string(),
+                secondary: None,
+            },
+            ReachabilityChange::Recovered { endpoint } => Event {
+                type_: EventType::Normal,
+                reason: "Reachable".to_string(),
+                note: Some(format!("{endpoint} is reachable again")),
+                action: "Probe".to_string(),
+                secondary: None,
+            },
+        };
+        if let Err(err) = event_recorder
+            .publish(&event, &listener.object_ref(&()))
+            .await
+        {
+            tracing::warn!(
+                listener = %listener.name_any(),
+                error = &err as &dyn std::error::Error,
+                "failed to publish reachability event"
+            );
+        }
+    }
+
+    tracing::debug!(
+        listener = %listener.name_any(),
+        endpoints = report.len(),
+        unreachable = report.values().filter(|reachability| !reachability.reachable).count(),
+        "probed Listener endpoints"
+    );
+
+    // Every write to the Listener triggers a reconcile (and updates all volumes that use it), so the annotation is
+    // only written when an endpoint's reachability or error has changed
+    let recorded_statuses = recorded_endpoint_statuses(listener);
+    let statuses = endpoint_statuses(&report, &recorded_statuses, now.clone());
+    if statuses != recorded_statuses {
+        let patch = serde_json::json!({
+            "metadata": {
+                "annotations": {
+                    LISTENER_ANNOTATION_REACHABILITY: serde_json::to_string(&statuses)
+                        .expect("EndpointStatuses must be serializable"),
+                },
+            },
+        });
+        if let Err(err) = client.merge_patch(listener, &patch).await {
+            tracing::warn!(
+                listener = %listener.name_any(),
+                error = &err as &dyn std::error::Error,
+                "failed to record endpoint reachability"
+            );
+        }
+    }
+
+    let condition = degraded_condition(&report, previous_condition.as_ref(), now.clone());
+    let mut written_condition = previous_condition.clone();
+    let service = status.service_name.as_ref().map(|service_name| Service {
+        metadata: ObjectMeta {
+            name: Some(service_name.clone()),
+            namespace: listener.namespace(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    if let Some(service) = &service {
+        let patch = serde_json::json!({
+            "metadata": {
+                "annotations": {
+                    SERVICE_ANNOTATION_LAST_PROBE_TIME: now,
+                },
+            },
+        });
+        if let Err(err) = client.merge_patch(service, &patch).await {
+            tracing::warn!(
+                listener = %listener.name_any(),
+                error = &err as &dyn std::error::Error,
+                "failed to record probe time"
+            );
+        }
+    }
+    if let Some(service) = &service
+        && condition_changed(previous_condition.as_ref(), &condition)
+    {
+        let service_status = ServiceStatus {
+            conditions: Some(vec![condition.clone()]),
+            ..Default::default()
+        };
+        match client
+            .apply_patch_status(PROBER_NAME, service, &service_status)
+            .await
+        {
+            Ok(_) => written_condition = Some(condition),
+            // Retried on the next probe, since the condition is only remembered once it has been written
+            Err(err) => tracing::warn!(
+                listener = %listener.name_any(),
+                error = &err as &dyn std::error::Error,
+                "failed to record reachability"
+            ),
+        }
+    }
+    states
+        .lock()
+        .expect("reachability state lock was poisoned")
+        .insert(
+            listener_ref,
+            ListenerState {
+                report,
+                condition: written_condition,
+            },
+        );
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn reachability(reachable: bool) -> EndpointReachability {
+        EndpointReachability {
+            reachable,
+            error: (!reachable).then(|| "failed to connect".to_string()),
+        }
+    }
+
+    #[test]
+    fn probe_targets_cover_all_ports() {
+        let status = listener::v1alpha1::ListenerStatus {
+            service_name: None,
+            ingress_addresses: Some(vec![
+                listener::v1alpha1::ListenerIngress {
+                    address: "10.0.0.1".to_string(),
+                    address_type: listener::v1alpha1::AddressType::Ip,
+                    ports: [("http".to_string(), 80), ("https".to_string(), 443)].into(),
+                },
+                listener::v1alpha1::ListenerIngress {
+                    address: "fd00::1".to_string(),
+                    address_type: listener::v1alpha1::AddressType::Ip,
+                    ports: [("http".to_string(), 80)].into(),
+                },
+            ]),
+            node_ports: None,
+        };
+        let endpoints = probe_targets(&status)
+            .iter()
+            .map(ProbeTarget::endpoint)
+            .collect::<Vec<_>>();
+        assert_eq!(endpoints, ["10.0.0.1:80", "10.0.0.1:443", "[fd00::1]:80"]);
+    }
+
+    #[tokio::test]
+    async fn probe_local_listener() {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = ProbeTarget {
+            address: "127.0.0.1".to_string(),
+            port: tcp_listener.local_addr().unwrap().port(),
+        };
+        probe(&target, std::time::Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        drop(tcp_listener);
+        assert!(matches!(
+            probe(&target, std::time::Duration::from_secs(5)).await,
+            Err(ProbeError::Connect { .. })
+        ));
+    }
+
+    #[test]
+    fn changes_are_reported_on_transition() {
+        let previous = ReachabilityReport::from([
+            ("10.0.0.1:80".to_string(), reachability(true)),
+            ("10.0.0.2:80".to_string(), reachability(false)),
+            ("10.0.0.3:80".to_string(), reachability(true)),
+        ]);
+        let current = ReachabilityReport::from([
+            ("10.0.0.1:80".to_string(), reachability(false)),
+            ("10.0.0.2:80".to_string(), reachability(true)),
+            ("10.0.0.3:80".to_string(), reachability(true)),
+            ("10.0.0.4:80".to_string(), reachability(false)),
+            ("10.0.0.5:80".to_string(), reachability(true)),
+        ]);
+        assert_eq!(
+            reachability_changes(&previous, &current),
+            [
+                ReachabilityChange::Unreachable {
+                    endpoint: "10.0.0.1:80",
+                    error: "failed to connect",
+                },
+                ReachabilityChange::Recovered {
+                    endpoint: "10.0.0.2:80",
+                },
+                ReachabilityChange::Unreachable {
+                    endpoint: "10.0.0.4:80",
+                    error: "failed to connect",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn endpoint_statuses_keep_transition_time() {
+        let time = |secs| Time(Timestamp::from_second(secs).unwrap());
+        let report = ReachabilityReport::from([
+            ("10.0.0.1:80".to_string(), reachability(true)),
+            ("10.0.0.2:80".to_string(), reachability(true)),
+        ]);
+        let statuses = endpoint_statuses(&report, &EndpointStatuses::new(), time(1));
+        assert_eq!(
+            statuses["10.0.0.1:80"],
+            EndpointStatus {
+                reachable: true,
+                error: None,
+                last_transition_time: time(1),
+            }
+        );
+
+        // Probing again without any change must not cause a write
+        assert_eq!(endpoint_statuses(&report, &statuses, time(2)), statuses);
+
+        let report = ReachabilityReport::from([
+            ("10.0.0.1:80".to_string(), reachability(true)),
+            ("10.0.0.2:80".to_string(), reachability(false)),
+        ]);
+        let statuses = endpoint_statuses(&report, &statuses, time(2));
+        assert_eq!(statuses["10.0.0.1:80"].last_transition_time, time(1));
+        assert_eq!(
+            statuses["10.0.0.2:80"],
+            EndpointStatus {
+                reachable: false,
+                error: Some("failed to connect".to_string()),
+                last_transition_time: time(2),
+            }
+        );
+
+        // Recorded statuses must survive a round-trip through the annotation
+        let annotation = serde_json::to_string(&statuses).unwrap();
+        assert_eq!(
+            serde_json::from_str::<EndpointStatuses>(&annotation).unwrap(),
+            statuses
+        );
+    }
+
+    #[test]
+    fn degraded_condition_only_changes_on_transition() {
+        let time = |secs| Time(Timestamp::from_second(secs).unwrap());
+        let healthy = ReachabilityReport::from([
+            ("10.0.0.1:80".to_string(), reachability(true)),
+            ("10.0.0.2:80".to_string(), reachability(true)),
+        ]);
+        let condition = degraded_condition(&healthy, None, time(1));
+        assert_eq!(condition.status, "False");
+        assert_eq!(condition.message, "all 2 endpoints are reachable");
+        assert_eq!(condition.last_transition_time, time(1));
+        assert!(condition_changed(None, &condition));
+
+        // Probing again must not cause a write, even though the probe time has moved on
+        let reprobed = degraded_condition(&healthy, Some(&condition), time(2));
+        assert_eq!(reprobed, condition);
+        assert!(!condition_changed(Some(&condition), &reprobed));
+
+        let degraded = ReachabilityReport::from([
+            ("10.0.0.1:80".to_string(), reachability(true)),
+            ("10.0.0.2:80".to_string(), reachability(false)),
+        ]);
+        let degraded_condition = degraded_condition(&degraded, Some(&condition), time(3));
+        assert_eq!(degraded_condition.status, "True");
+        assert_eq!(degraded_condition.reason, "EndpointsUnreachable");
+        assert_eq!(
+            degraded_condition.message,
+            "1 of 2 endpoints are unreachable: 10.0.0.2:80"
+        );
+        assert_eq!(degraded_condition.last_transition_time, time(3));
+        assert!(condition_changed(Some(&condition), &degraded_condition));
+    }
+}
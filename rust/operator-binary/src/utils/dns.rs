@@ -0,0 +1,809 @@
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    fmt::Write as _,
+    future::Future,
+    hash::Hash,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, Query, ResponseCode},
+    rr::{Name, RData, Record, RecordType},
+};
+use socket2::SockAddr;
+use stackable_operator::{crd::listener, shared::time::Duration};
+use tokio::{net::UdpSocket, sync::Semaphore};
+
+use super::address::AddressCandidates;
+
+/// The maximum number of DNS lookups that may be in flight at the same time.
+///
+/// The system resolver blocks a thread for each lookup, so this also bounds the number of blocking threads used.
+pub const MAX_CONCURRENT_LOOKUPS: usize = 16;
+
+/// How long a single DNS lookup may take before the address is published as-is.
+const LOOKUP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The maximum size of a DNS message over UDP, unless negotiated otherwise using EDNS (which we don't use).
+pub const MAX_UDP_MESSAGE_LEN: usize = 512;
+
+/// Performs DNS lookups on behalf of the [`AddressResolver`].
+///
+/// This is a trait so that the system resolver can be bypassed, such as by querying a specific nameserver
+/// using [`NameserverResolver`].
+pub trait Resolver: Send + Sync {
+    /// Looks up the hostname that `ip` points back to (its PTR record).
+    ///
+    /// Returns [`None`] if the address has no associated name.
+    fn reverse_lookup(&self, ip: IpAddr) -> BoxFuture<'_, std::io::Result<Option<String>>>;
+
+    /// Looks up all IP addresses that `hostname` resolves to.
+    ///
+    /// Returns an empty list only if the name definitively has no addresses (since such answers are cached), transient
+    /// failures (such as timeouts or SERVFAIL) must be reported as errors.
+    fn forward_lookup<'a>(
+        &'a self,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, std::io::Result<Vec<IpAddr>>>;
+}
+
+/// A [`Resolver`] that uses the operator's system resolver configuration (`/etc/resolv.conf` and friends).
+pub struct SystemResolver {
+    blocking_lookups: Arc<Semaphore>,
+}
+
+impl Default for SystemResolver {
+    fn default() -> Self {
+        Self {
+            blocking_lookups: Arc::new(Semaphore::new(MAX_CONCURRENT_LOOKUPS)),
+        }
+    }
+}
+
+impl Resolver for SystemResolver {
+    fn reverse_lookup(&self, ip: IpAddr) -> BoxFuture<'_, std::io::Result<Option<String>>> {
+        async move {
+            let permit = self
+                .blocking_lookups
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(io::Error::other)?;
+            // getnameinfo(3) is blocking, so run it on the blocking thread pool
+            tokio::task::spawn_blocking(move || {
+                // Keep the permit until the lookup has actually finished, even if the caller has timed out already
+                let _permit = permit;
+                getnameinfo(ip)
+            })
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)))
+        }
+        .boxed()
+    }
+
+    fn forward_lookup<'a>(
+        &'a self,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, std::io::Result<Vec<IpAddr>>> {
+        let hostname = hostname.to_string();
+        async move {
+            let permit = self
+                .blocking_lookups
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(io::Error::other)?;
+            // getaddrinfo(3) is blocking, so run it on the blocking thread pool
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                getaddrinfo(&hostname)
+            })
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)))
+        }
+        .boxed()
+    }
+}
+
+/// A [`Resolver`] that sends queries to a specific nameserver over UDP, rather than using the system resolver.
+pub struct NameserverResolver {
+    nameserver: SocketAddr,
+}
+
+impl NameserverResolver {
+    pub fn new(nameserver: SocketAddr) -> Self {
+        Self { nameserver }
+    }
+
+    /// Queries the nameserver for the `record_type` records of `name`.
+    ///
+    /// Returns [`None`] if the name does not exist.
+    async fn query(&self, name: &str, record_type: RecordType) -> io::Result<Option<Vec<RData>>> {
+        let mut name = Name::from_ascii(name).map_err(io::Error::other)?;
+        // Names are always looked up as-is, without trying any search domains
+        name.set_fqdn(true);
+        let local_addr = match self.nameserver {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(self.nameserver).await?;
+        let mut query = Message::new();
+        query
+            // Use a random ID for each query, to make it harder to spoof responses (RFC 5452)
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(name, record_type));
+        socket
+            .send(&query.to_vec().map_err(io::Error::other)?)
+            .await?;
+
+        let mut buf = [0; MAX_UDP_MESSAGE_LEN];
+        let mut response = loop {
+            let len = socket.recv(&mut buf).await?;
+            let response = Message::from_vec(&buf[..len]).map_err(io::Error::other)?;
+            // Ignore stray responses to other queries
+            if response.id() == query.id()
+                && response.message_type() == MessageType::Response
+                && response.queries() == query.queries()
+            {
+                break response;
+            }
+        };
+        match response.response_code() {
+            ResponseCode::NoError => {}
+            ResponseCode::NXDomain => return Ok(None),
+            code => {
+                return Err(io::Error::other(format!(
+                    "nameserver {nameserver} responded with {code}",
+                    nameserver = self.nameserver,
+                )));
+            }
+        }
+        if response.truncated() {
+            return Err(io::Error::other(format!(
+                "response from nameserver {nameserver} was truncated",
+                nameserver = self.nameserver,
+            )));
+        }
+        Ok(Some(
+            response
+                .take_answers()
+                .into_iter()
+                .map(Record::into_data)
+                .collect(),
+        ))
+    }
+}
+
+impl Resolver for NameserverResolver {
+    fn reverse_lookup(&self, ip: IpAddr) -> BoxFuture<'_, std::io::Result<Option<String>>> {
+        async move {
+            let answers = self
+                .query(&reverse_lookup_name(ip), RecordType::PTR)
+                .await?;
+            Ok(answers
+                .into_iter()
+                .flatten()
+                .find_map(|record| match record {
+                    // Publish the hostname without the trailing dot, like the ones reported by Kubernetes
+                    RData::PTR(hostname) => {
+                        Some(hostname.to_ascii().trim_end_matches('.').to_string())
+                    }
+                    _ => None,
+                }))
+        }
+        .boxed()
+    }
+
+    fn forward_lookup<'a>(
+        &'a self,
+        hostname: &'a str,
+    ) -> BoxFuture<'a, std::io::Result<Vec<IpAddr>>> {
+        async move {
+            let (v4, v6) = futures::try_join!(
+                self.query(hostname, RecordType::A),
+                self.query(hostname, RecordType::AAAA)
+            )?;
+            Ok(v4
+                .into_iter()
+                .chain(v6)
+                .flatten()
+                .filter_map(|record| match record {
+                    RData::A(ip) => Some(IpAddr::V4(ip.0)),
+                    RData::AAAA(ip) => Some(IpAddr::V6(ip.0)),
+                    _ => None,
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+/// The name that the PTR record of `ip` is published under (in `in-addr.arpa` or `ip6.arpa`).
+fn reverse_lookup_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for byte in ip.octets().iter().rev() {
+                write!(name, "{:x}.{:x}.", byte & 0xF, byte >> 4)
+                    .expect("writing to a String cannot fail");
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Looks up the name of `ip` using the system resolver, without falling back to the numeric address.
+fn getnameinfo(ip: IpAddr) -> std::io::Result<Option<String>> {
+    let addr = SockAddr::from(SocketAddr::new(ip, 0));
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    let ret = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr().cast::<libc::sockaddr>(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    match ret {
+        0 => {
+            // getnameinfo guarantees that the buffer is NUL-terminated on success
+            let host = unsafe { CStr::from_ptr(host.as_ptr()) };
+            Ok(Some(host.to_string_lossy().into_owned()))
+        }
+        libc::EAI_NONAME => Ok(None),
+        libc::EAI_SYSTEM => Err(std::io::Error::last_os_error()),
+        err => {
+            let msg = unsafe { CStr::from_ptr(libc::gai_strerror(err)) };
+            Err(std::io::Error::other(msg.to_string_lossy().into_owned()))
+        }
+    }
+}
+
+/// Looks up the addresses of `hostname` using the system resolver.
+///
+/// Unlike [`std::net::ToSocketAddrs`], this tells names that don't exist apart from transient failures (such as
+/// `EAI_AGAIN`), which are returned as errors.
+fn getaddrinfo(hostname: &str) -> std::io::Result<Vec<IpAddr>> {
+    let hostname = CString::new(hostname)?;
+    // Only ask for one address per IP, rather than one for each socket type
+    let hints = libc::addrinfo {
+        ai_socktype: libc::SOCK_STREAM,
+        // All other fields must be zero (or null)
+        ..unsafe { std::mem::zeroed() }
+    };
+    let mut addrinfo = std::ptr::null_mut::<libc::addrinfo>();
+    let ret =
+        unsafe { libc::getaddrinfo(hostname.as_ptr(), std::ptr::null(), &hints, &mut addrinfo) };
+    match ret {
+        0 => {}
+        libc::EAI_NONAME | libc::EAI_NODATA => return Ok(Vec::new()),
+        libc::EAI_SYSTEM => return Err(std::io::Error::last_os_error()),
+        err => {
+            let msg = unsafe { CStr::from_ptr(libc::gai_strerror(err)) };
+            return Err(std::io::Error::other(msg.to_string_lossy().into_owned()));
+        }
+    }
+
+    let mut ips = Vec::new();
+    let mut next = addrinfo;
+    while !next.is_null() {
+        // getaddrinfo returns a valid linked list on success, which stays valid until it is freed below
+        let info = unsafe { &*next };
+        if !info.ai_addr.is_null() {
+            match info.ai_family {
+                libc::AF_INET => {
+                    let addr = unsafe { &*info.ai_addr.cast::<libc::sockaddr_in>() };
+                    ips.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                        addr.sin_addr.s_addr,
+                    ))));
+                }
+                libc::AF_INET6 => {
+                    let addr = unsafe { &*info.ai_addr.cast::<libc::sockaddr_in6>() };
+                    ips.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+                }
+                _ => {}
+            }
+        }
+        next = info.ai_next;
+    }
+    unsafe { libc::freeaddrinfo(addrinfo) };
+    Ok(ips)
+}
+
+/// Controls whether (and how) DNS is used to find hostnames for published addresses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum HostnameResolution {
+    /// Only publish hostnames that are reported by the Node or LoadBalancer.
+    #[default]
+    None,
+
+    /// Look up hostnames for IP-only addresses using reverse DNS.
+    Reverse,
+
+    /// Like `reverse`, but only publish hostnames that resolve back to the address they were found for.
+    /// Hostnames reported by the Node or LoadBalancer must resolve at all.
+    Verified,
+}
+
+/// Picks addresses from [`AddressCandidates`], optionally consulting DNS to find or verify hostnames.
+///
+/// DNS responses are cached for the configured TTL, since the same Node addresses are typically
+/// picked by many Listeners.
+#[derive(Clone)]
+pub struct AddressResolver {
+    mode: HostnameResolution,
+    resolver: Arc<dyn Resolver>,
+    reverse_cache: Arc<Cache<IpAddr, Option<String>>>,
+    forward_cache: Arc<Cache<String, Vec<IpAddr>>>,
+}
+
+impl AddressResolver {
+    pub fn new(mode: HostnameResolution, resolver: Arc<dyn Resolver>, cache_ttl: Duration) -> Self {
+        Self {
+            mode,
+            resolver,
+            reverse_cache: Arc::new(Cache::new(cache_ttl)),
+            forward_cache: Arc::new(Cache::new(cache_ttl)),
+        }
+    }
+
+    /// Tries to pick the preferred [`listener::v1alpha1::AddressType`], falling back if it is not available.
+    ///
+    /// Behaves like [`AddressCandidates::pick`], except that hostnames may be looked up or verified
+    /// according to the configured [`HostnameResolution`].
+    pub async fn pick(
+        &self,
+        candidates: AddressCandidates<'_>,
+        preferred_address_type: listener::v1alpha1::AddressType,
+    ) -> Option<(String, listener::v1alpha1::AddressType)> {
+        let unresolved = || {
+            candidates
+                .pick(preferred_address_type)
+                .map(|(address, address_type)| (address.to_string(), address_type))
+        };
+        if self.mode == HostnameResolution::None
+            || preferred_address_type != listener::v1alpha1::AddressType::Hostname
+        {
+            return unresolved();
+        }
+        let verify = self.mode == HostnameResolution::Verified;
+
+        if let Some(hostname) = candidates.hostname {
+            if !verify || self.verify_hostname(hostname, None).await {
+                return Some((
+                    hostname.to_string(),
+                    listener::v1alpha1::AddressType::Hostname,
+                ));
+            }
+            tracing::debug!(hostname, "hostname does not resolve, falling back to IP");
+            return candidates
+                .ip
+                .map(|ip| (ip.to_string(), listener::v1alpha1::AddressType::Ip))
+                .or_else(unresolved);
+        }
+
+        let ip = candidates.ip?;
+        if let Ok(ip_addr) = ip.parse::<IpAddr>()
+            && let Some(hostname) = self.reverse_lookup(ip_addr).await
+        {
+            if !verify || self.verify_hostname(&hostname, Some(ip_addr)).await {
+                return Some((hostname, listener::v1alpha1::AddressType::Hostname));
+            }
+            tracing::debug!(
+                ip,
+                hostname,
+                "reverse DNS name does not resolve back to IP, ignoring it"
+            );
+        }
+        unresolved()
+    }
+
+    async fn reverse_lookup(&self, ip: IpAddr) -> Option<String> {
+        if let Some(hostname) = self.reverse_cache.get(&ip) {
+            return hostname;
+        }
+        match with_timeout(self.resolver.reverse_lookup(ip)).await {
+            Ok(hostname) => {
+                self.reverse_cache.insert(ip, hostname.clone());
+                hostname
+            }
+            Err(err) => {
+                tracing::warn!(%ip, error = &err as &dyn std::error::Error, "reverse DNS lookup failed");
+                None
+            }
+        }
+    }
+
+    /// Checks that `hostname` resolves, and (if specified) that it resolves to `expected_ip`.
+    async fn verify_hostname(&self, hostname: &str, expected_ip: Option<IpAddr>) -> bool {
+        let ips = match self.forward_cache.get(hostname) {
+            Some(ips) => ips,
+            // Failed lookups are not cached, so that a transient failure doesn't downgrade the address for the whole TTL
+            None => match with_timeout(self.resolver.forward_lookup(hostname)).await {
+                Ok(ips) => {
+                    self.forward_cache.insert(hostname.to_string(), ips.clone());
+                    ips
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        hostname,
+                        error = &err as &dyn std::error::Error,
+                        "forward DNS lookup failed"
+                    );
+                    return false;
+                }
+            },
+        };
+        match expected_ip {
+            Some(expected_ip) => ips.contains(&expected_ip),
+            None => !ips.is_empty(),
+        }
+    }
+}
+
+/// Fails `lookup` if it takes longer than [`LOOKUP_TIMEOUT`].
+async fn with_timeout<T>(lookup: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(LOOKUP_TIMEOUT, lookup)
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "DNS lookup timed out",
+            ))
+        })
+}
+
+/// A simple map whose entries expire after a fixed TTL.
+struct Cache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Hash + Eq, V: Clone> Cache<K, V> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let entries = self.entries.lock().expect("DNS cache lock was poisoned");
+        entries
+            .get(key)
+            .filter(|(inserted_at, _)| inserted_at.elapsed() < *self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().expect("DNS cache lock was poisoned");
+        // Prune expired entries, so that the cache doesn't grow indefinitely as addresses change
+        entries.retain(|_, (inserted_at, _)| inserted_at.elapsed() < *self.ttl);
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, SocketAddr},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use hickory_proto::{
+        op::{Message, MessageType, OpCode, ResponseCode},
+        rr::{
+            RData, Record, RecordType,
+            rdata::{A, AAAA, PTR},
+        },
+    };
+    use stackable_operator::{crd::listener, shared::time::Duration};
+    use tokio::net::UdpSocket;
+
+    use super::{
+        AddressResolver, HostnameResolution, MAX_UDP_MESSAGE_LEN, NameserverResolver, getaddrinfo,
+        reverse_lookup_name,
+    };
+    use crate::utils::address::AddressCandidates;
+
+    /// A DNS server on localhost that answers queries from a fixed set of records.
+    struct StubDnsServer {
+        address: SocketAddr,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl StubDnsServer {
+        /// Starts a server that publishes PTR and A/AAAA records for each `(ip, hostname)` pair.
+        async fn with_records<'a>(records: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+            let mut zone = HashMap::<(String, RecordType), Vec<RData>>::new();
+            for (ip, hostname) in records {
+                let ip = ip.parse::<IpAddr>().unwrap();
+                zone.entry((reverse_lookup_name(ip), RecordType::PTR))
+                    .or_default()
+                    .push(RData::PTR(PTR(hostname.parse().unwrap())));
+                let (record_type, record) = match ip {
+                    IpAddr::V4(ip) => (RecordType::A, RData::A(A(ip))),
+                    IpAddr::V6(ip) => (RecordType::AAAA, RData::AAAA(AAAA(ip))),
+                };
+                zone.entry((hostname.to_string(), record_type))
+                    .or_default()
+                    .push(record);
+            }
+            Self::with_zone(zone).await
+        }
+
+        async fn with_zone(zone: HashMap<(String, RecordType), Vec<RData>>) -> Self {
+            Self::with_zone_failing(zone, 0).await
+        }
+
+        /// Like [`Self::with_zone`], but answers the first `failures` queries with SERVFAIL.
+        async fn with_zone_failing(
+            zone: HashMap<(String, RecordType), Vec<RData>>,
+            failures: usize,
+        ) -> Self {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let address = socket.local_addr().unwrap();
+            let queries = Arc::<AtomicUsize>::default();
+            let server_queries = queries.clone();
+            tokio::spawn(async move {
+                let mut buf = [0; MAX_UDP_MESSAGE_LEN];
+                loop {
+                    let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                    let is_failure = server_queries.fetch_add(1, Ordering::SeqCst) < failures;
+                    let request = Message::from_vec(&buf[..len]).unwrap();
+                    let query = &request.queries()[0];
+                    let name = query.name().to_ascii().trim_end_matches('.').to_string();
+                    let (response_code, answers) =
+                        match zone.get(&(name.clone(), query.query_type())) {
+                            Some(records) => (
+                                ResponseCode::NoError,
+                                records
+                                    .iter()
+                                    .map(|data| {
+                                        Record::from_rdata(query.name().clone(), 30, data.clone())
+                                    })
+                                    .collect(),
+                            ),
+                            None if zone.keys().any(|(zone_name, _)| *zone_name == name) => {
+                                (ResponseCode::NoError, Vec::new())
+                            }
+                            None => (ResponseCode::NXDomain, Vec::new()),
+                        };
+                    let (response_code, answers) = if is_failure {
+                        (ResponseCode::ServFail, Vec::new())
+                    } else {
+                        (response_code, answers)
+                    };
+                    let mut response = Message::new();
+                    response
+                        .set_id(request.id())
+                        .set_message_type(MessageType::Response)
+                        .set_op_code(OpCode::Query)
+                        .set_response_code(response_code)
+                        .add_query(query.clone())
+                        .add_answers(answers);
+                    socket
+                        .send_to(&response.to_vec().unwrap(), peer)
+                        .await
+                        .unwrap();
+                }
+            });
+            Self { address, queries }
+        }
+
+        fn queries(&self) -> usize {
+            self.queries.load(Ordering::SeqCst)
+        }
+    }
+
+    fn address_resolver(mode: HostnameResolution, server: &StubDnsServer) -> AddressResolver {
+        AddressResolver::new(
+            mode,
+            Arc::new(NameserverResolver::new(server.address)),
+            Duration::from_secs(300),
+        )
+    }
+
+    #[tokio::test]
+    async fn disabled_resolution_does_not_look_up() {
+        let server = StubDnsServer::with_records([("1.2.3.4", "node-0.example.com")]).await;
+        let resolver = address_resolver(HostnameResolution::None, &server);
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: None,
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some(("1.2.3.4".to_string(), listener::v1alpha1::AddressType::Ip))
+        );
+        assert_eq!(server.queries(), 0);
+    }
+
+    #[tokio::test]
+    async fn reverse_resolution_finds_hostname_for_ip() {
+        let server = StubDnsServer::with_records([("1.2.3.4", "node-0.example.com")]).await;
+        let resolver = address_resolver(HostnameResolution::Reverse, &server);
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: None,
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some((
+                "node-0.example.com".to_string(),
+                listener::v1alpha1::AddressType::Hostname
+            ))
+        );
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Ip)
+                .await,
+            Some(("1.2.3.4".to_string(), listener::v1alpha1::AddressType::Ip))
+        );
+        // The second hostname pick should be served from the cache
+        resolver
+            .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+            .await;
+        assert_eq!(server.queries(), 1);
+    }
+
+    #[tokio::test]
+    async fn reverse_resolution_falls_back_to_ip() {
+        let server = StubDnsServer::with_zone(HashMap::new()).await;
+        let resolver = address_resolver(HostnameResolution::Reverse, &server);
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: None,
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some(("1.2.3.4".to_string(), listener::v1alpha1::AddressType::Ip))
+        );
+    }
+
+    #[tokio::test]
+    async fn verified_resolution_rejects_mismatched_hostname() {
+        // PTR record exists, but the A record points somewhere else
+        let server = StubDnsServer::with_zone(HashMap::from([
+            (
+                ("4.3.2.1.in-addr.arpa".to_string(), RecordType::PTR),
+                vec![RData::PTR(PTR("node-0.example.com".parse().unwrap()))],
+            ),
+            (
+                ("node-0.example.com".to_string(), RecordType::A),
+                vec![RData::A(A("5.6.7.8".parse().unwrap()))],
+            ),
+        ]))
+        .await;
+        let resolver = address_resolver(HostnameResolution::Verified, &server);
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: None,
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some(("1.2.3.4".to_string(), listener::v1alpha1::AddressType::Ip))
+        );
+    }
+
+    #[tokio::test]
+    async fn verified_resolution_rejects_unresolvable_hostname() {
+        let server = StubDnsServer::with_records([("1.2.3.4", "node-0.example.com")]).await;
+        let resolver = address_resolver(HostnameResolution::Verified, &server);
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: Some("node-0"),
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some(("1.2.3.4".to_string(), listener::v1alpha1::AddressType::Ip))
+        );
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: Some("node-0.example.com"),
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some((
+                "node-0.example.com".to_string(),
+                listener::v1alpha1::AddressType::Hostname
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn verified_resolution_does_not_cache_failures() {
+        // Both the A and AAAA queries of the first lookup fail
+        let server = StubDnsServer::with_zone_failing(
+            HashMap::from([(
+                ("node-0.example.com".to_string(), RecordType::A),
+                vec![RData::A(A("1.2.3.4".parse().unwrap()))],
+            )]),
+            2,
+        )
+        .await;
+        let resolver = address_resolver(HostnameResolution::Verified, &server);
+        let candidates = AddressCandidates {
+            ip: Some("1.2.3.4"),
+            hostname: Some("node-0.example.com"),
+        };
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some(("1.2.3.4".to_string(), listener::v1alpha1::AddressType::Ip))
+        );
+        // The failure must not be cached, so the hostname is published as soon as the nameserver recovers
+        assert_eq!(
+            resolver
+                .pick(candidates, listener::v1alpha1::AddressType::Hostname)
+                .await,
+            Some((
+                "node-0.example.com".to_string(),
+                listener::v1alpha1::AddressType::Hostname
+            ))
+        );
+    }
+
+    #[test]
+    fn system_resolver_resolves_addresses() {
+        assert_eq!(
+            getaddrinfo("127.0.0.1").unwrap(),
+            ["127.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            getaddrinfo("::1").unwrap(),
+            ["::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn reverse_lookup_names() {
+        assert_eq!(
+            reverse_lookup_name("1.2.3.4".parse().unwrap()),
+            "4.3.2.1.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_lookup_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+}
@@ -1,3 +1,4 @@
 pub mod address;
+pub mod dns;
 pub mod error;
 pub mod unix_stream;